@@ -0,0 +1,196 @@
+use std::f64;
+
+use prelude::*;
+
+/// An axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min: min, max: max }
+    }
+
+    /// A box containing nothing, which is the identity of `union`.
+    pub fn empty() -> Aabb {
+        Aabb::new(
+            Vec3::new(f64::MAX, f64::MAX, f64::MAX),
+            Vec3::new(f64::MIN, f64::MIN, f64::MIN),
+        )
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Aabb {
+        points
+            .iter()
+            .fold(Aabb::empty(), |bounds, &point| bounds.expand(point))
+    }
+
+    pub fn expand(&self, point: Vec3) -> Aabb {
+        Aabb::new(
+            Vec3::new(
+                self.min.x.min(point.x),
+                self.min.y.min(point.y),
+                self.min.z.min(point.z),
+            ),
+            Vec3::new(
+                self.max.x.max(point.x),
+                self.max.y.max(point.y),
+                self.max.z.max(point.z),
+            ),
+        )
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        self.expand(other.min).expand(other.max)
+    }
+
+    /// The overlap between two boxes, which is empty if they do not overlap.
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Vec3::new(
+                self.min.x.max(other.min.x),
+                self.min.y.max(other.min.y),
+                self.min.z.max(other.min.z),
+            ),
+            Vec3::new(
+                self.max.x.min(other.max.x),
+                self.max.y.min(other.max.y),
+                self.max.z.min(other.max.z),
+            ),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let extent = self.max - self.min;
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    /// The axis where the box is the widest, where 0, 1 and 2 is x, y and z.
+    pub fn largest_axis(&self) -> u32 {
+        let extent = self.max - self.min;
+        if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// The corners of the box, used to bound the box after it has been transformed.
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.clip(ray, t_min, t_max).is_some()
+    }
+
+    /// The part of the range from `t_min` to `t_max` where the ray is inside of the box.
+    pub fn clip(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let inverse_direction = Vec3::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+        self.clip_inverse(ray.origin, inverse_direction, t_min, t_max)
+    }
+
+    /// The slab test, taking the inverse of the ray direction so that it can be computed once
+    /// for all the boxes tested against a ray.
+    pub fn hit_inverse(
+        &self,
+        origin: Vec3,
+        inverse_direction: Vec3,
+        t_min: f64,
+        t_max: f64,
+    ) -> bool {
+        self.clip_inverse(origin, inverse_direction, t_min, t_max)
+            .is_some()
+    }
+
+    fn clip_inverse(
+        &self,
+        origin: Vec3,
+        inverse_direction: Vec3,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<(f64, f64)> {
+        let (mut t_min, mut t_max) = (t_min, t_max);
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - origin[axis]) * inverse_direction[axis];
+            let t1 = (self.max[axis] - origin[axis]) * inverse_direction[axis];
+            let (t0, t1) = if inverse_direction[axis] < 0.0 {
+                (t1, t0)
+            } else {
+                (t0, t1)
+            };
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn ray_should_hit_box_within_limits() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_that!(unit_box().hit(&ray, 0.0, 10.0), is(true));
+        assert_that!(unit_box().hit(&ray, 0.0, 3.0), is(false));
+    }
+
+    #[test]
+    fn ray_should_miss_box_beside_it() {
+        let ray = Ray::new(Vec3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_that!(unit_box().hit(&ray, 0.0, 10.0), is(false));
+    }
+
+    #[test]
+    fn union_should_contain_both_boxes() {
+        let other = Aabb::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(3.0, 2.0, 1.0));
+
+        let union = unit_box().union(&other);
+
+        assert_that!(union.min, is(equal_to(Vec3::new(-1.0, -1.0, -1.0))));
+        assert_that!(union.max, is(equal_to(Vec3::new(3.0, 2.0, 1.0))));
+        assert_that!(union.surface_area(), is(equal_to(2.0 * (12.0 + 6.0 + 8.0))));
+    }
+}
@@ -1,6 +1,9 @@
+use std::rc::Rc;
+
 use camera::Camera;
 use color::Color;
-use scene::{Scene, Sphere};
+use scene::{Intersectable, Scene, Sphere};
+use transform::Moving;
 use vec::Vec3;
 
 #[derive(Clone, Debug)]
@@ -37,6 +40,10 @@ impl Keyframes {
         Keyframes { frames: frames }
     }
 
+    fn last_t(&self) -> usize {
+        self.frames[self.frames.len() - 1].t
+    }
+
     fn get_or_last(&self, i: usize) -> Keyframe {
         let last = self.frames.len() - 1;
 
@@ -110,11 +117,46 @@ impl Keyframes {
 
 pub fn animate(scene: &Scene, camera: &Camera, frames: &Keyframes, t: usize) -> (Scene, Camera) {
     let mut shapes = Vec::new();
-    for s in 0..scene.shapes.len() {
-        shapes.push(scene.shapes[s].move_to(frames.pos(t, s)));
+    for s in 0..scene.shapes().len() {
+        shapes.push(scene.shapes()[s].move_to(frames.pos(t, s)));
     }
     (
         Scene::new(shapes),
         camera.look_at(frames.look(t)).move_to(frames.cam(t)),
     )
 }
+
+/// Like `animate`, but with the shutter of the camera open for the given fraction of the time
+/// until the next frame, so that shapes moving between the frames are blurred along the way.
+pub fn animate_with_motion_blur(
+    scene: &Scene,
+    camera: &Camera,
+    frames: &Keyframes,
+    t: usize,
+    shutter: f64,
+) -> (Scene, Camera) {
+    let mut shapes: Vec<Box<dyn Intersectable>> = Vec::new();
+    for s in 0..scene.shapes().len() {
+        let start = frames.pos(t, s);
+        let end = if t < frames.last_t() {
+            frames.pos(t + 1, s)
+        } else {
+            start
+        };
+        let shape = scene.shapes()[s].move_to(start);
+        if end == start {
+            shapes.push(shape);
+        } else {
+            let shape: Rc<dyn Intersectable> = Rc::from(shape);
+            let origin = Vec3::new(0.0, 0.0, 0.0);
+            shapes.push(Box::new(Moving::between(shape, origin, end - start)));
+        }
+    }
+    (
+        Scene::new(shapes),
+        camera
+            .look_at(frames.look(t))
+            .move_to(frames.cam(t))
+            .with_shutter(0.0, shutter),
+    )
+}
@@ -0,0 +1,95 @@
+extern crate rand;
+extern crate raytracer;
+
+use rand::{Rng, XorShiftRng};
+use raytracer::prelude::*;
+use std::f64;
+use std::time::{Duration, Instant};
+
+const NUMBER_OF_RAYS: usize = 20_000;
+
+fn create_spheres(rng: &mut XorShiftRng, count: usize) -> Vec<Box<dyn Intersectable>> {
+    let side = (count as f64).sqrt();
+    (0..count)
+        .map(|_| {
+            let origin = Vec3::new(
+                (rng.next_f64() - 0.5) * side,
+                rng.next_f64() * 2.0,
+                (rng.next_f64() - 0.5) * side,
+            );
+            Box::new(Sphere::new(origin, 0.3, Color::white())) as Box<dyn Intersectable>
+        })
+        .collect()
+}
+
+fn create_rays(rng: &mut XorShiftRng, count: usize) -> Vec<Ray> {
+    (0..count)
+        .map(|_| {
+            let direction = Vec3::new(
+                rng.next_f64() - 0.5,
+                rng.next_f64() - 0.8,
+                rng.next_f64() - 0.5,
+            );
+            Ray::new(Vec3::new(0.0, 5.0, 0.0), direction.normalize())
+        })
+        .collect()
+}
+
+fn intersect_linearly(shapes: &[Box<dyn Intersectable>], ray: &Ray) -> Option<f64> {
+    let mut closest_so_far = f64::MAX;
+    let mut hit = None;
+    for shape in shapes.iter() {
+        if let Some(intersection) = shape.intersects(ray, 0.0, closest_so_far) {
+            closest_so_far = intersection.distance;
+            hit = Some(closest_so_far);
+        }
+    }
+    hit
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
+
+fn main() {
+    let mut rng = XorShiftRng::new_unseeded();
+    let rays = create_rays(&mut rng, NUMBER_OF_RAYS);
+
+    println!(
+        "{:>8} | {:>10} | {:>12} | {:>10} | {:>8}",
+        "spheres", "build (ms)", "linear (ms)", "bvh (ms)", "speedup"
+    );
+    for &count in [100, 1_000, 10_000, 50_000].iter() {
+        let start = Instant::now();
+        let scene = Scene::new(create_spheres(&mut rng, count));
+        let build = millis(start.elapsed());
+
+        let start = Instant::now();
+        let linear: Vec<Option<f64>> = rays
+            .iter()
+            .map(|ray| intersect_linearly(scene.shapes(), ray))
+            .collect();
+        let linear_time = millis(start.elapsed());
+
+        let start = Instant::now();
+        let bvh: Vec<Option<f64>> = rays
+            .iter()
+            .map(|ray| {
+                scene
+                    .intersects(ray, 0.0, f64::MAX)
+                    .map(|intersection| intersection.distance)
+            })
+            .collect();
+        let bvh_time = millis(start.elapsed());
+
+        assert_eq!(linear, bvh, "the bvh and the linear search disagree");
+        println!(
+            "{:>8} | {:>10.1} | {:>12.1} | {:>10.1} | {:>7.0}x",
+            count,
+            build,
+            linear_time,
+            bvh_time,
+            linear_time / bvh_time
+        );
+    }
+}
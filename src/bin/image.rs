@@ -36,27 +36,29 @@ fn create_scene() -> Scene {
             Color::new(0.6, 0.6, 0.6),
             0.0,
         )),
-        Box::new(Sphere::refractive(
-            Vec3::new(-1.1, 0.0, -0.8),
-            0.5,
-            Color::new(0.7, 0.3, 0.7),
-            1.5,
-        )),
-        Box::new(Sphere::refractive(
-            Vec3::new(-1.1, 0.0, -0.8),
-            -0.47,
-            Color::new(0.7, 0.3, 0.7),
-            1.5,
+        Box::new(Csg::difference(
+            Sphere::refractive(
+                Vec3::new(-1.1, 0.0, -0.8),
+                0.5,
+                Color::new(0.7, 0.3, 0.7),
+                1.5,
+            ),
+            Sphere::refractive(
+                Vec3::new(-1.1, 0.0, -0.8),
+                0.47,
+                Color::new(0.7, 0.3, 0.7),
+                1.5,
+            ),
         )),
         Box::new(Sphere::new(
             Vec3::new(0.0, 0.0, -1.0),
             0.5,
             Color::new(0.1, 0.2, 0.5),
         )),
-        Box::new(Sphere::new(
-            Vec3::new(0.0, -100.5, -1.0),
-            100.0,
-            Color::new(0.5, 0.8, 0.0),
+        Box::new(Plane::new(
+            Vec3::new(0.0, -0.5, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Material::Lambertian(Color::new(0.5, 0.8, 0.0)),
         )),
     ])
 }
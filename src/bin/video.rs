@@ -3,6 +3,7 @@ extern crate raytracer;
 
 use gif::{Encoder, Frame, Repeat, SetParameter};
 use raytracer::prelude::*;
+use std::f64::consts::PI;
 use std::fs::File;
 
 fn create_camera(width: u32, height: u32) -> Camera {
@@ -24,7 +25,32 @@ fn create_camera(width: u32, height: u32) -> Camera {
     )
 }
 
-fn create_scene() -> Scene {
+/// Three drops circling each other, melting together and splitting up again once during the
+/// 50 frames of the video.
+fn create_blob(t: usize) -> Metaballs {
+    let phase = t as f64 / 50.0 * 2.0 * PI;
+    let spread = 0.15 + 0.1 * (1.0 + phase.cos());
+    let balls = (0..3)
+        .map(|k| {
+            let angle = phase + k as f64 * 2.0 * PI / 3.0;
+            let center = Vec3::new(
+                spread * angle.cos(),
+                0.1 * (2.0 * angle).sin(),
+                spread * angle.sin(),
+            );
+            Ball::new(center, 0.35, 1.0)
+        })
+        .collect();
+    Metaballs::new(
+        balls,
+        0.2,
+        Material::Dielectric {
+            refraction_index: 1.33,
+        },
+    )
+}
+
+fn create_scene(t: usize) -> Scene {
     Scene::new(vec![
         Box::new(Sphere::refractive(
             Vec3::new(0.5, -0.2, -0.4),
@@ -38,28 +64,31 @@ fn create_scene() -> Scene {
             Color::new(0.6, 0.6, 0.6),
             0.0,
         )),
-        Box::new(Sphere::refractive(
-            Vec3::new(-1.1, 0.0, -0.8),
-            0.5,
-            Color::new(0.7, 0.3, 0.7),
-            1.5,
-        )),
-        Box::new(Sphere::refractive(
-            Vec3::new(-1.1, 0.0, -0.8),
-            -0.47,
-            Color::new(0.7, 0.3, 0.7),
-            1.5,
+        Box::new(Csg::difference(
+            Sphere::refractive(
+                Vec3::new(-1.1, 0.0, -0.8),
+                0.5,
+                Color::new(0.7, 0.3, 0.7),
+                1.5,
+            ),
+            Sphere::refractive(
+                Vec3::new(-1.1, 0.0, -0.8),
+                0.47,
+                Color::new(0.7, 0.3, 0.7),
+                1.5,
+            ),
         )),
         Box::new(Sphere::new(
             Vec3::new(0.0, 0.0, -1.0),
             0.5,
             Color::new(0.1, 0.2, 0.5),
         )),
-        Box::new(Sphere::new(
-            Vec3::new(0.0, -100.5, -1.0),
-            100.0,
-            Color::new(0.5, 0.8, 0.0),
+        Box::new(Plane::new(
+            Vec3::new(0.0, -0.5, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Material::Lambertian(Color::new(0.5, 0.8, 0.0)),
         )),
+        Box::new(create_blob(t)),
     ])
 }
 
@@ -73,9 +102,9 @@ fn create_frames() -> Keyframes {
                 Vec3::new(0.5, -0.2, -0.4),
                 Vec3::new(1.1, 0.0, -1.2),
                 Vec3::new(-1.1, 0.0, -0.8),
-                Vec3::new(-1.1, 0.0, -0.8),
                 Vec3::new(0.0, 0.0, -1.0),
-                Vec3::new(0.0, -100.5, -1.0),
+                Vec3::new(0.0, -0.5, -1.0),
+                Vec3::new(-0.2, 0.8, -1.5),
             ],
         ),
         Keyframe::new(
@@ -86,9 +115,9 @@ fn create_frames() -> Keyframes {
                 Vec3::new(1.0, -0.2, -0.4),
                 Vec3::new(1.1, 0.0, -1.2),
                 Vec3::new(-1.1, 0.0, -0.8),
-                Vec3::new(-1.1, 0.0, -0.8),
                 Vec3::new(0.0, 0.0, -1.0),
-                Vec3::new(0.0, -100.5, -1.0),
+                Vec3::new(0.0, -0.5, -1.0),
+                Vec3::new(-0.2, 0.8, -1.5),
             ],
         ),
         Keyframe::new(
@@ -99,18 +128,17 @@ fn create_frames() -> Keyframes {
                 Vec3::new(2.0, -0.2, -0.4),
                 Vec3::new(1.1, 0.0, -1.2),
                 Vec3::new(-1.1, 0.0, -0.8),
-                Vec3::new(-1.1, 0.0, -0.8),
                 Vec3::new(0.0, 0.0, -1.0),
-                Vec3::new(0.0, -100.5, -1.0),
+                Vec3::new(0.0, -0.5, -1.0),
+                Vec3::new(-0.2, 0.8, -1.5),
             ],
         ),
     ])
 }
 
 fn main() {
-    let (width, height, number_of_samples) = (300, 150, 2);
+    let (width, height, number_of_samples) = (300, 150, 4);
     let orginal_camera = create_camera(width, height);
-    let orginal_scene = create_scene();
     let frames = create_frames();
 
     let mut image = File::create("video.gif").unwrap();
@@ -118,7 +146,8 @@ fn main() {
     encoder.set(Repeat::Infinite).unwrap();
 
     for i in 0..50 {
-        let (scene, camera) = animate(&orginal_scene, &orginal_camera, &frames, i);
+        let (scene, camera) =
+            animate_with_motion_blur(&create_scene(i), &orginal_camera, &frames, i, 0.5);
 
         let mut pixels = pixels_to_vec(raytracer::trace_scene(
             width,
@@ -0,0 +1,341 @@
+use std::f64;
+use std::ops::Deref;
+
+use prelude::*;
+use scene::Intersection;
+
+const BINS: usize = 12;
+const MAX_PRIMITIVES_IN_LEAF: usize = 4;
+const TRAVERSAL_COST: f64 = 1.0;
+const STACK_SIZE: usize = 64;
+
+/// A node of the flattened hierarchy. The first child of an interior node is stored right
+/// after it, while `offset` points to the second child. For leaves, `offset` is the first of
+/// `count` entries in `Bvh::indices`.
+#[derive(Clone, Debug)]
+struct Node {
+    bounds: Aabb,
+    offset: usize,
+    count: usize,
+    axis: u32,
+}
+
+/// A bounding volume hierarchy over a list of primitives, given by their bounding boxes. It
+/// only stores the indices of the primitives, and leaves testing them to the caller.
+#[derive(Clone, Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+/// A `Bvh` over a list of shapes, which may be boxed or shared. Shapes without a bounding box
+/// can not be placed in the hierarchy, and are tested separately for every ray.
+#[derive(Clone, Debug)]
+pub struct ShapeBvh {
+    bvh: Bvh,
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+impl ShapeBvh {
+    pub fn new<S: Deref<Target = dyn Intersectable>>(shapes: &[S]) -> ShapeBvh {
+        let mut bounds = Vec::with_capacity(shapes.len());
+        let mut bounded = Vec::with_capacity(shapes.len());
+        let mut unbounded = Vec::new();
+        for (index, shape) in shapes.iter().enumerate() {
+            match shape.bounding_box() {
+                Some(bounding_box) => {
+                    bounds.push(bounding_box);
+                    bounded.push(index);
+                }
+                None => unbounded.push(index),
+            }
+        }
+        ShapeBvh {
+            bvh: Bvh::new(&bounds),
+            bounded: bounded,
+            unbounded: unbounded,
+        }
+    }
+
+    /// The bounds of all the shapes, or `None` if any of them is unbounded.
+    pub fn bounds(&self) -> Option<Aabb> {
+        if self.unbounded.is_empty() {
+            self.bvh.bounds()
+        } else {
+            None
+        }
+    }
+
+    /// Finds the closest intersection with `shapes`, which must be the shapes the hierarchy
+    /// was built from.
+    pub fn intersects<S: Deref<Target = dyn Intersectable>>(
+        &self,
+        shapes: &[S],
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<Intersection> {
+        let mut intersection: Option<Intersection> = None;
+        let mut closest_so_far: f64 = t_max;
+
+        for &index in self.unbounded.iter() {
+            if let Some(other_intersection) = shapes[index].intersects(ray, t_min, closest_so_far) {
+                closest_so_far = other_intersection.distance;
+                intersection = Some(other_intersection);
+            }
+        }
+
+        let bounded = &self.bounded;
+        self.bvh
+            .intersects(ray, t_min, closest_so_far, |index, t_min, t_max| {
+                shapes[bounded[index]].intersects(ray, t_min, t_max)
+            })
+            .or(intersection)
+    }
+}
+
+struct Primitive {
+    index: usize,
+    bounds: Aabb,
+    centroid: Vec3,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Bvh {
+        let mut primitives: Vec<Primitive> = bounds
+            .iter()
+            .enumerate()
+            .map(|(index, bounds)| Primitive {
+                index: index,
+                bounds: *bounds,
+                centroid: bounds.centroid(),
+            })
+            .collect();
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * primitives.len()),
+            indices: Vec::with_capacity(primitives.len()),
+        };
+        if !primitives.is_empty() {
+            bvh.build(&mut primitives);
+        }
+        bvh
+    }
+
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.bounds)
+    }
+
+    /// Finds the closest intersection by calling `intersect` with the index of each primitive
+    /// whose bounding box is hit, together with the current `t_min` and `t_max`. Nodes are
+    /// visited front to back, so that `t_max` shrinks as early as possible.
+    pub fn intersects<F>(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        mut intersect: F,
+    ) -> Option<Intersection>
+    where
+        F: FnMut(usize, f64, f64) -> Option<Intersection>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse_direction = Vec3::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+        let mut intersection = None;
+        let mut closest_so_far = t_max;
+        let mut stack = Vec::with_capacity(STACK_SIZE);
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            if node
+                .bounds
+                .hit_inverse(ray.origin, inverse_direction, t_min, closest_so_far)
+            {
+                if node.count > 0 {
+                    for &index in &self.indices[node.offset..node.offset + node.count] {
+                        if let Some(other_intersection) = intersect(index, t_min, closest_so_far) {
+                            closest_so_far = other_intersection.distance;
+                            intersection = Some(other_intersection);
+                        }
+                    }
+                } else {
+                    let (near, far) = if inverse_direction[node.axis] < 0.0 {
+                        (node.offset, current + 1)
+                    } else {
+                        (current + 1, node.offset)
+                    };
+                    stack.push(far);
+                    current = near;
+                    continue;
+                }
+            }
+            match stack.pop() {
+                Some(next) => current = next,
+                None => break,
+            }
+        }
+        intersection
+    }
+
+    fn build(&mut self, primitives: &mut [Primitive]) -> usize {
+        let bounds = primitives.iter().fold(Aabb::empty(), |bounds, primitive| {
+            bounds.union(&primitive.bounds)
+        });
+        let node_index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: bounds,
+            offset: self.indices.len(),
+            count: primitives.len(),
+            axis: 0,
+        });
+
+        let split = if primitives.len() > 1 {
+            find_split(primitives, &bounds)
+        } else {
+            None
+        };
+        match split {
+            Some((axis, position)) => {
+                let (left, right) = primitives.split_at_mut(position);
+                self.build(left);
+                let second_child = self.build(right);
+                self.nodes[node_index].offset = second_child;
+                self.nodes[node_index].count = 0;
+                self.nodes[node_index].axis = axis;
+            }
+            None => {
+                self.indices
+                    .extend(primitives.iter().map(|primitive| primitive.index));
+            }
+        }
+        node_index
+    }
+}
+
+/// Finds the cheapest split according to the surface area heuristic, binning the primitives
+/// by their centroids along the widest axis. The primitives are partitioned so that the split
+/// is at the returned position, or `None` is returned if a leaf is cheaper.
+fn find_split(primitives: &mut [Primitive], bounds: &Aabb) -> Option<(u32, usize)> {
+    let centroid_bounds = primitives.iter().fold(Aabb::empty(), |bounds, primitive| {
+        bounds.expand(primitive.centroid)
+    });
+    let axis = centroid_bounds.largest_axis();
+    let (low, high) = (centroid_bounds.min[axis], centroid_bounds.max[axis]);
+    if high - low <= 0.0 {
+        return if primitives.len() > MAX_PRIMITIVES_IN_LEAF {
+            Some((axis, primitives.len() / 2))
+        } else {
+            None
+        };
+    }
+
+    let bin_of = |primitive: &Primitive| {
+        let bin = (BINS as f64 * (primitive.centroid[axis] - low) / (high - low)) as usize;
+        bin.min(BINS - 1)
+    };
+    let mut counts = [0; BINS];
+    let mut bin_bounds = [Aabb::empty(); BINS];
+    for primitive in primitives.iter() {
+        let bin = bin_of(primitive);
+        counts[bin] += 1;
+        bin_bounds[bin] = bin_bounds[bin].union(&primitive.bounds);
+    }
+
+    let mut best: Option<(usize, f64)> = None;
+    let mut best_cost = f64::MAX;
+    for split in 1..BINS {
+        let (below, above) = (&bin_bounds[..split], &bin_bounds[split..]);
+        let count_below: usize = counts[..split].iter().sum();
+        let count_above: usize = counts[split..].iter().sum();
+        if count_below == 0 || count_above == 0 {
+            continue;
+        }
+        let area = |bins: &[Aabb]| {
+            bins.iter()
+                .fold(Aabb::empty(), |bounds, bin| bounds.union(bin))
+                .surface_area()
+        };
+        let cost = TRAVERSAL_COST
+            + (count_below as f64 * area(below) + count_above as f64 * area(above))
+                / bounds.surface_area().max(f64::MIN_POSITIVE);
+        if cost < best_cost {
+            best = Some((split, cost));
+            best_cost = cost;
+        }
+    }
+
+    match best {
+        Some((split, cost))
+            if cost < primitives.len() as f64 || primitives.len() > MAX_PRIMITIVES_IN_LEAF =>
+        {
+            primitives.sort_by_key(|primitive| bin_of(primitive) >= split);
+            Some((axis, counts[..split].iter().sum()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bvh::Bvh;
+    use hamcrest::prelude::*;
+    use prelude::*;
+    use rand::{Rng, XorShiftRng};
+
+    fn random_vec(rng: &mut XorShiftRng, scale: f64) -> Vec3 {
+        let v = Vec3::new(rng.next_f64(), rng.next_f64(), rng.next_f64());
+        (v - Vec3::new(0.5, 0.5, 0.5)) * scale
+    }
+
+    #[test]
+    fn bvh_should_find_the_same_intersections_as_a_linear_search() {
+        let mut rng = XorShiftRng::new_unseeded();
+        let spheres: Vec<Sphere> = (0..500)
+            .map(|_| Sphere::new(random_vec(&mut rng, 20.0), 0.3, Color::white()))
+            .collect();
+        let bounds: Vec<Aabb> = spheres
+            .iter()
+            .map(|sphere| sphere.bounding_box().unwrap())
+            .collect();
+        let bvh = Bvh::new(&bounds);
+
+        for _ in 0..500 {
+            let ray = Ray::new(random_vec(&mut rng, 30.0), random_vec(&mut rng, 1.0));
+
+            let expected = spheres
+                .iter()
+                .filter_map(|sphere| sphere.intersects(&ray, 0.0, 100.0))
+                .map(|intersection| intersection.distance)
+                .fold(None, |closest: Option<f64>, distance| {
+                    Some(closest.map_or(distance, |closest| closest.min(distance)))
+                });
+            let actual = bvh
+                .intersects(&ray, 0.0, 100.0, |index, t_min, t_max| {
+                    spheres[index].intersects(&ray, t_min, t_max)
+                })
+                .map(|intersection| intersection.distance);
+
+            assert_that!(actual, is(equal_to(expected)));
+        }
+    }
+
+    #[test]
+    fn empty_bvh_should_not_intersect() {
+        let bvh = Bvh::new(&[]);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+
+        assert_that!(bvh.bounds().is_none(), is(true));
+        assert_that!(
+            bvh.intersects(&ray, 0.0, 100.0, |_, _, _| panic!())
+                .is_none(),
+            is(true)
+        );
+    }
+}
@@ -18,6 +18,8 @@ pub struct Camera {
     aspect_ratio: f64,
     aperture: f64,
     distance_to_focus: f64,
+    shutter_open: f64,
+    shutter_close: f64,
 }
 
 impl Camera {
@@ -56,15 +58,29 @@ impl Camera {
             aspect_ratio: aspect_ratio,
             aperture: aperture,
             distance_to_focus: distance_to_focus,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    /// Keeps the shutter open from `open` to `close`, giving every ray a random time in between
+    /// so that moving shapes are blurred.
+    pub fn with_shutter(self, open: f64, close: f64) -> Camera {
+        Camera {
+            shutter_open: open,
+            shutter_close: close,
+            ..self
         }
     }
 
     pub fn create_ray(&self, u: f64, v: f64) -> Ray {
+        let mut rng = rand::thread_rng();
         let rd = self.lens_radius * random_point_in_unit_disc();
         let offset = self.u * rd.x + self.v * rd.y;
         let direection =
             self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset;
-        Ray::new(self.origin + offset, direection.normalize())
+        let time = self.shutter_open + rng.next_f64() * (self.shutter_close - self.shutter_open);
+        Ray::new(self.origin + offset, direection.normalize()).with_time(time)
     }
 
     pub fn look_at(&self, at: Vec3) -> Camera {
@@ -77,6 +93,7 @@ impl Camera {
             self.aperture,
             self.distance_to_focus,
         )
+        .with_shutter(self.shutter_open, self.shutter_close)
     }
 
     pub fn move_to(&self, origin: Vec3) -> Camera {
@@ -89,6 +106,7 @@ impl Camera {
             self.aperture,
             self.distance_to_focus,
         )
+        .with_shutter(self.shutter_open, self.shutter_close)
     }
 }
 
@@ -0,0 +1,263 @@
+use std::f64;
+use std::rc::Rc;
+
+use instance;
+use prelude::*;
+use scene::Intersection;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl Operation {
+    fn is_inside(&self, inside_left: bool, inside_right: bool) -> bool {
+        match *self {
+            Operation::Union => inside_left || inside_right,
+            Operation::Intersection => inside_left && inside_right,
+            Operation::Difference => inside_left && !inside_right,
+        }
+    }
+}
+
+/// Constructive solid geometry, combining two closed shapes. The surface of the result is
+/// found by walking through the intersections of both shapes along the ray, keeping track of
+/// whether the ray is inside of each of them.
+#[derive(Clone)]
+pub struct Csg {
+    operation: Operation,
+    left: Rc<dyn Intersectable>,
+    right: Rc<dyn Intersectable>,
+}
+
+impl Csg {
+    pub fn new(
+        operation: Operation,
+        left: Box<dyn Intersectable>,
+        right: Box<dyn Intersectable>,
+    ) -> Csg {
+        Csg {
+            operation: operation,
+            left: Rc::from(left),
+            right: Rc::from(right),
+        }
+    }
+
+    pub fn union<A, B>(left: A, right: B) -> Csg
+    where
+        A: Intersectable + 'static,
+        B: Intersectable + 'static,
+    {
+        Csg::new(Operation::Union, Box::new(left), Box::new(right))
+    }
+
+    pub fn intersection<A, B>(left: A, right: B) -> Csg
+    where
+        A: Intersectable + 'static,
+        B: Intersectable + 'static,
+    {
+        Csg::new(Operation::Intersection, Box::new(left), Box::new(right))
+    }
+
+    /// The `left` shape with the volume of the `right` shape taken out of it.
+    pub fn difference<A, B>(left: A, right: B) -> Csg
+    where
+        A: Intersectable + 'static,
+        B: Intersectable + 'static,
+    {
+        Csg::new(Operation::Difference, Box::new(left), Box::new(right))
+    }
+}
+
+/// Whether the ray leaves the shape at the intersection, according to the outward normal.
+fn is_exit(ray: &Ray, intersection: &Intersection) -> bool {
+    ray.direction.dot(intersection.geometric_normal) > 0.0
+}
+
+/// Whether the ray starts inside of a shape, given all its intersections ahead of the origin.
+fn starts_inside(ray: &Ray, intersections: &[Intersection]) -> bool {
+    intersections
+        .first()
+        .is_some_and(|intersection| is_exit(ray, intersection))
+}
+
+impl Intersectable for Csg {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.intersections(ray, t_min, t_max).into_iter().next()
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let offset = match self.bounding_box() {
+            Some(bounds) => vec - bounds.centroid(),
+            None => Vec3::new(0.0, 0.0, 0.0),
+        };
+        Box::new(Csg::new(
+            self.operation,
+            instance::translate(&self.left, offset),
+            instance::translate(&self.right, offset),
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self.operation {
+            Operation::Union => match (self.left.bounding_box(), self.right.bounding_box()) {
+                (Some(left), Some(right)) => Some(left.union(&right)),
+                _ => None,
+            },
+            Operation::Intersection => {
+                match (self.left.bounding_box(), self.right.bounding_box()) {
+                    (Some(left), Some(right)) => Some(left.intersection(&right)),
+                    (Some(bounds), None) | (None, Some(bounds)) => Some(bounds),
+                    (None, None) => None,
+                }
+            }
+            Operation::Difference => self.left.bounding_box(),
+        }
+    }
+
+    fn intersections(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<Intersection> {
+        // The shapes are intersected all the way, so that a ray starting inside of a shape can
+        // be told apart from a ray that never reaches it.
+        let left = self.left.intersections(ray, t_min, f64::MAX);
+        let right = self.right.intersections(ray, t_min, f64::MAX);
+        let mut inside_left = starts_inside(ray, &left);
+        let mut inside_right = starts_inside(ray, &right);
+
+        let mut result = Vec::new();
+        let mut left = left.into_iter().peekable();
+        let mut right = right.into_iter().peekable();
+        loop {
+            let from_left = match (left.peek(), right.peek()) {
+                (Some(l), Some(r)) => l.distance <= r.distance,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let was_inside = self.operation.is_inside(inside_left, inside_right);
+            let intersection = if from_left {
+                inside_left = !inside_left;
+                left.next().unwrap()
+            } else {
+                inside_right = !inside_right;
+                right.next().unwrap()
+            };
+            if intersection.distance >= t_max {
+                break;
+            }
+            if was_inside == self.operation.is_inside(inside_left, inside_right) {
+                continue;
+            }
+            // The surface of the subtracted shape faces into the result.
+            if !from_left && self.operation == Operation::Difference {
+                result.push(Intersection {
+                    normal: intersection.normal.invert(),
+                    geometric_normal: intersection.geometric_normal.invert(),
+                    ..intersection
+                });
+            } else {
+                result.push(intersection);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+
+    fn sphere(x: f64, radius: f64) -> Sphere {
+        Sphere::new(Vec3::new(x, 0.0, 0.0), radius, Color::white())
+    }
+
+    fn ray() -> Ray {
+        Ray::new(Vec3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0))
+    }
+
+    fn distances(shape: &dyn Intersectable) -> Vec<f64> {
+        shape
+            .intersections(&ray(), 0.0, 100.0)
+            .iter()
+            .map(|intersection| intersection.distance)
+            .collect()
+    }
+
+    #[test]
+    fn union_should_only_keep_the_outer_surfaces() {
+        let union = Csg::union(sphere(-1.0, 2.0), sphere(1.0, 2.0));
+
+        assert_that!(distances(&union), is(equal_to(vec![7.0, 13.0])));
+    }
+
+    #[test]
+    fn intersection_should_give_a_lens() {
+        let lens = Csg::intersection(sphere(-1.0, 2.0), sphere(1.0, 2.0));
+
+        assert_that!(distances(&lens), is(equal_to(vec![9.0, 11.0])));
+        assert_that!(
+            lens.bounding_box().unwrap().min,
+            is(equal_to(Vec3::new(-1.0, -2.0, -2.0)))
+        );
+    }
+
+    #[test]
+    fn difference_should_take_a_bite_out_of_the_shape() {
+        let bitten = Csg::difference(sphere(0.0, 2.0), sphere(2.0, 1.0));
+
+        let intersections = bitten.intersections(&ray(), 0.0, 100.0);
+
+        let distances: Vec<f64> = intersections.iter().map(|i| i.distance).collect();
+        assert_that!(distances, is(equal_to(vec![8.0, 11.0])));
+        assert_that!(
+            intersections[1].normal,
+            is(equal_to(Vec3::new(1.0, 0.0, 0.0)))
+        );
+    }
+
+    #[test]
+    fn hollow_sphere_should_have_inner_surfaces_facing_inwards() {
+        let hollow = Csg::difference(sphere(0.0, 2.0), sphere(0.0, 1.0));
+
+        let intersections = hollow.intersections(&ray(), 0.0, 100.0);
+
+        let normals: Vec<f64> = intersections.iter().map(|i| i.normal.x).collect();
+        assert_that!(normals, is(equal_to(vec![-1.0, 1.0, -1.0, 1.0])));
+    }
+
+    fn cut_sphere() -> Csg {
+        let half_space = Plane::new(
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Material::Lambertian(Color::white()),
+        );
+        Csg::difference(sphere(0.0, 2.0), half_space)
+    }
+
+    #[test]
+    fn moved_difference_should_keep_where_the_half_space_cuts_it() {
+        let moved = cut_sphere().move_to(Vec3::new(5.0, 0.0, 0.0));
+
+        assert_that!(distances(&*moved), is(equal_to(vec![13.0, 16.0])));
+    }
+
+    #[test]
+    fn csg_should_be_transformed_like_any_other_shape() {
+        let stretched =
+            Transformed::new(cut_sphere(), Matrix4::scaling(Vec3::new(2.0, 1.0, 1.0))).unwrap();
+
+        assert_that!(distances(&stretched), is(equal_to(vec![6.0, 12.0])));
+    }
+
+    #[test]
+    fn ray_starting_inside_should_only_hit_the_way_out() {
+        let union = Csg::union(sphere(-1.0, 2.0), sphere(1.0, 2.0));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let i = union.intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(i.distance, is(equal_to(3.0)));
+    }
+}
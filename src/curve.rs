@@ -0,0 +1,459 @@
+use std::f64;
+
+use prelude::*;
+use quadric::Frame;
+use scatter;
+use scene::Intersection;
+
+/// How much of the width the curve is allowed to stray from the straight segments it is
+/// split into before being intersected.
+const FLATNESS: f64 = 0.05;
+const MAX_SUBDIVISIONS: u32 = 10;
+/// How far from its origin a ray has to get before a hit counts, so that rays leaving the
+/// curve don't hit it again right away.
+const SURFACE_DISTANCE: f64 = 1e-9;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveShape {
+    /// A flat strip that always faces the ray, which is cheap and looks right for thin curves.
+    Ribbon,
+    /// A round tube, for curves that are thick enough for their sides to be seen.
+    Tube,
+}
+
+/// A cubic Bézier curve through four control points, with a width that changes linearly from
+/// the start to the end. Useful for hair, fur and grass, where a scene can hold millions of
+/// them, so the curve itself is kept small and is only bounded by its control points.
+#[derive(Clone)]
+pub struct Curve {
+    pub points: [Vec3; 4],
+    pub widths: (f64, f64),
+    pub shape: CurveShape,
+    pub material: Material,
+    hair_roughness: Option<f64>,
+    subdivisions: u32,
+}
+
+/// A hit with a piece of the curve, in the coordinates of the ray.
+struct CurveHit {
+    depth: f64,
+    u: f64,
+    v: f64,
+    center: Vec3,
+}
+
+impl Curve {
+    pub fn new(points: [Vec3; 4], start_width: f64, end_width: f64, material: Material) -> Curve {
+        Curve {
+            points: points,
+            widths: (start_width, end_width),
+            shape: CurveShape::Ribbon,
+            material: material,
+            hair_roughness: None,
+            subdivisions: subdivisions(&points, start_width.max(end_width)),
+        }
+    }
+
+    pub fn with_shape(self, shape: CurveShape) -> Curve {
+        Curve {
+            shape: shape,
+            ..self
+        }
+    }
+
+    /// Shades the curve as a hair fiber with the color of its material, see `scatter::hair`.
+    pub fn with_hair_shading(self, roughness: f64) -> Curve {
+        Curve {
+            hair_roughness: Some(roughness),
+            ..self
+        }
+    }
+
+    pub fn point(&self, u: f64) -> Vec3 {
+        evaluate(&self.points, u)
+    }
+
+    /// The direction of the curve at `u`, not normalized.
+    pub fn tangent(&self, u: f64) -> Vec3 {
+        let p = &self.points;
+        let (p0, p1, p2) = (p[1] - p[0], p[2] - p[1], p[3] - p[2]);
+        let tangent = 3.0 * ((1.0 - u) * (1.0 - u) * p0 + 2.0 * u * (1.0 - u) * p1 + u * u * p2);
+        if tangent.squared_length() > 0.0 {
+            tangent
+        } else {
+            // The ends of a curve where two control points are equal.
+            p[3] - p[0]
+        }
+    }
+
+    pub fn width(&self, u: f64) -> f64 {
+        self.widths.0 + (self.widths.1 - self.widths.0) * u
+    }
+
+    /// The part of the curve between `u0` and `u1` as a curve of its own.
+    pub fn section(&self, u0: f64, u1: f64) -> Curve {
+        let (head, _) = split(&self.points, u1);
+        let (_, points) = split(&head, if u1 > 0.0 { u0 / u1 } else { 0.0 });
+        let widths = (self.width(u0), self.width(u1));
+        Curve {
+            points: points,
+            widths: widths,
+            subdivisions: subdivisions(&points, widths.0.max(widths.1)),
+            ..self.clone()
+        }
+    }
+
+    /// Splits the curve into `count` sections, which have much tighter bounding boxes than the
+    /// whole curve when it bends a lot.
+    pub fn sections(&self, count: u32) -> Vec<Curve> {
+        let count = count.max(1);
+        (0..count)
+            .map(|i| {
+                let u0 = i as f64 / count as f64;
+                let u1 = (i + 1) as f64 / count as f64;
+                self.section(u0, u1)
+            })
+            .collect()
+    }
+
+    /// Finds the closest hit with the part of the curve between `u0` and `u1`, given by
+    /// `points` in the coordinates of the ray, where the ray starts at the origin and goes
+    /// along the y axis.
+    fn intersect_section(
+        &self,
+        points: &[Vec3; 4],
+        u0: f64,
+        u1: f64,
+        subdivisions: u32,
+        depth_min: f64,
+        depth_max: f64,
+    ) -> Option<CurveHit> {
+        let half_width = self.width(u0).max(self.width(u1)) / 2.0;
+        let bounds = Aabb::from_points(points);
+        if bounds.min.x - half_width > 0.0
+            || bounds.max.x + half_width < 0.0
+            || bounds.min.z - half_width > 0.0
+            || bounds.max.z + half_width < 0.0
+            || bounds.min.y - half_width > depth_max
+            || bounds.max.y + half_width < depth_min
+        {
+            return None;
+        }
+
+        if subdivisions > 0 {
+            let (head, tail) = split(points, 0.5);
+            let middle = (u0 + u1) / 2.0;
+            let first =
+                self.intersect_section(&head, u0, middle, subdivisions - 1, depth_min, depth_max);
+            let depth_max = first.as_ref().map_or(depth_max, |hit| hit.depth);
+            let second =
+                self.intersect_section(&tail, middle, u1, subdivisions - 1, depth_min, depth_max);
+            return second.or(first);
+        }
+
+        // The section is close enough to the straight line between its ends. The ray has to
+        // pass between the lines through its ends that are perpendicular to the curve.
+        let flat = |v: Vec3| Vec3::new(v.x, 0.0, v.z);
+        let (start, end) = (flat(points[0]), flat(points[3]));
+        let start_direction = flat(points[1] - points[0]);
+        let start_direction = if start_direction.squared_length() > 0.0 {
+            start_direction
+        } else {
+            end - start
+        };
+        let end_direction = flat(points[3] - points[2]);
+        let end_direction = if end_direction.squared_length() > 0.0 {
+            end_direction
+        } else {
+            end - start
+        };
+        if start.dot(start_direction) > 0.0 || end.dot(end_direction) < 0.0 {
+            return None;
+        }
+
+        let segment = end - start;
+        let w = if segment.squared_length() > 0.0 {
+            (-start.dot(segment) / segment.squared_length()).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        // The closest point on the segment, at the depth of the curve.
+        let closest = start + segment * w;
+        let center = Vec3::new(closest.x, evaluate(points, w).y, closest.z);
+        let u = u0 + (u1 - u0) * w;
+        let half_width = self.width(u) / 2.0;
+        let distance2 = center.x * center.x + center.z * center.z;
+        if distance2 > half_width * half_width {
+            return None;
+        }
+
+        // Which side of the curve the ray passes on, from 0 to 1 across the width.
+        let side = segment.x * center.z - segment.z * center.x;
+        let offset = distance2.sqrt() / half_width;
+        let v = if side > 0.0 {
+            0.5 + offset / 2.0
+        } else {
+            0.5 - offset / 2.0
+        };
+
+        // A ribbon is hit where the ray passes the curve, and a tube on its round side.
+        let thickness = match self.shape {
+            CurveShape::Ribbon => 0.0,
+            CurveShape::Tube => (half_width * half_width - distance2).sqrt(),
+        };
+        [center.y - thickness, center.y + thickness]
+            .iter()
+            .cloned()
+            .find(|&depth| depth > depth_min && depth < depth_max)
+            .map(|depth| CurveHit {
+                depth: depth,
+                u: u,
+                v: v,
+                center: center,
+            })
+    }
+}
+
+fn evaluate(points: &[Vec3; 4], u: f64) -> Vec3 {
+    let v = 1.0 - u;
+    points[0] * (v * v * v)
+        + points[1] * (3.0 * v * v * u)
+        + points[2] * (3.0 * v * u * u)
+        + points[3] * (u * u * u)
+}
+
+/// Splits the control points at `u` with de Casteljau's algorithm.
+fn split(points: &[Vec3; 4], u: f64) -> ([Vec3; 4], [Vec3; 4]) {
+    let lerp = |a: Vec3, b: Vec3| a + (b - a) * u;
+    let (a, b, c) = (
+        lerp(points[0], points[1]),
+        lerp(points[1], points[2]),
+        lerp(points[2], points[3]),
+    );
+    let (d, e) = (lerp(a, b), lerp(b, c));
+    let middle = lerp(d, e);
+    ([points[0], a, d, middle], [middle, e, c, points[3]])
+}
+
+/// How many times the curve has to be halved before every section is close enough to a
+/// straight line, compared to its width.
+fn subdivisions(points: &[Vec3; 4], width: f64) -> u32 {
+    let bend = (0..2)
+        .map(|i| (points[i] - 2.0 * points[i + 1] + points[i + 2]).length())
+        .fold(0.0, f64::max);
+    let tolerance = width * FLATNESS;
+    if bend <= 0.0 {
+        return 0;
+    } else if tolerance <= 0.0 {
+        return MAX_SUBDIVISIONS;
+    }
+    let subdivisions = (2.0f64.sqrt() * 6.0 * bend / (8.0 * tolerance)).log2() / 2.0;
+    (subdivisions.max(0.0).ceil() as u32).min(MAX_SUBDIVISIONS)
+}
+
+impl Intersectable for Curve {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let length = ray.direction.length();
+        let frame = Frame::new(ray.origin, ray.direction);
+        let points = [
+            frame.local(self.points[0] - ray.origin),
+            frame.local(self.points[1] - ray.origin),
+            frame.local(self.points[2] - ray.origin),
+            frame.local(self.points[3] - ray.origin),
+        ];
+        let depth_min = t_min * length + SURFACE_DISTANCE;
+        let depth_max = if t_max < f64::MAX {
+            t_max * length
+        } else {
+            f64::MAX
+        };
+
+        self.intersect_section(&points, 0.0, 1.0, self.subdivisions, depth_min, depth_max)
+            .map(|hit| {
+                let delta = hit.depth / length;
+                let intersection_point = ray.point_along_direction(delta);
+                let tangent = self.tangent(hit.u).normalize();
+                let normal = match self.shape {
+                    CurveShape::Ribbon => {
+                        // Facing the ray, but turned around the curve.
+                        let facing = ray.direction.invert();
+                        (facing - tangent * facing.dot(tangent)).normalize()
+                    }
+                    CurveShape::Tube => {
+                        let hit_point = Vec3::new(0.0, hit.depth, 0.0);
+                        frame.world(hit_point - hit.center).normalize()
+                    }
+                };
+                Intersection::new(delta, intersection_point, normal, Box::new(self.clone()))
+                    .with_uv(hit.u, hit.v)
+                    .with_material(self.material.clone())
+            })
+    }
+
+    fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Color, Ray)> {
+        match self.hair_roughness {
+            Some(roughness) => scatter::hair(
+                self.material.albedo(),
+                roughness,
+                self.tangent(intersection.uv.0),
+                ray,
+                intersection,
+            ),
+            None => self.material.scatter(ray, intersection),
+        }
+    }
+
+    fn scatter_pdf(&self, ray: &Ray, intersection: &Intersection, direction: Vec3) -> f64 {
+        match self.hair_roughness {
+            Some(roughness) => scatter::hair_pdf(
+                roughness,
+                self.tangent(intersection.uv.0),
+                ray,
+                intersection,
+                direction,
+            ),
+            None => self.material.pdf(ray, intersection, direction),
+        }
+    }
+
+    fn scatter_eval(&self, ray: &Ray, intersection: &Intersection, direction: Vec3) -> Color {
+        match self.hair_roughness {
+            Some(roughness) => scatter::hair_eval(
+                self.material.albedo(),
+                roughness,
+                self.tangent(intersection.uv.0),
+                ray,
+                intersection,
+                direction,
+            ),
+            None => self.material.eval(ray, intersection, direction),
+        }
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let offset = vec - Aabb::from_points(&self.points).centroid();
+        Box::new(Curve {
+            points: [
+                self.points[0] + offset,
+                self.points[1] + offset,
+                self.points[2] + offset,
+                self.points[3] + offset,
+            ],
+            ..self.clone()
+        })
+    }
+
+    /// The curve lies within the control points, and reaches at most half of its width
+    /// further out.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let half_width = self.widths.0.max(self.widths.1) / 2.0;
+        let extent = Vec3::new(half_width, half_width, half_width);
+        let bounds = Aabb::from_points(&self.points);
+        Some(Aabb::new(bounds.min - extent, bounds.max + extent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use curve::CurveShape;
+    use hamcrest::prelude::*;
+    use prelude::*;
+    use tests::assert_close_within;
+
+    fn straight_curve() -> Curve {
+        Curve::new(
+            [
+                Vec3::new(-1.0, 0.0, 0.0),
+                Vec3::new(-0.5, 0.0, 0.0),
+                Vec3::new(0.5, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+            ],
+            0.2,
+            0.2,
+            Material::Lambertian(Color::white()),
+        )
+    }
+
+    #[test]
+    fn ray_should_hit_a_ribbon_in_the_middle_of_the_curve() {
+        let ray = Ray::new(Vec3::new(0.25, 0.05, 5.0), Vec3::new(0.0, 0.0, -2.0));
+
+        let i = straight_curve().intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_close_within(i.distance, 2.5, 1e-6);
+        assert_close_within(i.normal.z, 1.0, 1e-6);
+        assert_close_within((i.uv.1 - 0.5).abs(), 0.25, 1e-6);
+        assert_that!(
+            straight_curve()
+                .intersects(
+                    &Ray::new(Vec3::new(0.0, 0.15, 5.0), ray.direction),
+                    0.0,
+                    100.0
+                )
+                .is_none(),
+            is(true)
+        );
+    }
+
+    #[test]
+    fn ray_should_hit_the_round_side_of_a_tube() {
+        let tube = straight_curve().with_shape(CurveShape::Tube);
+        let ray = Ray::new(Vec3::new(0.0, 0.06, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let intersections = tube.intersections(&ray, 0.0, 100.0);
+
+        assert_that!(intersections.len(), is(equal_to(2)));
+        assert_close_within(intersections[0].distance, 4.92, 1e-6);
+        assert_close_within(intersections[1].distance, 5.08, 1e-6);
+        assert_close_within(intersections[0].normal.y, 0.6, 1e-6);
+        assert_close_within(intersections[0].normal.z, 0.8, 1e-6);
+    }
+
+    #[test]
+    fn ray_should_follow_a_bent_curve_with_a_varying_width() {
+        let curve = Curve::new(
+            [
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+            ],
+            0.1,
+            0.0,
+            Material::Lambertian(Color::white()),
+        );
+        let top = curve.point(0.5);
+        let at = |x: f64, y: f64| Ray::new(Vec3::new(x, y, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_close_within(top.y, 0.75, 1e-6);
+        assert_that!(
+            curve.intersects(&at(0.5, 0.74), 0.0, 100.0).is_some(),
+            is(true)
+        );
+        assert_that!(
+            curve.intersects(&at(0.5, 0.5), 0.0, 100.0).is_none(),
+            is(true)
+        );
+        assert_that!(
+            curve.intersects(&at(0.0, 0.01), 0.0, 100.0).is_some(),
+            is(true)
+        );
+        assert_that!(
+            curve.intersects(&at(1.0, 0.01), 0.0, 100.0).is_none(),
+            is(true)
+        );
+    }
+
+    #[test]
+    fn sections_of_a_curve_should_have_tighter_bounding_boxes() {
+        let curve = straight_curve();
+
+        let sections = curve.sections(2);
+
+        assert_that!(sections.len(), is(equal_to(2)));
+        assert_close_within(sections[0].point(1.0).x, 0.0, 1e-6);
+        assert_close_within(sections[1].point(0.5).x, curve.point(0.75).x, 1e-6);
+        assert_close_within(sections[0].bounding_box().unwrap().max.x, 0.1, 1e-6);
+        assert_close_within(curve.bounding_box().unwrap().max.x, 1.1, 1e-6);
+    }
+}
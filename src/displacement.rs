@@ -0,0 +1,728 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use bvh::Bvh;
+use image;
+use mesh::Face;
+use prelude::*;
+use processing;
+use scene::Intersection;
+
+const MAX_LEVEL: u32 = 8;
+
+/// The vertices, normals, uvs and faces of a displaced mesh.
+pub(crate) type Buffers = (Vec<Vec3>, Vec<Vec3>, Vec<(f64, f64)>, Vec<Face>);
+
+#[derive(Clone)]
+enum Source {
+    Texture {
+        width: usize,
+        height: usize,
+        values: Rc<Vec<f64>>,
+    },
+    Function(Rc<dyn Fn(Vec3, (f64, f64)) -> f64>),
+}
+
+/// A height for every point of a surface, which the surface is pushed out along its normals by.
+#[derive(Clone)]
+pub struct Displacement {
+    source: Source,
+    range: (f64, f64),
+}
+
+impl Displacement {
+    /// Heights from a grayscale texture given row by row from the top, looked up by the uv
+    /// coordinates of the surface and repeating outside of 0 to 1.
+    pub fn texture(values: Vec<f64>, width: usize, height: usize) -> Displacement {
+        assert_eq!(
+            values.len(),
+            width * height,
+            "a texture needs one value per pixel"
+        );
+        let range = values
+            .iter()
+            .fold((f64::MAX, f64::MIN), |(min, max), &value| {
+                (min.min(value), max.max(value))
+            });
+        Displacement {
+            source: Source::Texture {
+                width: width,
+                height: height,
+                values: Rc::new(values),
+            },
+            range: range,
+        }
+    }
+
+    /// Loads a texture from a BMP or PNG image, where black is 0 and white is 1.
+    pub fn from_image<P: AsRef<Path>>(path: P) -> io::Result<Displacement> {
+        let (values, width, height) = image::read_grayscale(path.as_ref())?;
+        Ok(Displacement::texture(values, width, height))
+    }
+
+    /// Heights given by a function of the point on the undisplaced surface and its uv
+    /// coordinates. The function must stay within `range`, which bounds the displaced surface.
+    pub fn function<F>(range: (f64, f64), function: F) -> Displacement
+    where
+        F: Fn(Vec3, (f64, f64)) -> f64 + 'static,
+    {
+        Displacement {
+            source: Source::Function(Rc::new(function)),
+            range: range,
+        }
+    }
+
+    /// The lowest and the highest height.
+    pub fn range(&self) -> (f64, f64) {
+        self.range
+    }
+
+    pub fn height(&self, point: Vec3, uv: (f64, f64)) -> f64 {
+        match self.source {
+            Source::Texture {
+                width,
+                height,
+                ref values,
+            } => {
+                // Bilinear interpolation between the centers of the pixels, wrapping around.
+                let x = uv.0.rem_euclid(1.0) * width as f64 - 0.5;
+                let y = (1.0 - uv.1.rem_euclid(1.0)) * height as f64 - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let pixel = |x: f64, y: f64| {
+                    let x = (x as i64).rem_euclid(width as i64) as usize;
+                    let y = (y as i64).rem_euclid(height as i64) as usize;
+                    values[y * width + x]
+                };
+                let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1.0, y0) * fx;
+                let bottom = pixel(x0, y0 + 1.0) * (1.0 - fx) + pixel(x0 + 1.0, y0 + 1.0) * fx;
+                top * (1.0 - fy) + bottom * fy
+            }
+            Source::Function(ref function) => function(point, uv),
+        }
+    }
+}
+
+/// How finely a displaced mesh is tessellated. Edges are halved until the displaced surface
+/// stays within `tolerance` of them and they are no longer than `max_edge_length`, but no more
+/// than `max_level` times.
+#[derive(Clone, Copy, Debug)]
+pub struct Tessellation {
+    pub tolerance: f64,
+    pub max_edge_length: f64,
+    pub max_level: u32,
+}
+
+impl Tessellation {
+    pub fn new(tolerance: f64, max_edge_length: f64) -> Tessellation {
+        Tessellation {
+            tolerance: tolerance,
+            max_edge_length: max_edge_length,
+            max_level: MAX_LEVEL,
+        }
+    }
+
+    pub fn with_max_level(self, max_level: u32) -> Tessellation {
+        Tessellation {
+            max_level: max_level,
+            ..self
+        }
+    }
+}
+
+/// A point of the undisplaced surface. It is displaced along the smooth `normal`, which is the
+/// same for every face at the point, while the normal of the face itself is only used for
+/// `shading`.
+#[derive(Clone, Copy, Debug)]
+struct Vertex {
+    position: Vec3,
+    normal: Vec3,
+    shading: Vec3,
+    uv: (f64, f64),
+}
+
+impl Vertex {
+    /// The same for both orders of the vertices, so that faces sharing an edge split it at
+    /// exactly the same point.
+    fn midpoint(&self, other: &Vertex) -> Vertex {
+        Vertex {
+            position: (self.position + other.position) * 0.5,
+            normal: (self.normal + other.normal).normalize(),
+            shading: (self.shading + other.shading).normalize(),
+            uv: (
+                (self.uv.0 + other.uv.0) * 0.5,
+                (self.uv.1 + other.uv.1) * 0.5,
+            ),
+        }
+    }
+
+    fn displaced(&self, displacement: &Displacement, scale: f64) -> Vec3 {
+        self.position + self.normal * (displacement.height(self.position, self.uv) * scale)
+    }
+
+    fn key(&self) -> [u64; 11] {
+        [
+            self.position.x.to_bits(),
+            self.position.y.to_bits(),
+            self.position.z.to_bits(),
+            self.normal.x.to_bits(),
+            self.normal.y.to_bits(),
+            self.normal.z.to_bits(),
+            self.shading.x.to_bits(),
+            self.shading.y.to_bits(),
+            self.shading.z.to_bits(),
+            self.uv.0.to_bits(),
+            self.uv.1.to_bits(),
+        ]
+    }
+}
+
+struct Displacer<'a> {
+    displacement: &'a Displacement,
+    scale: f64,
+    tessellation: Tessellation,
+}
+
+impl<'a> Displacer<'a> {
+    /// Whether the edge has to be split, which only depends on the edge itself so that the
+    /// faces on both sides of it agree and no cracks open up between them.
+    fn should_split(&self, a: &Vertex, b: &Vertex) -> bool {
+        if (b.position - a.position).length() > self.tessellation.max_edge_length {
+            return true;
+        }
+        let middle = a.midpoint(b).displaced(self.displacement, self.scale);
+        let straight = (a.displaced(self.displacement, self.scale)
+            + b.displaced(self.displacement, self.scale))
+            * 0.5;
+        (middle - straight).length() > self.tessellation.tolerance
+    }
+
+    /// Splits the face into smaller triangles, level by level, halving the edges that need it.
+    fn tessellate(&self, corners: [Vertex; 3]) -> (Vec<Vertex>, Vec<[usize; 3]>) {
+        let mut vertices = corners.to_vec();
+        let mut triangles = vec![[0, 1, 2]];
+        let mut midpoints: HashMap<(usize, usize), Option<usize>> = HashMap::new();
+        for _ in 0..self.tessellation.max_level {
+            let mut split = Vec::with_capacity(triangles.len() * 4);
+            for triangle in &triangles {
+                let mut middle = [None; 3];
+                for (i, middle) in middle.iter_mut().enumerate() {
+                    let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                    let edge = (a.min(b), a.max(b));
+                    *middle = *midpoints.entry(edge).or_insert_with(|| {
+                        if self.should_split(&vertices[a], &vertices[b]) {
+                            vertices.push(vertices[a].midpoint(&vertices[b]));
+                            Some(vertices.len() - 1)
+                        } else {
+                            None
+                        }
+                    });
+                }
+                split_triangle(*triangle, middle, &mut split);
+            }
+            if split.len() == triangles.len() {
+                break;
+            }
+            triangles = split;
+        }
+        (vertices, triangles)
+    }
+}
+
+/// Replaces the triangle by the smaller triangles between its corners and the midpoints of the
+/// edges that are split, where edge `i` goes from corner `i` to the next one.
+fn split_triangle(triangle: [usize; 3], middle: [Option<usize>; 3], split: &mut Vec<[usize; 3]>) {
+    let corner = |i: usize| triangle[i % 3];
+    match middle.iter().filter(|m| m.is_some()).count() {
+        0 => split.push(triangle),
+        1 => {
+            let i = middle.iter().position(|m| m.is_some()).unwrap();
+            let m = middle[i].unwrap();
+            split.push([corner(i), m, corner(i + 2)]);
+            split.push([m, corner(i + 1), corner(i + 2)]);
+        }
+        2 => {
+            // Turned so that the edge that is not split goes from the last corner to the first.
+            let i = (middle.iter().position(|m| m.is_none()).unwrap() + 1) % 3;
+            let (first, second) = (middle[i].unwrap(), middle[(i + 1) % 3].unwrap());
+            split.push([first, corner(i + 1), second]);
+            split.push([corner(i), first, second]);
+            split.push([corner(i), second, corner(i + 2)]);
+        }
+        _ => {
+            let (ab, bc, ca) = (middle[0].unwrap(), middle[1].unwrap(), middle[2].unwrap());
+            split.push([triangle[0], ab, ca]);
+            split.push([ab, triangle[1], bc]);
+            split.push([ca, bc, triangle[2]]);
+            split.push([ab, bc, ca]);
+        }
+    }
+}
+
+/// The normal of every vertex, smoothed over all the faces around its position. Vertices at
+/// the same position get the same normal, even when the faces around them don't share them.
+/// Vertices that only belong to degenerate faces get a zero normal.
+fn vertex_normals(vertices: &[Vec3], triangles: &[[usize; 3]]) -> Vec<Vec3> {
+    let mut positions = Vec::new();
+    let mut indices: HashMap<[u64; 3], usize> = HashMap::new();
+    let welded: Vec<usize> = vertices
+        .iter()
+        .map(|vertex| {
+            *indices.entry(position_key(*vertex)).or_insert_with(|| {
+                positions.push(*vertex);
+                positions.len() - 1
+            })
+        })
+        .collect();
+    let faces: Vec<Face> = triangles
+        .iter()
+        .map(|&[a, b, c]| Face::new(welded[a], welded[b], welded[c]))
+        .collect();
+    let (normals, faces) = processing::smooth_normals(&positions, &[], &faces, 180.0);
+    let mut smooth = vec![Vec3::new(0.0, 0.0, 0.0); positions.len()];
+    for face in &faces {
+        if let Some(corners) = face.normals {
+            for k in 0..3 {
+                smooth[face.vertices[k]] = normals[corners[k]];
+            }
+        }
+    }
+    welded.iter().map(|&index| smooth[index]).collect()
+}
+
+fn position_key(position: Vec3) -> [u64; 3] {
+    [
+        position.x.to_bits(),
+        position.y.to_bits(),
+        position.z.to_bits(),
+    ]
+}
+
+/// The other faces of the mesh that have a corner at one of the corners of each face.
+fn neighbouring_faces(mesh: &Mesh) -> Vec<Vec<usize>> {
+    let corner_keys = |face: &Face| {
+        let vertices = mesh.vertices();
+        face.vertices
+            .iter()
+            .map(|&vertex| position_key(vertices[vertex]))
+            .collect::<Vec<_>>()
+    };
+    let mut around: HashMap<[u64; 3], Vec<usize>> = HashMap::new();
+    for (index, face) in mesh.faces().iter().enumerate() {
+        for key in corner_keys(face) {
+            around.entry(key).or_default().push(index);
+        }
+    }
+    mesh.faces()
+        .iter()
+        .enumerate()
+        .map(|(index, face)| {
+            let mut neighbours: Vec<usize> = corner_keys(face)
+                .iter()
+                .flat_map(|key| around[key].iter().cloned())
+                .filter(|&other| other != index)
+                .collect();
+            neighbours.sort();
+            neighbours.dedup();
+            neighbours
+        })
+        .collect()
+}
+
+fn corners(mesh: &Mesh, face: &Face, smooth_normals: &[Vec3]) -> [Vertex; 3] {
+    let corner = |k: usize| Vertex {
+        position: mesh.vertices()[face.vertices[k]],
+        normal: smooth_normals[face.vertices[k]],
+        shading: match face.normals {
+            Some(normals) => mesh.normals()[normals[k]].normalize(),
+            None => smooth_normals[face.vertices[k]],
+        },
+        uv: match face.uvs {
+            Some(uvs) => mesh.uvs()[uvs[k]],
+            None => (0.0, 0.0),
+        },
+    };
+    [corner(0), corner(1), corner(2)]
+}
+
+fn mesh_normals(mesh: &Mesh) -> Vec<Vec3> {
+    let faces: Vec<[usize; 3]> = mesh.faces().iter().map(|face| face.vertices).collect();
+    vertex_normals(mesh.vertices(), &faces)
+}
+
+/// The displaced vertices and their normals, with the normals of the displaced surface
+/// averaged over the given triangles and turned the same way as the undisplaced ones. The
+/// normals of the faces are turned by as much as the displacement turns the surface, so that
+/// flat shaded faces keep their look where the displacement is even.
+fn displaced_buffers(
+    vertices: &[Vertex],
+    triangles: &[[usize; 3]],
+    displacer: &Displacer,
+) -> (Vec<Vec3>, Vec<Vec3>) {
+    let positions: Vec<Vec3> = vertices
+        .iter()
+        .map(|vertex| vertex.displaced(displacer.displacement, displacer.scale))
+        .collect();
+    let normals = vertex_normals(&positions, triangles)
+        .into_iter()
+        .zip(vertices)
+        .map(|(normal, vertex)| {
+            let normal = if normal.squared_length() == 0.0 {
+                vertex.normal
+            } else if normal.dot(vertex.normal) < 0.0 {
+                normal.invert()
+            } else {
+                normal
+            };
+            let shading = vertex.shading + normal - vertex.normal;
+            if shading.squared_length() > 0.0 {
+                shading.normalize()
+            } else {
+                normal
+            }
+        })
+        .collect();
+    (positions, normals)
+}
+
+fn displaced_face(triangle: [usize; 3], face: &Face) -> Face {
+    Face {
+        vertices: triangle,
+        normals: Some(triangle),
+        uvs: face.uvs.map(|_| triangle),
+        material: face.material,
+    }
+}
+
+/// Tessellates and displaces every face of the mesh, joining the vertices where faces meet.
+pub(crate) fn displace(
+    mesh: &Mesh,
+    displacement: &Displacement,
+    scale: f64,
+    tessellation: Tessellation,
+) -> Buffers {
+    let displacer = Displacer {
+        displacement: displacement,
+        scale: scale,
+        tessellation: tessellation,
+    };
+    let normals = mesh_normals(mesh);
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut indices: HashMap<[u64; 11], usize> = HashMap::new();
+    let mut triangles: Vec<[usize; 3]> = Vec::new();
+    let mut faces = Vec::new();
+    for face in mesh.faces() {
+        let (face_vertices, face_triangles) = displacer.tessellate(corners(mesh, face, &normals));
+        let joined: Vec<usize> = face_vertices
+            .iter()
+            .map(|vertex| {
+                *indices.entry(vertex.key()).or_insert_with(|| {
+                    vertices.push(*vertex);
+                    vertices.len() - 1
+                })
+            })
+            .collect();
+        for [a, b, c] in face_triangles {
+            let triangle = [joined[a], joined[b], joined[c]];
+            triangles.push(triangle);
+            faces.push(displaced_face(triangle, face));
+        }
+    }
+    let (positions, normals) = displaced_buffers(&vertices, &triangles, &displacer);
+    let uvs = vertices.iter().map(|vertex| vertex.uv).collect();
+    (positions, normals, uvs, faces)
+}
+
+/// A mesh that is displaced while rendering instead of up front. Each face is only
+/// tessellated the first time a ray comes close to it, so parts of a large displaced mesh that
+/// are never seen cost nothing. Until then, the face is bounded by its box grown by the
+/// largest displacement. Tessellated faces are kept for as long as the mesh, so a mesh that is
+/// seen in full ends up taking as much memory as one displaced up front.
+#[derive(Clone)]
+pub struct DisplacedMesh {
+    mesh: Mesh,
+    displacement: Displacement,
+    scale: f64,
+    tessellation: Tessellation,
+    normals: Rc<Vec<Vec3>>,
+    neighbours: Rc<Vec<Vec<usize>>>,
+    bvh: Rc<Bvh>,
+    tessellated: Rc<RefCell<Vec<Option<Mesh>>>>,
+}
+
+impl DisplacedMesh {
+    pub fn new(
+        mesh: Mesh,
+        displacement: Displacement,
+        scale: f64,
+        tessellation: Tessellation,
+    ) -> DisplacedMesh {
+        let (low, high) = displacement.range();
+        let reach = (low * scale).abs().max((high * scale).abs());
+        let extent = Vec3::new(reach, reach, reach);
+        let bounds: Vec<Aabb> = mesh
+            .faces()
+            .iter()
+            .map(|face| {
+                let [a, b, c] = face.vertices;
+                let vertices = mesh.vertices();
+                let bounds = Aabb::from_points(&[vertices[a], vertices[b], vertices[c]]);
+                Aabb::new(bounds.min - extent, bounds.max + extent)
+            })
+            .collect();
+        DisplacedMesh {
+            normals: Rc::new(mesh_normals(&mesh)),
+            neighbours: Rc::new(neighbouring_faces(&mesh)),
+            bvh: Rc::new(Bvh::new(&bounds)),
+            tessellated: Rc::new(RefCell::new(vec![None; mesh.faces().len()])),
+            mesh: mesh,
+            displacement: displacement,
+            scale: scale,
+            tessellation: tessellation,
+        }
+    }
+
+    pub fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+
+    fn tessellate_face(&self, index: usize) -> Mesh {
+        let displacer = Displacer {
+            displacement: &self.displacement,
+            scale: self.scale,
+            tessellation: self.tessellation,
+        };
+        let tessellate = |index: usize| {
+            let face = &self.mesh.faces()[index];
+            displacer.tessellate(corners(&self.mesh, face, &self.normals))
+        };
+        // The faces around it are tessellated as well, so that the normals along its edges are
+        // averaged over both sides like when the whole mesh is displaced up front.
+        let (mut vertices, mut triangles) = tessellate(index);
+        let (own_vertices, own_triangles) = (vertices.len(), triangles.len());
+        for &other in self.neighbours[index].iter() {
+            let (other_vertices, other_triangles) = tessellate(other);
+            let offset = vertices.len();
+            triangles.extend(
+                other_triangles
+                    .iter()
+                    .map(|&[a, b, c]| [a + offset, b + offset, c + offset]),
+            );
+            vertices.extend(other_vertices);
+        }
+        let (mut positions, mut normals) = displaced_buffers(&vertices, &triangles, &displacer);
+        positions.truncate(own_vertices);
+        normals.truncate(own_vertices);
+        vertices.truncate(own_vertices);
+        triangles.truncate(own_triangles);
+        let face = &self.mesh.faces()[index];
+        let uvs = vertices.iter().map(|vertex| vertex.uv).collect();
+        let faces = triangles
+            .iter()
+            .map(|&triangle| Face {
+                material: None,
+                ..displaced_face(triangle, face)
+            })
+            .collect();
+        let material = self.mesh.triangle(face).material;
+        Mesh::with_buffers(positions, normals, uvs, faces, vec![], material)
+    }
+}
+
+impl Intersectable for DisplacedMesh {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.bvh
+            .intersects(ray, t_min, t_max, |index, t_min, t_max| {
+                if self.tessellated.borrow()[index].is_none() {
+                    let face = self.tessellate_face(index);
+                    self.tessellated.borrow_mut()[index] = Some(face);
+                }
+                let tessellated = self.tessellated.borrow();
+                tessellated[index]
+                    .as_ref()
+                    .and_then(|face| face.intersects(ray, t_min, t_max))
+            })
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let mesh = self.mesh.translated(vec - self.mesh.center());
+        Box::new(DisplacedMesh::new(
+            mesh,
+            self.displacement.clone(),
+            self.scale,
+            self.tessellation,
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use mesh::Face;
+    use prelude::*;
+    use tests::assert_close;
+
+    fn quad() -> Mesh {
+        Mesh::new(
+            vec![
+                Vec3::new(-1.0, 0.0, 1.0),
+                Vec3::new(1.0, 0.0, 1.0),
+                Vec3::new(1.0, 0.0, -1.0),
+                Vec3::new(-1.0, 0.0, -1.0),
+            ],
+            vec![Face::new(0, 1, 2), Face::new(0, 2, 3)],
+            Material::Lambertian(Color::white()),
+        )
+    }
+
+    fn bump() -> Displacement {
+        Displacement::function((0.0, 1.0), |point, _| {
+            (1.0 - point.x * point.x - point.z * point.z)
+                .max(0.0)
+                .powi(2)
+        })
+    }
+
+    fn down(x: f64, z: f64) -> Ray {
+        Ray::new(Vec3::new(x, 5.0, z), Vec3::new(0.0, -1.0, 0.0))
+    }
+
+    #[test]
+    fn texture_should_be_interpolated_and_repeated() {
+        let texture = Displacement::texture(vec![0.0, 1.0, 1.0, 0.0], 2, 2);
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+
+        assert_close(texture.height(origin, (0.25, 0.75)), 0.0);
+        assert_close(texture.height(origin, (0.75, 0.75)), 1.0);
+        assert_close(texture.height(origin, (0.5, 0.75)), 0.5);
+        assert_close(texture.height(origin, (1.75, -0.25)), 1.0);
+        assert_that!(texture.range(), is(equal_to((0.0, 1.0))));
+    }
+
+    #[test]
+    fn flat_displacement_should_move_the_mesh_without_splitting_it() {
+        let raise = Displacement::function((0.5, 0.5), |_, _| 0.5);
+
+        let raised = quad().displaced(&raise, 2.0, Tessellation::new(1e-3, 10.0));
+
+        assert_that!(raised.faces().len(), is(equal_to(2)));
+        assert_that!(raised.vertices().len(), is(equal_to(4)));
+        assert_that!(raised.bounding_box().unwrap().min.y, is(equal_to(1.0)));
+        assert_close(
+            raised
+                .intersects(&down(0.3, 0.2), 0.0, 100.0)
+                .unwrap()
+                .distance,
+            4.0,
+        );
+    }
+
+    #[test]
+    fn tessellation_should_follow_the_displacement_without_cracks() {
+        let displaced = quad().displaced(&bump(), 0.5, Tessellation::new(1e-3, 10.0));
+
+        let top = displaced.intersects(&down(0.0, 0.0), 0.0, 100.0).unwrap();
+        let misses = (0..20)
+            .flat_map(|i| (0..20).map(move |j| (i, j)))
+            .filter(|&(i, j)| {
+                let (x, z) = (-0.95 + i as f64 * 0.1, -0.95 + j as f64 * 0.1);
+                displaced.intersects(&down(x, z), 0.0, 100.0).is_none()
+            })
+            .count();
+
+        assert_that!(displaced.faces().len() > 2, is(true));
+        assert_that!((top.distance - 4.5).abs() < 1e-2, is(true));
+        assert_that!(top.normal.y > 0.99, is(true));
+        assert_that!(misses, is(equal_to(0)));
+    }
+
+    #[test]
+    fn flat_shaded_faces_should_not_crack_open_where_they_meet() {
+        // A tent with a ridge along z, where the two sides don't share their vertices.
+        let vertices = vec![
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, -1.0),
+            Vec3::new(0.0, 1.0, 1.0),
+            Vec3::new(-1.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 1.0),
+        ];
+        let normals = vec![Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, 1.0, 0.0)];
+        let face = |a: usize, b: usize, c: usize, normal: usize| Face {
+            normals: Some([normal; 3]),
+            ..Face::new(a, b, c)
+        };
+        let faces = vec![
+            face(0, 2, 1, 0),
+            face(0, 3, 2, 0),
+            face(4, 6, 5, 1),
+            face(4, 7, 6, 1),
+        ];
+        let tent = Mesh::with_buffers(
+            vertices,
+            normals,
+            vec![],
+            faces,
+            vec![],
+            Material::Lambertian(Color::white()),
+        );
+        let raise = Displacement::function((0.5, 0.5), |_, _| 0.5);
+        let tessellation = Tessellation::new(1e-3, 10.0);
+
+        let up_front = tent.displaced(&raise, 1.0, tessellation);
+        let at_render_time = DisplacedMesh::new(tent, raise, 1.0, tessellation);
+
+        // The ridge is raised straight up, where pushing each side out along its own normal
+        // would leave a gap between them.
+        for shape in [&up_front as &dyn Intersectable, &at_render_time].iter() {
+            let ridge = shape.intersects(&down(0.001, 0.0), 0.0, 100.0).unwrap();
+            assert_that!((ridge.distance - 3.5).abs() < 1e-2, is(true));
+        }
+    }
+
+    #[test]
+    fn edges_far_from_the_displacement_should_not_be_split() {
+        let fine = Tessellation::new(1e-3, 10.0);
+        let local = Displacement::function((0.0, 1.0), |point, _| {
+            (0.25 - (point.x - 1.0).powi(2) - (point.z - 1.0).powi(2)).max(0.0)
+        });
+
+        let everywhere = quad().displaced(&bump(), 0.5, fine);
+        let corner = quad().displaced(&local, 0.5, fine);
+
+        assert_that!(corner.faces().len() > 2, is(true));
+        assert_that!(corner.faces().len() < everywhere.faces().len(), is(true));
+    }
+
+    #[test]
+    fn displacing_at_render_time_should_match_displacing_up_front() {
+        let tessellation = Tessellation::new(1e-3, 10.0);
+        let up_front = quad().displaced(&bump(), 0.5, tessellation);
+        let at_render_time = DisplacedMesh::new(quad(), bump(), 0.5, tessellation);
+
+        let bounds = at_render_time.bounding_box().unwrap();
+
+        // The last two are right next to the edge between the faces, on either side of it.
+        for &(x, z) in [(0.3, -0.4), (0.3, -0.31), (0.3, -0.29)].iter() {
+            let expected = up_front.intersects(&down(x, z), 0.0, 100.0).unwrap();
+            let actual = at_render_time.intersects(&down(x, z), 0.0, 100.0).unwrap();
+
+            assert_close(actual.distance, expected.distance);
+            assert_that!((actual.normal - expected.normal).length() < 1e-9, is(true));
+        }
+        assert_that!(
+            bounds.max.y >= up_front.bounding_box().unwrap().max.y,
+            is(true)
+        );
+        assert_that!(bounds.max.y, is(equal_to(0.5)));
+    }
+}
@@ -0,0 +1,270 @@
+use std::f64::consts::PI;
+
+use math;
+use prelude::*;
+use quadric::Frame;
+use scene::Intersection;
+
+/// The estimated distance to the Mandelbulb of the given power, centered at the origin. Points
+/// where the iteration `z -> z^power + point` escapes to infinity are outside of it.
+pub fn mandelbulb_distance(point: Vec3, power: f64, iterations: u32) -> f64 {
+    let mut z = point;
+    let mut derivative = 1.0;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        if r > 2.0 {
+            break;
+        }
+        // Raising to a power in spherical coordinates scales the radius and the angles.
+        let theta = if r > 0.0 { (z.z / r).acos() } else { 0.0 };
+        let phi = z.y.atan2(z.x);
+        derivative = r.powf(power - 1.0) * power * derivative + 1.0;
+        let (theta, phi) = (theta * power, phi * power);
+        z = Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        ) * r.powf(power)
+            + point;
+        r = z.length();
+    }
+    if r == 0.0 {
+        return 0.0;
+    }
+    0.5 * r.ln() * r / derivative
+}
+
+/// The radius of the sphere around the Mandelbulb of the given power. Beyond it, the iteration
+/// grows every step and escapes, and it is never more than the escape radius of 2.
+pub fn mandelbulb_radius(power: f64) -> f64 {
+    if power > 1.0 {
+        2.0f64.powf(1.0 / (power - 1.0)).min(2.0)
+    } else {
+        2.0
+    }
+}
+
+/// The distance to the Menger sponge filling the cube from -1 to 1, where each level of `depth`
+/// removes the middle cross from every remaining cube.
+pub fn menger_sponge_distance(point: Vec3, depth: u32) -> f64 {
+    let cube = Sdf::cuboid(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+    let mut distance = cube.distance(point);
+    let mut scale = 1.0;
+    for _ in 0..depth {
+        let cell = |x: f64| (x * scale).rem_euclid(2.0) - 1.0;
+        let a = Vec3::new(cell(point.x), cell(point.y), cell(point.z));
+        scale *= 3.0;
+        let r = Vec3::new(
+            (1.0 - 3.0 * a.x.abs()).abs(),
+            (1.0 - 3.0 * a.y.abs()).abs(),
+            (1.0 - 3.0 * a.z.abs()).abs(),
+        );
+        // The distance to the cross of three bars going through the middle of the cell.
+        let cross = r.x.max(r.y).min(r.y.max(r.z)).min(r.z.max(r.x));
+        distance = distance.max((cross - 1.0) / scale);
+    }
+    distance
+}
+
+/// A sphere with nine smaller spheres around it, each of them with nine smaller spheres around
+/// it again, down to the given depth. Every sphere is a third of the size of its parent.
+#[derive(Clone)]
+pub struct Sphereflake {
+    pub center: Vec3,
+    pub radius: f64,
+    pub depth: u32,
+    pub material: Material,
+    directions: [Vec3; 9],
+}
+
+impl Sphereflake {
+    pub fn new(center: Vec3, radius: f64, depth: u32, material: Material) -> Sphereflake {
+        // Six children around the equator, and three above them, relative to the direction
+        // from the parent. The directions are given along the tangent, axis and bitangent.
+        let mut directions = [Vec3::new(0.0, 0.0, 0.0); 9];
+        for (i, direction) in directions.iter_mut().enumerate() {
+            let (elevation, azimuth) = if i < 6 {
+                (0.0, i as f64 * PI / 3.0)
+            } else {
+                (PI / 3.0, PI / 6.0 + (i - 6) as f64 * 2.0 * PI / 3.0)
+            };
+            *direction = Vec3::new(
+                elevation.cos() * azimuth.cos(),
+                elevation.sin(),
+                elevation.cos() * azimuth.sin(),
+            );
+        }
+        Sphereflake {
+            center: center,
+            radius: radius,
+            depth: depth,
+            material: material,
+            directions: directions,
+        }
+    }
+
+    /// The closest hit with a sphere of the flake grown from the sphere in `frame`, as the
+    /// distance along the ray together with the center and radius of the sphere that was hit.
+    fn intersect_flake(
+        &self,
+        frame: Frame,
+        radius: f64,
+        depth: u32,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<(f64, Vec3, f64)> {
+        // All the spheres grown from this one fit in a sphere of twice its radius.
+        let bounds = sphere_roots(frame.origin, 2.0 * radius, ray);
+        match (bounds.first(), bounds.last()) {
+            (Some(&entry), Some(&exit)) if exit > t_min && entry < t_max => (),
+            _ => return None,
+        }
+
+        let mut closest = sphere_roots(frame.origin, radius, ray)
+            .into_iter()
+            .find(|&delta| delta < t_max && delta > t_min)
+            .map(|delta| (delta, frame.origin, radius));
+        if depth > 0 {
+            let child_radius = radius / 3.0;
+            for &direction in self.directions.iter() {
+                let direction = frame.world(direction);
+                let child = Frame::new(
+                    frame.origin + direction * (radius + child_radius),
+                    direction,
+                );
+                let closest_so_far = closest.map_or(t_max, |hit| hit.0);
+                if let Some(hit) =
+                    self.intersect_flake(child, child_radius, depth - 1, ray, t_min, closest_so_far)
+                {
+                    closest = Some(hit);
+                }
+            }
+        }
+        closest
+    }
+}
+
+fn sphere_roots(center: Vec3, radius: f64, ray: &Ray) -> Vec<f64> {
+    let translated_origin = ray.origin - center;
+    math::solve_quadratic(
+        ray.direction.dot(ray.direction),
+        2.0 * translated_origin.dot(ray.direction),
+        translated_origin.dot(translated_origin) - radius * radius,
+    )
+}
+
+impl Intersectable for Sphereflake {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let frame = Frame::new(self.center, Vec3::new(0.0, 1.0, 0.0));
+        self.intersect_flake(frame, self.radius, self.depth, ray, t_min, t_max)
+            .map(|(delta, center, radius)| {
+                let intersection_point = ray.point_along_direction(delta);
+                let normal = (intersection_point - center) / radius;
+                Intersection::new(delta, intersection_point, normal, Box::new(self.clone()))
+                    .with_material(self.material.clone())
+            })
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        Box::new(Sphereflake {
+            center: vec,
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let extent = Vec3::new(1.0, 1.0, 1.0) * (2.0 * self.radius);
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fractal::*;
+    use hamcrest::prelude::*;
+    use prelude::*;
+
+    #[test]
+    fn mandelbulb_distance_should_be_negative_only_inside() {
+        assert_that!(
+            mandelbulb_distance(Vec3::new(0.0, 0.0, 0.0), 8.0, 10) <= 0.0,
+            is(true)
+        );
+        let distance = mandelbulb_distance(Vec3::new(3.0, 0.0, 0.0), 8.0, 10);
+        assert_that!(distance > 0.0 && distance < 3.0, is(true));
+    }
+
+    #[test]
+    fn mandelbulb_should_fit_inside_of_its_radius() {
+        // Lower powers give bigger bulbs, with a spike out to 2 on the negative z-axis for
+        // power 2, well outside of the unit sphere.
+        let spike = Vec3::new(0.0, 0.0, -1.9);
+        assert_that!(mandelbulb_distance(spike, 2.0, 10) < 1e-3, is(true));
+        for &power in &[2.0, 3.0, 8.0] {
+            let radius = mandelbulb_radius(power) + 1e-3;
+            for k in 0..100 {
+                let (theta, phi) = (k as f64 * 0.31, k as f64 * 0.77);
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                let distance = mandelbulb_distance(direction * radius, power, 10);
+                assert_that!(distance > 0.0, is(true));
+            }
+        }
+        assert_that!(mandelbulb_radius(8.0) < 1.2, is(true));
+    }
+
+    #[test]
+    fn menger_sponge_should_have_holes_through_the_middle() {
+        let center = Vec3::new(0.0, 0.0, 0.0);
+        let corner = Vec3::new(0.9, 0.9, 0.9);
+
+        assert_that!(menger_sponge_distance(center, 0), is(equal_to(-1.0)));
+        assert_that!(menger_sponge_distance(center, 1) > 0.0, is(true));
+        assert_that!(menger_sponge_distance(corner, 1) < 0.0, is(true));
+    }
+
+    #[test]
+    fn menger_sponge_should_be_traced_like_other_distance_fields() {
+        let sponge = SdfShape::new(
+            Sdf::menger_sponge(2).scale(0.5),
+            Material::Lambertian(Color::white()),
+        );
+        let through_the_hole = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let through_a_corner = Ray::new(Vec3::new(0.4, 0.4, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let i = sponge.intersects(&through_a_corner, 0.0, 100.0).unwrap();
+
+        assert_that!((i.distance - 4.5).abs() < 1e-4, is(true));
+        assert_that!(
+            sponge.intersects(&through_the_hole, 0.0, 100.0).is_none(),
+            is(true)
+        );
+    }
+
+    #[test]
+    fn ray_should_hit_the_children_of_a_sphereflake() {
+        let flake = |depth| {
+            Sphereflake::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                1.0,
+                depth,
+                Material::Lambertian(Color::white()),
+            )
+        };
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let without_children = flake(0).intersects(&ray, 0.0, 100.0).unwrap();
+        let with_children = flake(2).intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(without_children.distance, is(equal_to(4.0)));
+        assert_that!((with_children.distance - 10.0 / 3.0).abs() < 1e-9, is(true));
+        assert_that!(
+            flake(2).bounding_box().unwrap().max,
+            is(equal_to(Vec3::new(2.0, 2.0, 2.0)))
+        );
+    }
+}
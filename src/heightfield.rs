@@ -0,0 +1,340 @@
+use std::f64;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use image::read_grayscale;
+use prelude::*;
+use scene::Intersection;
+
+/// Terrain given by a grid of heights between 0 and 1, stretched over the box from `min` to
+/// `min + size`. Each cell of the grid is split into two triangles, but rather than keeping
+/// the triangles in a hierarchy, rays walk the cells of the grid in order and stop at the
+/// first cell they hit.
+#[derive(Clone)]
+pub struct Heightfield {
+    pub min: Vec3,
+    pub size: Vec3,
+    pub material: Material,
+    width: usize,
+    depth: usize,
+    heights: Rc<Vec<f64>>,
+    normals: Rc<Vec<Vec3>>,
+    /// The lowest and highest height in each cell, for skipping cells the ray passes over.
+    cell_ranges: Rc<Vec<(f64, f64)>>,
+}
+
+impl Heightfield {
+    /// Creates a heightfield from `width` times `depth` heights, given row by row along the
+    /// x-axis.
+    pub fn new(
+        heights: Vec<f64>,
+        width: usize,
+        depth: usize,
+        min: Vec3,
+        size: Vec3,
+        material: Material,
+    ) -> Heightfield {
+        assert!(
+            width >= 2 && depth >= 2,
+            "a heightfield needs at least 2x2 heights"
+        );
+        assert_eq!(heights.len(), width * depth);
+
+        let height = |i: usize, j: usize| heights[j * width + i];
+        let mut cell_ranges = Vec::with_capacity((width - 1) * (depth - 1));
+        for j in 0..depth - 1 {
+            for i in 0..width - 1 {
+                let corners = [
+                    height(i, j),
+                    height(i + 1, j),
+                    height(i, j + 1),
+                    height(i + 1, j + 1),
+                ];
+                let low = corners.iter().cloned().fold(f64::MAX, f64::min);
+                let high = corners.iter().cloned().fold(f64::MIN, f64::max);
+                cell_ranges.push((low, high));
+            }
+        }
+
+        // The normals follow the slope between the neighbouring heights.
+        let (cell_width, cell_depth) = (size.x / (width - 1) as f64, size.z / (depth - 1) as f64);
+        let mut normals = Vec::with_capacity(width * depth);
+        for j in 0..depth {
+            for i in 0..width {
+                let (left, right) = (i.saturating_sub(1), (i + 1).min(width - 1));
+                let (back, front) = (j.saturating_sub(1), (j + 1).min(depth - 1));
+                let slope_x = (height(right, j) - height(left, j)) * size.y
+                    / ((right - left) as f64 * cell_width);
+                let slope_z = (height(i, front) - height(i, back)) * size.y
+                    / ((front - back) as f64 * cell_depth);
+                normals.push(Vec3::new(-slope_x, 1.0, -slope_z).normalize());
+            }
+        }
+
+        Heightfield {
+            min: min,
+            size: size,
+            material: material,
+            width: width,
+            depth: depth,
+            heights: Rc::new(heights),
+            normals: Rc::new(normals),
+            cell_ranges: Rc::new(cell_ranges),
+        }
+    }
+
+    /// Loads the heights from a grayscale BMP or PNG image, where black is the bottom of the
+    /// box and white is the top. The image is laid out with its x-axis along the x-axis, and
+    /// must be at least 2x2 pixels.
+    pub fn from_image<P: AsRef<Path>>(
+        path: P,
+        min: Vec3,
+        size: Vec3,
+        material: Material,
+    ) -> io::Result<Heightfield> {
+        let (heights, width, depth) = read_grayscale(path.as_ref())?;
+        if width < 2 || depth < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "a heightfield needs at least 2x2 heights, the image is {}x{}",
+                    width, depth
+                ),
+            ));
+        }
+        Ok(Heightfield::new(heights, width, depth, min, size, material))
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Vec3 {
+        Vec3::new(
+            self.min.x + self.size.x * i as f64 / (self.width - 1) as f64,
+            self.min.y + self.size.y * self.heights[j * self.width + i],
+            self.min.z + self.size.z * j as f64 / (self.depth - 1) as f64,
+        )
+    }
+
+    fn uv(&self, i: usize, j: usize) -> (f64, f64) {
+        (
+            i as f64 / (self.width - 1) as f64,
+            j as f64 / (self.depth - 1) as f64,
+        )
+    }
+
+    /// The two triangles of the cell, with their normals pointing up.
+    fn triangles(&self, i: usize, j: usize) -> [Triangle; 2] {
+        let corners = [(i, j), (i, j + 1), (i + 1, j + 1), (i + 1, j)];
+        let triangle = |a: (usize, usize), b: (usize, usize), c: (usize, usize)| {
+            let normal = |(i, j): (usize, usize)| self.normals[j * self.width + i];
+            Triangle::new(
+                self.vertex(a.0, a.1),
+                self.vertex(b.0, b.1),
+                self.vertex(c.0, c.1),
+                self.material.clone(),
+            )
+            .with_normals(normal(a), normal(b), normal(c))
+            .with_uvs(self.uv(a.0, a.1), self.uv(b.0, b.1), self.uv(c.0, c.1))
+        };
+        [
+            triangle(corners[0], corners[1], corners[2]),
+            triangle(corners[0], corners[2], corners[3]),
+        ]
+    }
+}
+
+impl Intersectable for Heightfield {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let bounds = self.bounding_box().unwrap();
+        let (t_enter, t_exit) = bounds.clip(ray, t_min, t_max)?;
+        let (cells_x, cells_z) = (self.width - 1, self.depth - 1);
+        let cell_size = (self.size.x / cells_x as f64, self.size.z / cells_z as f64);
+
+        // Walk the cells with a 2D DDA, keeping track of the distance along the ray to the
+        // next cell boundary along each axis.
+        let start = ray.point_along_direction(t_enter);
+        let cell = |offset: f64, size: f64, cells: usize| {
+            ((offset / size).floor().max(0.0) as usize).min(cells - 1)
+        };
+        let mut i = cell(start.x - self.min.x, cell_size.0, cells_x);
+        let mut j = cell(start.z - self.min.z, cell_size.1, cells_z);
+        let step = |direction: f64, origin: f64, min: f64, size: f64, index: usize| {
+            if direction > 0.0 {
+                let boundary = min + (index + 1) as f64 * size;
+                (1, size / direction, (boundary - origin) / direction)
+            } else if direction < 0.0 {
+                let boundary = min + index as f64 * size;
+                (-1, -size / direction, (boundary - origin) / direction)
+            } else {
+                (0, f64::INFINITY, f64::INFINITY)
+            }
+        };
+        let (step_i, delta_x, mut next_x) =
+            step(ray.direction.x, ray.origin.x, self.min.x, cell_size.0, i);
+        let (step_j, delta_z, mut next_z) =
+            step(ray.direction.z, ray.origin.z, self.min.z, cell_size.1, j);
+
+        let mut t_cell = t_enter;
+        loop {
+            let t_next = next_x.min(next_z).min(t_exit);
+            let (low, high) = self.cell_ranges[j * cells_x + i];
+            let (y0, y1) = (
+                ray.point_along_direction(t_cell).y,
+                ray.point_along_direction(t_next).y,
+            );
+            let margin = 1e-9 * self.size.y.abs().max(1.0);
+            if y0.min(y1) <= self.min.y + high * self.size.y + margin
+                && y0.max(y1) >= self.min.y + low * self.size.y - margin
+            {
+                let mut intersection: Option<Intersection> = None;
+                let mut closest_so_far = t_max;
+                for triangle in self.triangles(i, j).iter() {
+                    if let Some(other_intersection) =
+                        triangle.intersects(ray, t_min, closest_so_far)
+                    {
+                        closest_so_far = other_intersection.distance;
+                        intersection = Some(other_intersection);
+                    }
+                }
+                if intersection.is_some() {
+                    return intersection;
+                }
+            }
+
+            if t_next >= t_exit {
+                return None;
+            }
+            t_cell = t_next;
+            if next_x < next_z {
+                if (step_i < 0 && i == 0) || (step_i > 0 && i == cells_x - 1) {
+                    return None;
+                }
+                i = (i as isize + step_i) as usize;
+                next_x += delta_x;
+            } else {
+                if (step_j < 0 && j == 0) || (step_j > 0 && j == cells_z - 1) {
+                    return None;
+                }
+                j = (j as isize + step_j) as usize;
+                next_z += delta_z;
+            }
+        }
+    }
+
+    /// Moves the center of the box around the heightfield to `vec`.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        Box::new(Heightfield {
+            min: vec - self.size * 0.5,
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(&[self.min, self.min + self.size]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bmp::{Image, Pixel};
+    use hamcrest::prelude::*;
+    use prelude::*;
+    use std::env;
+    use std::io;
+    use tests::assert_close;
+
+    fn slope() -> Heightfield {
+        // Rising from 0 at x = 0 to 1 at x = 2.
+        let heights = (0..9).map(|k| (k % 3) as f64 / 2.0).collect();
+        Heightfield::new(
+            heights,
+            3,
+            3,
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 1.0, 2.0),
+            Material::Lambertian(Color::white()),
+        )
+    }
+
+    #[test]
+    fn ray_from_above_should_hit_the_slope() {
+        let ray = Ray::new(Vec3::new(1.5, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+
+        let i = slope().intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_close(i.distance, 4.25);
+        assert_close(i.normal.x, -0.5 / 1.25f64.sqrt());
+        assert_close(i.uv.0, 0.75);
+        assert_close(i.uv.1, 0.25);
+    }
+
+    #[test]
+    fn ray_along_the_ground_should_hit_the_first_cell_that_rises_above_it() {
+        let ray = Ray::new(Vec3::new(-1.0, 0.75, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        let over = Ray::new(Vec3::new(-1.0, 1.5, 1.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let i = slope().intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_close(i.intersection_point.x, 1.5);
+        assert_that!(slope().intersects(&over, 0.0, 100.0).is_none(), is(true));
+    }
+
+    #[test]
+    fn ray_walking_backwards_should_find_the_closest_cell() {
+        let ray = Ray::new(Vec3::new(3.0, 0.6, 1.5), Vec3::new(-1.0, -0.1, -0.2));
+
+        let i = slope().intersects(&ray, 0.0, 100.0).unwrap();
+        let expected = slope()
+            .triangles(1, 0)
+            .iter()
+            .chain(slope().triangles(1, 1).iter())
+            .chain(slope().triangles(0, 0).iter())
+            .chain(slope().triangles(0, 1).iter())
+            .filter_map(|triangle| triangle.intersects(&ray, 0.0, 100.0))
+            .map(|intersection| intersection.distance)
+            .fold(f64::MAX, f64::min);
+
+        assert_close(i.distance, expected);
+    }
+
+    #[test]
+    fn heights_should_be_read_from_an_image() {
+        let mut image = Image::new(2, 2);
+        image.set_pixel(0, 0, Pixel::new(0, 0, 0));
+        image.set_pixel(1, 0, Pixel::new(255, 255, 255));
+        image.set_pixel(0, 1, Pixel::new(0, 0, 0));
+        image.set_pixel(1, 1, Pixel::new(255, 255, 255));
+        let path = env::temp_dir().join("heightfield_test.bmp");
+        image.save(path.to_str().unwrap()).unwrap();
+
+        let heightfield = Heightfield::from_image(
+            &path,
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Material::Lambertian(Color::white()),
+        )
+        .unwrap();
+        let ray = Ray::new(Vec3::new(0.25, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+
+        assert_close(
+            heightfield.intersects(&ray, 0.0, 100.0).unwrap().distance,
+            4.75,
+        );
+    }
+
+    #[test]
+    fn image_with_a_single_row_should_be_rejected() {
+        let path = env::temp_dir().join("heightfield_row_test.bmp");
+        Image::new(3, 1).save(path.to_str().unwrap()).unwrap();
+
+        let error = Heightfield::from_image(
+            &path,
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Material::Lambertian(Color::white()),
+        )
+        .err()
+        .unwrap();
+
+        assert_that!(error.kind(), is(equal_to(io::ErrorKind::InvalidData)));
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use bmp;
+use png;
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Reads a BMP or PNG image as brightness values between 0 and 1, row by row from the top,
+/// together with its width and height.
+pub(crate) fn read_grayscale(path: &Path) -> io::Result<(Vec<f64>, usize, usize)> {
+    let (pixels, width, height) = read_image(path)?;
+    let heights = pixels.iter().map(|&[r, g, b]| (r + g + b) / 3.0).collect();
+    Ok((heights, width, height))
+}
+
+/// Reads a BMP or PNG image as red, green and blue values between 0 and 1, row by row from
+/// the top, together with its width and height.
+pub(crate) fn read_image(path: &Path) -> io::Result<(Vec<[f64; 3]>, usize, usize)> {
+    let is_png = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+    if is_png {
+        read_png(path)
+    } else {
+        read_bmp(path)
+    }
+}
+
+fn read_bmp(path: &Path) -> io::Result<(Vec<[f64; 3]>, usize, usize)> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| invalid_data("path is not valid unicode"))?;
+    let image = bmp::open(path).map_err(invalid_data)?;
+    let (width, height) = (image.get_width(), image.get_height());
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = image.get_pixel(x, y);
+            pixels.push([
+                pixel.r as f64 / 255.0,
+                pixel.g as f64 / 255.0,
+                pixel.b as f64 / 255.0,
+            ]);
+        }
+    }
+    Ok((pixels, width as usize, height as usize))
+}
+
+fn read_png(path: &Path) -> io::Result<(Vec<[f64; 3]>, usize, usize)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(invalid_data)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(invalid_data)?;
+
+    // Gray images have a single channel, which is used for all three colors.
+    let samples = info.color_type.samples();
+    let gray = matches!(
+        info.color_type,
+        png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha
+    );
+    let mut pixels = Vec::with_capacity((info.width * info.height) as usize);
+    for row in buffer.chunks(info.line_size).take(info.height as usize) {
+        for pixel in row.chunks(samples).take(info.width as usize) {
+            let channel = |index: usize| pixel[if gray { 0 } else { index }] as f64 / 255.0;
+            pixels.push([channel(0), channel(1), channel(2)]);
+        }
+    }
+    Ok((pixels, info.width as usize, info.height as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use png;
+    use std::env;
+    use std::fs::File;
+
+    #[test]
+    fn gray_png_should_be_read_into_all_three_colors() {
+        let path = env::temp_dir().join("image_gray_test.png");
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 2, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 255]).unwrap();
+        writer.finish().unwrap();
+
+        let (pixels, width, height) = super::read_image(&path).unwrap();
+
+        assert_that!((width, height), is(equal_to((2, 1))));
+        assert_that!(pixels, is(equal_to(vec![[0.0; 3], [1.0; 3]])));
+        assert_that!(
+            super::read_grayscale(&path).unwrap().0,
+            is(equal_to(vec![0.0, 1.0]))
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::rc::Rc;
+
+use bvh::ShapeBvh;
+use prelude::*;
+use scene::Intersection;
+
+/// A collection of shapes with its own bounding volume hierarchy. Wrapped in an `Rc`, a group
+/// is geometry that can be shared by many instances.
+pub struct Group {
+    shapes: Vec<Rc<dyn Intersectable>>,
+    bvh: ShapeBvh,
+}
+
+impl Group {
+    pub fn new(shapes: Vec<Box<dyn Intersectable>>) -> Group {
+        let shapes: Vec<Rc<dyn Intersectable>> = shapes.into_iter().map(Rc::from).collect();
+        Group {
+            bvh: ShapeBvh::new(&shapes),
+            shapes: shapes,
+        }
+    }
+}
+
+impl Intersectable for Group {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.bvh.intersects(&self.shapes, ray, t_min, t_max)
+    }
+
+    /// Moves the shapes together so that the center of the bounded ones ends up at `vec`.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let bounds = self
+            .shapes
+            .iter()
+            .filter_map(|shape| shape.bounding_box())
+            .fold(None, |union: Option<Aabb>, bounds| {
+                Some(union.map_or(bounds, |union| union.union(&bounds)))
+            });
+        let offset = match bounds {
+            Some(bounds) => vec - bounds.centroid(),
+            None => Vec3::new(0.0, 0.0, 0.0),
+        };
+        let shapes = self
+            .shapes
+            .iter()
+            .map(|shape| translate(shape, offset))
+            .collect();
+        Box::new(Group::new(shapes))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+}
+
+/// A placement of shared geometry in the scene. Rays are transformed into the space of the
+/// geometry, so that the geometry and its hierarchy is only stored once no matter how many
+/// instances there are of it.
+pub type Instance = Transformed<Rc<dyn Intersectable>>;
+
+/// Moves the shape by `offset`. Shapes without bounds, such as planes, have no center to be
+/// moved to, so they are placed in a translated instance instead.
+pub(crate) fn translate(shape: &Rc<dyn Intersectable>, offset: Vec3) -> Box<dyn Intersectable> {
+    match shape.bounding_box() {
+        Some(bounds) => shape.move_to(bounds.centroid() + offset),
+        // Translations can always be inverted.
+        None => Box::new(Instance::new(shape.clone(), Matrix4::translation(offset)).unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use matrix::Axis;
+    use prelude::*;
+    use std::f64::consts::PI;
+    use std::rc::Rc;
+    use tests::assert_close;
+
+    fn unit_sphere() -> Rc<dyn Intersectable> {
+        Rc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Color::white()))
+    }
+
+    #[test]
+    fn ray_should_intersect_with_translated_instance() {
+        let instance = Instance::new(
+            unit_sphere(),
+            Matrix4::translation(Vec3::new(0.0, 0.0, -5.0)),
+        )
+        .unwrap();
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let i = instance.intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(i.distance, is(equal_to(4.0)));
+        assert_close(i.intersection_point, Vec3::new(0.0, 0.0, -4.0));
+        assert_close(i.normal, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn normals_should_be_returned_in_world_space() {
+        let triangle = Triangle::new(
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Material::Lambertian(Color::white()),
+        );
+        let transform =
+            Matrix4::translation(Vec3::new(3.0, 0.0, 0.0)) * Matrix4::rot(Axis::Y, -PI / 2.0);
+        let instance = Instance::new(Rc::new(triangle), transform).unwrap();
+        let ray = Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let i = instance.intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!((i.distance - 7.0).abs() < 1e-9, is(true));
+        assert_close(i.geometric_normal, Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn instances_should_share_their_geometry() {
+        let geometry: Rc<dyn Intersectable> = Rc::new(Group::new(vec![
+            Box::new(Sphere::new(Vec3::new(-1.0, 0.0, 0.0), 0.5, Color::white())),
+            Box::new(Sphere::new(Vec3::new(1.0, 0.0, 0.0), 0.5, Color::white())),
+        ]));
+        let instances: Vec<Box<dyn Intersectable>> = (0..10)
+            .map(|i| {
+                let offset = Vec3::new(0.0, 0.0, -5.0 * i as f64);
+                Box::new(Instance::new(geometry.clone(), Matrix4::translation(offset)).unwrap())
+                    as Box<dyn Intersectable>
+            })
+            .collect();
+        let scene = Scene::new(instances);
+        let ray = Ray::new(Vec3::new(1.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let i = scene.intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(Rc::strong_count(&geometry), is(equal_to(11)));
+        assert_that!(i.distance, is(equal_to(9.5)));
+        assert_that!(
+            scene.bounding_box().unwrap().min,
+            is(equal_to(Vec3::new(-1.5, -0.5, -45.5)))
+        );
+    }
+
+    #[test]
+    fn moved_group_should_keep_its_unbounded_shapes() {
+        let group = Group::new(vec![
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 0.5, Color::white())),
+            Box::new(Plane::new(
+                Vec3::new(0.0, -1.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Material::Lambertian(Color::white()),
+            )),
+        ]);
+        let down = |x: f64| Ray::new(Vec3::new(x, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        let moved = group.move_to(Vec3::new(10.0, 0.0, 0.0));
+
+        // The floor stays one unit below the center of the sphere.
+        let sphere = moved.intersects(&down(10.0), 0.0, 100.0).unwrap();
+        let floor = moved.intersects(&down(0.0), 0.0, 100.0).unwrap();
+        assert_that!(sphere.distance, is(equal_to(4.5)));
+        assert_that!(floor.distance, is(equal_to(6.0)));
+    }
+}
@@ -9,32 +9,82 @@ extern crate hamcrest;
 
 extern crate rand;
 extern crate bmp;
+extern crate png;
 extern crate rayon;
 
 use std::f64;
 use rand::Rng;
 use prelude::*;
 
+mod aabb;
+mod bvh;
 mod scatter;
+mod material;
+mod texture;
 mod vec;
 mod ray;
 mod color;
 mod camera;
 mod scene;
+mod triangle;
+mod instance;
+mod transform;
+mod csg;
+mod plane;
+mod quadric;
+mod torus;
+mod sdf;
+mod fractal;
+mod heightfield;
+mod image;
+mod metaballs;
+mod curve;
+mod medium;
+mod volume;
+mod displacement;
+mod math;
+mod mesh;
+mod processing;
+mod obj;
+mod ply;
+mod stl;
 mod matrix;
 mod animate;
+mod light;
+mod microfacet;
 
 #[cfg(test)]
 mod tests;
 
 pub mod prelude {
+    pub use aabb::Aabb;
     pub use ray::Ray;
     pub use vec::Vec3;
-    pub use matrix::Matrix4;
+    pub use matrix::{Axis, Matrix4};
     pub use color::Color;
     pub use camera::Camera;
     pub use scene::{Scene, Sphere, Intersectable};
-    pub use animate::{animate, Keyframes, Keyframe};
+    pub use triangle::Triangle;
+    pub use instance::{Group, Instance};
+    pub use transform::{Moving, Transformed};
+    pub use csg::{Csg, Operation};
+    pub use plane::{Plane, Rect, Cuboid};
+    pub use quadric::{Capsule, Cone, Cylinder, Disc};
+    pub use torus::Torus;
+    pub use sdf::{Sdf, SdfShape};
+    pub use fractal::Sphereflake;
+    pub use heightfield::Heightfield;
+    pub use metaballs::{Ball, Falloff, Metaballs};
+    pub use curve::{Curve, CurveShape};
+    pub use medium::{ConstantMedium, Medium};
+    pub use volume::{GridMedium, VoxelGrid};
+    pub use displacement::{DisplacedMesh, Displacement, Tessellation};
+    pub use mesh::{Face, Mesh, MeshError};
+    pub use processing::Processing;
+    pub use material::Material;
+    pub use texture::Texture;
+    pub use light::Light;
+    pub use animate::{animate, animate_with_motion_blur, Keyframes, Keyframe};
 }
 
 pub fn trace_scene(width: u32,
@@ -64,6 +114,8 @@ pub fn trace_scene(width: u32,
     pixels
 }
 
+/// Traces a ray through the scene. Once Step 4 is done, this can call `trace_camera_ray`
+/// instead to get lights, fog and the rest of the materials, see Step 4d in the README.
 fn trace_ray_in_scene(ray: &Ray, scene: &Scene, depth: u32) -> Color {
     if depth == 50 {
         return Color::black(); // Return black to avoid being stuck with an unlimited recursion
@@ -72,6 +124,96 @@ fn trace_ray_in_scene(ray: &Ray, scene: &Scene, depth: u32) -> Color {
             parameter")
 }
 
+/// Traces a ray from the camera, which starts out inside of the media that the camera is
+/// placed in, such as fog around it.
+fn trace_camera_ray(ray: &Ray, scene: &Scene, depth: u32) -> Color {
+    trace_ray_in_media(ray, scene, &scene.media_at(ray), depth, None)
+}
+
+/// Traces the ray through the scene, where `media` are the volumes that the ray is inside of,
+/// with the innermost one last. `scatter` is the point on the surface that the ray left and
+/// the density of that surface scattering it this way, when the surface also sampled the
+/// emitters of the scene. Light from the emitters is then weighted between the two samples.
+fn trace_ray_in_media(
+    ray: &Ray,
+    scene: &Scene,
+    media: &[&dyn Medium],
+    depth: u32,
+    scatter: Option<(Vec3, f64)>,
+) -> Color {
+    if depth == 50 {
+        return Color::black(); // Return black to avoid being stuck with an unlimited recursion
+    }
+    let intersection = scene.intersects(ray, 0.0, f64::MAX);
+
+    // The ray may scatter inside of the medium before it reaches the next surface.
+    if let Some(medium) = media.last() {
+        let t_max = intersection.as_ref().map_or(f64::MAX, |i| i.distance);
+        if let Some(distance) = medium.sample_distance(ray, t_max) {
+            let point = ray.point_along_direction(distance);
+            let emitted = medium.emission(ray, point);
+            return match medium.scatter_at(ray, point) {
+                Some((color, scattered)) => {
+                    let scattered = scattered.with_time(ray.time);
+                    let traced = trace_ray_in_media(&scattered, scene, media, depth + 1, None);
+                    emitted + color * traced
+                }
+                None => emitted,
+            };
+        }
+    }
+
+    match intersection {
+        Some(intersection) => {
+            let mut emitted = intersection
+                .material
+                .as_ref()
+                .map_or(Color::black(), |material| material.emitted(&intersection));
+            if let Some((origin, scatter_pdf)) = scatter {
+                if emitted != Color::black() {
+                    let light_pdf = scene.emitter_pdf(origin, intersection.intersection_point, ray.time);
+                    emitted = emitted * light::power_heuristic(scatter_pdf, light_pdf);
+                }
+            }
+            // Light from the lights and emitters is sampled straight away, except at the
+            // boundaries of media, which rays pass straight through.
+            let boundary = intersection.shape.medium();
+            let sample_lights =
+                boundary.is_none() && (scene.has_emitters() || !scene.lights.is_empty());
+            if sample_lights {
+                emitted = emitted
+                    + light::sample_lights(scene, media, ray, &intersection)
+                    + light::sample_emitters(scene, media, ray, &intersection);
+            }
+            match intersection.shape.scatter(ray, &intersection) {
+                Some((color, scattered)) => {
+                    let scattered = scattered.with_time(ray.time);
+                    // Hitting the boundary of a medium takes the ray into or out of it.
+                    // It keeps its direction, so the surface it was scattered from still
+                    // decides how light from the emitters it reaches is weighted.
+                    let crossed: Vec<&dyn Medium>;
+                    let (media, scatter) = match boundary {
+                        Some(medium) => {
+                            crossed = medium::cross_boundary(media, medium, ray, &intersection);
+                            (&crossed[..], scatter)
+                        }
+                        None if sample_lights => {
+                            let direction = scattered.direction.normalize();
+                            let pdf = intersection.shape.scatter_pdf(ray, &intersection, direction);
+                            (media, Some((scattered.origin, pdf)).filter(|&(_, pdf)| pdf > 0.0))
+                        }
+                        None => (media, None),
+                    };
+                    let traced = trace_ray_in_media(&scattered, scene, media, depth + 1, scatter);
+                    emitted + color * traced
+                }
+                None => emitted,
+            }
+        }
+        None => scene.background.unwrap_or_else(|| gradient(ray)),
+    }
+}
+
 fn gradient(ray: &Ray) -> Color {
     let t = 0.5 * (ray.direction.normalize().y + 1.0);
     (1.0 - t) * Color::white() + t * Color::new(0.5, 0.7, 1.0)
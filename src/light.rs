@@ -0,0 +1,550 @@
+use rand::{self, Rng};
+use std::f64;
+use std::f64::consts::PI;
+
+use medium;
+use prelude::*;
+use quadric::Frame;
+use scatter;
+use scene::Intersection;
+
+/// Shadow rays stop this far short of the point on the light, relative to the distance to
+/// it, so that they don't hit the light itself.
+const SHADOW_EPSILON: f64 = 1e-6;
+
+/// A light that is not part of any shape, and so is never hit by rays. Its light reaches
+/// diffuse surfaces through shadow rays only.
+#[derive(Clone, Copy, Debug)]
+pub enum Light {
+    /// Shines equally in every direction from `position`, dimming with the squared distance.
+    Point { position: Vec3, intensity: Color },
+    /// A point light that only shines within a cone around `direction`. The light fades out
+    /// between the cosines of the inner and the outer angle of the cone.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        intensity: Color,
+        cos_inner: f64,
+        cos_outer: f64,
+    },
+    /// Light from so far away, such as the sun, that it arrives along `direction` everywhere.
+    Directional { direction: Vec3, irradiance: Color },
+}
+
+impl Light {
+    pub fn point(position: Vec3, intensity: Color) -> Light {
+        Light::Point {
+            position: position,
+            intensity: intensity,
+        }
+    }
+
+    /// A spot light at `position` pointing at `target`. It is at full strength within
+    /// `inner_angle` degrees of its direction, and dark beyond `outer_angle` degrees.
+    pub fn spot(
+        position: Vec3,
+        target: Vec3,
+        intensity: Color,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) -> Light {
+        let outer_angle = outer_angle.max(inner_angle);
+        Light::Spot {
+            position: position,
+            direction: (target - position).normalize(),
+            intensity: intensity,
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+        }
+    }
+
+    /// A light shining along `direction`, giving `irradiance` to surfaces facing it.
+    pub fn directional(direction: Vec3, irradiance: Color) -> Light {
+        Light::Directional {
+            direction: direction.normalize(),
+            irradiance: irradiance,
+        }
+    }
+
+    /// The direction from `point` towards the light, the distance to it, and the light
+    /// arriving at `point` from it when nothing is in the way.
+    pub fn illuminate(&self, point: Vec3) -> (Vec3, f64, Color) {
+        match *self {
+            Light::Point {
+                position,
+                intensity,
+            } => {
+                let offset = position - point;
+                let distance = offset.length();
+                (
+                    offset / distance,
+                    distance,
+                    intensity / (distance * distance),
+                )
+            }
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => {
+                let offset = position - point;
+                let distance = offset.length();
+                let towards = offset / distance;
+                let falloff = smoothstep(cos_outer, cos_inner, direction.dot(towards.invert()));
+                (
+                    towards,
+                    distance,
+                    intensity * (falloff / (distance * distance)),
+                )
+            }
+            Light::Directional {
+                direction,
+                irradiance,
+            } => (direction.invert(), f64::MAX, irradiance),
+        }
+    }
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    if edge1 <= edge0 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// A random direction within the cone around `axis` whose half angle has the cosine
+/// `cos_max`, picked evenly over the solid angle of the cone.
+pub fn uniform_in_cone(axis: Vec3, cos_max: f64) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let cos_theta = 1.0 - rng.next_f64() * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.next_f64();
+    let frame = Frame::new(Vec3::new(0.0, 0.0, 0.0), axis);
+    frame.world(Vec3::new(
+        sin_theta * phi.cos(),
+        cos_theta,
+        sin_theta * phi.sin(),
+    ))
+}
+
+/// Where a ray from `origin` to `point` hits `shape`, if it hits the shape at that point and
+/// not before it.
+pub fn hit_at(shape: &dyn Intersectable, origin: Vec3, point: Vec3) -> Option<Intersection> {
+    let ray = Ray::new(origin, point - origin);
+    shape
+        .intersects(&ray, 0.0, 1.0 + SHADOW_EPSILON)
+        .filter(|intersection| intersection.distance > 1.0 - SHADOW_EPSILON)
+}
+
+/// Turns the density of picking a point on a surface evenly by its `area` into the density
+/// per solid angle seen from `origin`, where the surface was hit at `intersection`.
+pub fn area_pdf(origin: Vec3, intersection: &Intersection, area: f64) -> f64 {
+    let offset = intersection.intersection_point - origin;
+    let distance_squared = offset.squared_length();
+    let cosine = intersection.geometric_normal.dot(offset.normalize()).abs();
+    if cosine == 0.0 || area == 0.0 {
+        0.0
+    } else {
+        distance_squared / (cosine * area)
+    }
+}
+
+/// The weight of a sample picked with the density `pdf`, when it could also have been picked
+/// with the density `other_pdf`. The weights of the two always add up to one, and the weight
+/// goes to the one that is most likely to pick the sample, which keeps the noise from either
+/// of them down.
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (pdf, other_pdf) = (pdf * pdf, other_pdf * other_pdf);
+    if pdf + other_pdf == 0.0 {
+        0.0
+    } else {
+        pdf / (pdf + other_pdf)
+    }
+}
+
+/// Follows a shadow ray from `t_min` to `t_max`, passing through the boundaries of media on the
+/// way. Returns the fraction of light that gets through the media, where `media` are the ones
+/// the ray starts out inside of, and the first other shape that the ray hits, if any.
+fn trace_shadow(
+    scene: &Scene,
+    media: &[&dyn Medium],
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> (f64, Option<Intersection>) {
+    let hit = scene.intersects(ray, t_min, t_max);
+    let end = hit.as_ref().map_or(t_max, |hit| hit.distance);
+    let transmittance = media.last().map_or(1.0, |medium| {
+        let start = Ray::new(ray.point_along_direction(t_min), ray.direction);
+        medium.transmittance(&start.with_time(ray.time), end - t_min)
+    });
+    match hit {
+        Some(hit) => match hit.shape.medium() {
+            Some(boundary) => {
+                let media = medium::cross_boundary(media, boundary, ray, &hit);
+                let t_min = hit.distance + SHADOW_EPSILON;
+                let (rest, blocker) = trace_shadow(scene, &media, ray, t_min, t_max);
+                (transmittance * rest, blocker)
+            }
+            None => (transmittance, Some(hit)),
+        },
+        None => (transmittance, None),
+    }
+}
+
+/// The light from the emitters of the scene that the surface at the intersection scatters back
+/// along `ray`, inside of `media`. A ray is aimed at a random point on a random emitter, and
+/// the light it gets is weighted against the surface scattering a ray that way itself.
+pub fn sample_emitters(
+    scene: &Scene,
+    media: &[&dyn Medium],
+    ray: &Ray,
+    intersection: &Intersection,
+) -> Color {
+    let origin = scatter::reflection_origin(intersection);
+    let target = match scene.sample_emitter(origin, ray.time) {
+        Some(target) => target,
+        None => return Color::black(),
+    };
+    let direction = target - origin;
+    let scattered = intersection
+        .shape
+        .scatter_eval(ray, intersection, direction.normalize());
+    if scattered == Color::black() {
+        return Color::black();
+    }
+    let shadow_ray = Ray::new(origin, direction).with_time(ray.time);
+    let (transmittance, hit) =
+        match trace_shadow(scene, media, &shadow_ray, 0.0, 1.0 + SHADOW_EPSILON) {
+            (transmittance, Some(hit)) if hit.distance > 1.0 - SHADOW_EPSILON => {
+                (transmittance, hit)
+            }
+            _ => return Color::black(),
+        };
+    let pdf = scene.emitter_pdf(origin, target, ray.time);
+    if pdf <= 0.0 {
+        return Color::black();
+    }
+    let scatter_pdf = intersection
+        .shape
+        .scatter_pdf(ray, intersection, direction.normalize());
+    let emitted = hit
+        .material
+        .as_ref()
+        .map_or(Color::black(), |material| material.emitted(&hit));
+    emitted * scattered * (transmittance * power_heuristic(pdf, scatter_pdf) / pdf)
+}
+
+/// The light from the lights of the scene that the surface at the intersection scatters back
+/// along `ray`, inside of `media`. Each light takes a single shadow ray, so every light is
+/// connected to the point rather than picking one, which would only add noise. Rays scattered
+/// by the surface never hit these lights, so there is nothing to weigh them against.
+pub fn sample_lights(
+    scene: &Scene,
+    media: &[&dyn Medium],
+    ray: &Ray,
+    intersection: &Intersection,
+) -> Color {
+    let origin = scatter::reflection_origin(intersection);
+    scene.lights.iter().fold(Color::black(), |sum, light| {
+        let (direction, distance, incoming) = light.illuminate(origin);
+        if incoming == Color::black() {
+            return sum;
+        }
+        let scattered = intersection
+            .shape
+            .scatter_eval(ray, intersection, direction);
+        if scattered == Color::black() {
+            return sum;
+        }
+        let shadow_ray = Ray::new(origin, direction).with_time(ray.time);
+        match trace_shadow(scene, media, &shadow_ray, 0.0, distance) {
+            (_, Some(_)) => sum,
+            (transmittance, None) => sum + incoming * scattered * transmittance,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+    use scatter;
+    use std::f64::consts::PI;
+    use tests::assert_close_within;
+
+    fn light() -> Material {
+        Material::Emissive(Color::white())
+    }
+
+    #[test]
+    fn rectangle_should_be_sampled_evenly_over_its_area() {
+        let rect = Rect::xz((0.0, 2.0), (0.0, 1.0), 0.0, light());
+        let origin = Vec3::new(1.0, 5.0, 0.5);
+
+        let point = rect.sample_towards(origin, 0.0).unwrap();
+
+        assert_that!(point.y, is(equal_to(0.0)));
+        assert_that!(point.x >= 0.0 && point.x <= 2.0, is(true));
+        // 5² / (1 · 2), straight below the origin.
+        let pdf = rect.pdf_towards(origin, Vec3::new(1.0, 0.0, 0.5), 0.0);
+        assert_that!((pdf - 12.5).abs() < 1e-9, is(true));
+        assert_that!(
+            rect.pdf_towards(origin, Vec3::new(3.0, 0.0, 0.5), 0.0),
+            is(equal_to(0.0))
+        );
+    }
+
+    #[test]
+    fn sphere_should_be_sampled_within_the_cone_it_fills() {
+        let sphere = Sphere::with_material(Vec3::new(0.0, 0.0, -4.0), 2.0, light());
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        let cos_max = (1.0 - 4.0 / 16.0_f64).sqrt();
+
+        for _ in 0..100 {
+            let point = sphere.sample_towards(origin, 0.0).unwrap();
+            let pdf = sphere.pdf_towards(origin, point, 0.0);
+
+            assert_that!(
+                ((point - sphere.origin).length() - 2.0).abs() < 1e-9,
+                is(true)
+            );
+            assert_that!(
+                (pdf - 1.0 / (2.0 * PI * (1.0 - cos_max))).abs() < 1e-9,
+                is(true)
+            );
+        }
+        // The far side of the sphere is hidden behind the near side.
+        let far_side = Vec3::new(0.0, 0.0, -6.0);
+        assert_that!(sphere.pdf_towards(origin, far_side, 0.0), is(equal_to(0.0)));
+    }
+
+    #[test]
+    fn room_with_black_background_should_be_lit_by_the_emitter_around_it() {
+        // Every direction above the floor sees the light, so the floor reflects its albedo.
+        let floor = Rect::xz(
+            (-1.0, 1.0),
+            (-1.0, 1.0),
+            0.0,
+            Material::Lambertian(Color::new(0.5, 0.5, 0.5)),
+        );
+        let dome = Sphere::with_material(Vec3::new(0.0, 0.0, 0.0), 10.0, light());
+        let scene =
+            Scene::new(vec![Box::new(floor), Box::new(dome)]).with_background(Color::black());
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        let samples = 4000;
+        let sum = (0..samples).fold(0.0, |sum, _| {
+            sum + ::trace_ray_in_media(&ray, &scene, &[], 0, None).r
+        });
+
+        assert_that!((sum / samples as f64 - 0.5).abs() < 0.03, is(true));
+    }
+
+    #[test]
+    fn glossy_floor_should_converge_when_light_and_scatter_samples_are_combined() {
+        // Under an even light, the floor reflects what its scattered rays carry on average.
+        let albedo = Color::new(0.5, 0.5, 0.5);
+        let floor = Rect::xz(
+            (-1.0, 1.0),
+            (-1.0, 1.0),
+            0.0,
+            Material::Metal {
+                albedo: albedo,
+                fuzz: 0.5,
+            },
+        );
+        let dome = Sphere::with_material(Vec3::new(0.0, 0.0, 0.0), 10.0, light());
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let i = floor.intersects(&ray, 0.0, 100.0).unwrap();
+        let scene =
+            Scene::new(vec![Box::new(floor), Box::new(dome)]).with_background(Color::black());
+
+        let samples = 4000;
+        let expected = (0..samples).fold(0.0, |sum, _| {
+            sum + scatter::reflection(albedo, 0.5, &ray, &i).map_or(0.0, |(color, _)| color.r)
+        });
+        let traced = (0..samples).fold(0.0, |sum, _| {
+            sum + ::trace_ray_in_media(&ray, &scene, &[], 0, None).r
+        });
+
+        assert_that!(
+            (traced - expected).abs() / (samples as f64) < 0.03,
+            is(true)
+        );
+    }
+
+    #[test]
+    fn power_heuristic_should_split_the_weight_between_the_samples() {
+        let weight = super::power_heuristic(3.0, 1.0);
+
+        assert_that!((weight - 0.9).abs() < 1e-12, is(true));
+        assert_that!(
+            (weight + super::power_heuristic(1.0, 3.0) - 1.0).abs() < 1e-12,
+            is(true)
+        );
+        assert_that!(super::power_heuristic(2.0, 0.0), is(equal_to(1.0)));
+    }
+
+    #[test]
+    fn emitter_hidden_behind_another_shape_should_not_light_the_surface() {
+        let floor = Rect::xz(
+            (-1.0, 1.0),
+            (-1.0, 1.0),
+            0.0,
+            Material::Lambertian(Color::white()),
+        );
+        let ceiling = Rect::xz(
+            (-5.0, 5.0),
+            (-5.0, 5.0),
+            1.0,
+            Material::Lambertian(Color::white()),
+        );
+        let lamp = Rect::xz((-1.0, 1.0), (-1.0, 1.0), 2.0, light()).flipped();
+        let scene = Scene::new(vec![Box::new(floor), Box::new(ceiling), Box::new(lamp)]);
+        let ray = Ray::new(Vec3::new(0.0, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let i = scene.intersects(&ray, 0.0, 100.0).unwrap();
+
+        let direct = super::sample_emitters(&scene, &[], &ray, &i);
+
+        assert_that!(direct, is(equal_to(Color::black())));
+    }
+
+    #[test]
+    fn emitter_behind_fog_should_be_dimmed_by_it() {
+        let floor = || {
+            Rect::xz(
+                (-1.0, 1.0),
+                (-1.0, 1.0),
+                0.0,
+                Material::Lambertian(Color::white()),
+            )
+        };
+        let lamp = || Rect::xz((-0.01, 0.01), (-0.01, 0.01), 2.0, light()).flipped();
+        let slab = Cuboid::new(
+            Vec3::new(-5.0, 0.5, -5.0),
+            Vec3::new(5.0, 1.5, 5.0),
+            Material::Lambertian(Color::white()),
+        );
+        let fog = ConstantMedium::new(slab, 1.0, Color::white());
+        let clear = Scene::new(vec![Box::new(floor()), Box::new(lamp())]);
+        let foggy = Scene::new(vec![Box::new(floor()), Box::new(lamp()), Box::new(fog)]);
+        let ray = Ray::new(Vec3::new(0.0, 0.25, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let i = foggy.intersects(&ray, 0.0, 100.0).unwrap();
+
+        let direct = super::sample_emitters(&clear, &[], &ray, &i);
+        let dimmed = super::sample_emitters(&foggy, &[], &ray, &i);
+
+        // The light goes straight up through one unit of fog.
+        assert_that!(
+            (dimmed.r / direct.r - (-1.0f64).exp()).abs() < 1e-3,
+            is(true)
+        );
+    }
+
+    fn lit_floor(lights: Vec<Light>, mut shapes: Vec<Box<dyn Intersectable>>) -> (Scene, Ray) {
+        let floor = Rect::xz(
+            (-5.0, 5.0),
+            (-5.0, 5.0),
+            0.0,
+            Material::Lambertian(Color::white()),
+        );
+        shapes.push(Box::new(floor));
+        let scene = Scene::new(shapes)
+            .with_background(Color::black())
+            .with_lights(lights);
+        (
+            scene,
+            Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0)),
+        )
+    }
+
+    #[test]
+    fn point_light_should_dim_with_the_squared_distance() {
+        // A white surface reflects cos / π of the light it gets, which is 4π / 2² here.
+        let intensity = Color::new(4.0 * PI, 4.0 * PI, 4.0 * PI);
+        let (scene, ray) = lit_floor(
+            vec![Light::point(Vec3::new(0.0, 2.0, 0.0), intensity)],
+            vec![],
+        );
+
+        let color = ::trace_ray_in_media(&ray, &scene, &[], 0, None);
+
+        assert_close_within(color, Color::white(), 1e-6);
+    }
+
+    #[test]
+    fn point_light_behind_fog_should_be_dimmed_by_it() {
+        let intensity = Color::new(4.0 * PI, 4.0 * PI, 4.0 * PI);
+        let lamp = Light::point(Vec3::new(0.0, 2.0, 0.0), intensity);
+        let fog = |bottom: f64| {
+            let slab = Cuboid::new(
+                Vec3::new(-5.0, bottom, -5.0),
+                Vec3::new(5.0, 1.5, 5.0),
+                Material::Lambertian(Color::white()),
+            );
+            ConstantMedium::new(slab, 1.0, Color::white())
+        };
+        let (above, ray) = lit_floor(vec![lamp], vec![Box::new(fog(0.5))]);
+        let (around, _) = lit_floor(vec![lamp], vec![Box::new(fog(-1.0))]);
+        let ray = Ray::new(Vec3::new(0.0, 0.25, 0.0), ray.direction);
+        let i = above.intersects(&ray, 0.0, 100.0).unwrap();
+        let inside = around.media_at(&ray);
+
+        let behind = super::sample_lights(&above, &[], &ray, &i);
+        let within = super::sample_lights(&around, &inside, &ray, &i);
+
+        // One unit of the way up to the light is inside of the fog, or one and a half when the
+        // floor is inside of it too.
+        assert_close_within(behind, Color::white() * (-1.0f64).exp(), 1e-6);
+        assert_close_within(within, Color::white() * (-1.5f64).exp(), 1e-6);
+    }
+
+    #[test]
+    fn spot_light_should_only_light_within_its_cone() {
+        let spot = Light::spot(
+            Vec3::new(3.0, 2.0, 0.0),
+            Vec3::new(3.0, 0.0, 0.0),
+            Color::white(),
+            10.0,
+            20.0,
+        );
+        let (_, _, inside) = spot.illuminate(Vec3::new(3.0, 0.0, 0.0));
+        let (_, _, edge) =
+            spot.illuminate(Vec3::new(3.0 + 2.0 * 15f64.to_radians().tan(), 0.0, 0.0));
+        let (_, _, outside) = spot.illuminate(Vec3::new(4.0, 0.0, 0.0));
+
+        assert_close_within(inside, Color::white() / 4.0, 1e-6);
+        assert_that!(edge.r > 0.0 && edge.r < inside.r, is(true));
+        assert_that!(outside, is(equal_to(Color::black())));
+    }
+
+    #[test]
+    fn directional_light_should_be_blocked_by_shapes_in_its_way() {
+        let sun = Light::directional(Vec3::new(0.0, -1.0, -1.0), Color::new(PI, PI, PI));
+        let (scene, ray) = lit_floor(vec![sun], vec![]);
+        let blocker = Sphere::new(Vec3::new(0.0, 3.0, 3.0), 1.0, Color::black());
+        let (blocked_scene, _) = lit_floor(vec![sun], vec![Box::new(blocker)]);
+
+        let lit = ::trace_ray_in_media(&ray, &scene, &[], 0, None);
+        let shadowed = ::trace_ray_in_media(&ray, &blocked_scene, &[], 0, None);
+
+        assert_close_within(lit, Color::white() * 0.5f64.sqrt(), 1e-6);
+        assert_that!(shadowed, is(equal_to(Color::black())));
+    }
+
+    #[test]
+    fn missed_rays_should_get_the_background_color() {
+        let background = Color::new(0.1, 0.2, 0.3);
+        let scene = Scene::new(vec![]).with_background(background);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        assert_that!(
+            ::trace_ray_in_media(&ray, &scene, &[], 0, None),
+            is(equal_to(background))
+        );
+    }
+}
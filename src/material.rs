@@ -0,0 +1,325 @@
+use microfacet;
+use prelude::*;
+use scatter;
+use scene::Intersection;
+use texture::Texture;
+
+/// What a surface is made of, which decides how light scatters off of it. Shapes hand their
+/// material on to their intersections, so that every material works on every shape.
+#[derive(Clone)]
+pub enum Material {
+    /// A matte surface, scattering light evenly in every direction.
+    Lambertian(Color),
+    /// A metal of the color `albedo`, which is a mirror without `fuzz`, and is blurred more
+    /// the larger `fuzz` is.
+    Metal { albedo: Color, fuzz: f64 },
+    /// A metal with the complex index of refraction `eta + ik` for red, green and blue, such as
+    /// the ones measured for `gold`, `copper`, `aluminium` and `silver`. The roughness along
+    /// the tangent and the bitangent may differ, which stretches the highlights.
+    Conductor {
+        eta: Color,
+        k: Color,
+        roughness: (f64, f64),
+    },
+    /// Glass, water and other clear surfaces, which both reflect and refract light.
+    Dielectric { refraction_index: f64 },
+    /// A surface giving off light of its color, without scattering any.
+    Emissive(Color),
+    /// A matte surface colored by a texture at the uv coordinates of the intersection.
+    Textured(Texture),
+}
+
+impl Material {
+    pub fn gold(roughness: f64) -> Material {
+        Material::conductor(
+            Color::new(0.143119, 0.374957, 1.44248),
+            Color::new(3.98316, 2.38572, 1.60322),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Material {
+        Material::conductor(
+            Color::new(0.200438, 0.924033, 1.10221),
+            Color::new(3.91295, 2.45285, 2.14219),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Material {
+        Material::conductor(
+            Color::new(1.65746, 0.880369, 0.521229),
+            Color::new(9.22387, 6.26952, 4.837),
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: f64) -> Material {
+        Material::conductor(
+            Color::new(0.155265, 0.116723, 0.138342),
+            Color::new(4.82835, 3.12225, 2.14696),
+            roughness,
+        )
+    }
+
+    fn conductor(eta: Color, k: Color, roughness: f64) -> Material {
+        Material::Conductor {
+            eta: eta,
+            k: k,
+            roughness: (roughness, roughness),
+        }
+    }
+
+    /// The same conductor with a roughness along the tangent and another along the bitangent.
+    /// Metal is blurred by the average of the two, as it is as rough in every direction, and
+    /// other materials are left as they are.
+    pub fn with_roughness(self, tangent: f64, bitangent: f64) -> Material {
+        match self {
+            Material::Conductor { eta, k, .. } => Material::Conductor {
+                eta: eta,
+                k: k,
+                roughness: (tangent, bitangent),
+            },
+            Material::Metal { albedo, .. } => Material::Metal {
+                albedo: albedo,
+                fuzz: (tangent + bitangent) / 2.0,
+            },
+            _ => self,
+        }
+    }
+
+    pub fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Color, Ray)> {
+        match *self {
+            Material::Lambertian(albedo) => scatter::diffusive(albedo, intersection),
+            Material::Metal { albedo, fuzz } => {
+                scatter::reflection(albedo, fuzz, ray, intersection)
+            }
+            Material::Conductor { eta, k, roughness } => {
+                scatter::conductor(eta, k, roughness, ray, intersection)
+            }
+            Material::Dielectric { refraction_index } => {
+                scatter::refraction(refraction_index, ray, intersection)
+            }
+            Material::Emissive(_) => None,
+            Material::Textured(ref texture) => {
+                scatter::diffusive(texture.color_at(intersection.uv), intersection)
+            }
+        }
+    }
+
+    /// The density of `scatter` sending the ray in `direction`, see `scatter`.
+    pub fn pdf(&self, ray: &Ray, intersection: &Intersection, direction: Vec3) -> f64 {
+        match *self {
+            Material::Lambertian(_) | Material::Textured(_) => {
+                scatter::diffusive_pdf(intersection, direction)
+            }
+            Material::Metal { fuzz, .. } => {
+                scatter::reflection_pdf(fuzz, ray, intersection, direction)
+            }
+            Material::Conductor { roughness, .. } => {
+                scatter::conductor_pdf(roughness, ray, intersection, direction)
+            }
+            Material::Dielectric { refraction_index } => {
+                scatter::refraction_pdf(refraction_index, ray, intersection, direction)
+            }
+            Material::Emissive(_) => 0.0,
+        }
+    }
+
+    /// The color scattered in `direction` times its cosine to the normal, see `scatter`.
+    pub fn eval(&self, ray: &Ray, intersection: &Intersection, direction: Vec3) -> Color {
+        match *self {
+            Material::Lambertian(albedo) => {
+                scatter::diffusive_eval(albedo, intersection, direction)
+            }
+            Material::Metal { albedo, fuzz } => {
+                scatter::reflection_eval(albedo, fuzz, ray, intersection, direction)
+            }
+            Material::Conductor { eta, k, roughness } => {
+                scatter::conductor_eval(eta, k, roughness, ray, intersection, direction)
+            }
+            Material::Dielectric { refraction_index } => {
+                scatter::refraction_eval(refraction_index, ray, intersection, direction)
+            }
+            Material::Emissive(_) => Color::black(),
+            Material::Textured(ref texture) => {
+                scatter::diffusive_eval(texture.color_at(intersection.uv), intersection, direction)
+            }
+        }
+    }
+
+    /// The light given off by the surface at the intersection.
+    pub fn emitted(&self, intersection: &Intersection) -> Color {
+        match *self {
+            Material::Emissive(color) => color,
+            _ => Color::black(),
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        matches!(*self, Material::Emissive(_))
+    }
+
+    /// The color of the material, which is white for clear and textured materials. For
+    /// conductors it is the color they reflect when looked straight at.
+    pub fn albedo(&self) -> Color {
+        match *self {
+            Material::Lambertian(color)
+            | Material::Metal { albedo: color, .. }
+            | Material::Emissive(color) => color,
+            Material::Conductor { eta, k, .. } => microfacet::conductor_fresnel(eta, k, 1.0),
+            Material::Dielectric { .. } | Material::Textured(_) => Color::white(),
+        }
+    }
+
+    /// The same material in another color. Textured materials become matte in the color,
+    /// conductors get the index of refraction of a metal that reflects the color when looked
+    /// straight at, and clear materials stay clear.
+    pub fn with_color(self, color: Color) -> Material {
+        match self {
+            Material::Lambertian(_) | Material::Textured(_) => Material::Lambertian(color),
+            Material::Metal { fuzz, .. } => Material::Metal {
+                albedo: color,
+                fuzz: fuzz,
+            },
+            Material::Conductor { roughness, .. } => {
+                let (eta, k) = microfacet::conductor_of_color(color);
+                Material::Conductor {
+                    eta: eta,
+                    k: k,
+                    roughness: roughness,
+                }
+            }
+            Material::Emissive(_) => Material::Emissive(color),
+            Material::Dielectric { .. } => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+
+    fn down() -> Ray {
+        Ray::new(Vec3::new(0.25, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0))
+    }
+
+    fn floor(material: Material) -> Rect {
+        Rect::xz((0.0, 2.0), (0.0, 1.0), 0.0, material)
+    }
+
+    #[test]
+    fn intersections_should_carry_the_material_of_the_shape() {
+        let color = Color::new(0.2, 0.4, 0.6);
+        let rect = floor(Material::Lambertian(color));
+
+        let i = rect.intersects(&down(), 0.0, 100.0).unwrap();
+        let (attenuation, scattered) = i.shape.scatter(&down(), &i).unwrap();
+
+        assert_that!(i.material.unwrap().albedo(), is(equal_to(color)));
+        assert_that!(attenuation, is(equal_to(color)));
+        assert_that!(scattered.direction.y > 0.0, is(true));
+    }
+
+    #[test]
+    fn metal_without_fuzz_should_mirror_the_ray() {
+        let metal = Material::Metal {
+            albedo: Color::white(),
+            fuzz: 0.0,
+        };
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.5), Vec3::new(1.0, -1.0, 0.0));
+
+        let i = floor(metal).intersects(&ray, 0.0, 100.0).unwrap();
+        let (_, reflected) = i.shape.scatter(&ray, &i).unwrap();
+
+        assert_that!(
+            reflected.direction,
+            is(equal_to(Vec3::new(1.0, 1.0, 0.0).normalize()))
+        );
+    }
+
+    #[test]
+    fn conductor_presets_should_reflect_the_color_of_the_metal() {
+        let (gold, silver) = (Material::gold(0.2).albedo(), Material::silver(0.2).albedo());
+        let (copper, aluminium) = (Material::copper(0.2), Material::aluminium(0.2));
+
+        assert_that!(gold.r > gold.g && gold.g > gold.b, is(true));
+        assert_that!(copper.albedo().r > copper.albedo().b, is(true));
+        assert_that!(silver.r > 0.9 && silver.b > 0.9, is(true));
+        assert_that!(aluminium.albedo().b > 0.85, is(true));
+    }
+
+    #[test]
+    fn recolored_conductor_should_stay_a_conductor_of_that_color() {
+        let color = Color::new(0.9, 0.5, 0.2);
+
+        let recolored = Material::silver(0.2).with_color(color);
+        let metal = Material::Metal {
+            albedo: color,
+            fuzz: 0.0,
+        };
+
+        assert_that!(matches!(recolored, Material::Conductor { .. }), is(true));
+        let albedo = recolored.albedo();
+        assert_that!((albedo.r - 0.9).abs() < 1e-9, is(true));
+        assert_that!((albedo.b - 0.2).abs() < 1e-9, is(true));
+        assert_that!(
+            matches!(metal.with_roughness(0.3, 0.1), Material::Metal { fuzz, .. } if fuzz == 0.2),
+            is(true)
+        );
+    }
+
+    #[test]
+    fn anisotropic_conductor_should_stretch_reflections_along_the_tangent() {
+        // The tangent of the floor is along x.
+        let rough = floor(Material::gold(0.3).with_roughness(0.5, 0.02));
+        let i = rough.intersects(&down(), 0.0, 100.0).unwrap();
+
+        let (mut along, mut across) = (0.0, 0.0);
+        for _ in 0..1000 {
+            if let Some((color, scattered)) = i.shape.scatter(&down(), &i) {
+                assert_that!(color.b < color.r, is(true));
+                assert_that!(scattered.direction.y > 0.0, is(true));
+                along += scattered.direction.x.abs();
+                across += scattered.direction.z.abs();
+            }
+        }
+
+        assert_that!(along > 5.0 * across, is(true));
+    }
+
+    #[test]
+    fn emissive_material_should_give_off_light_without_scattering() {
+        let light = Color::new(4.0, 4.0, 4.0);
+        let scene = Scene::new(vec![Box::new(floor(Material::Emissive(light)))]);
+
+        let i = scene.intersects(&down(), 0.0, 100.0).unwrap();
+
+        assert_that!(i.shape.scatter(&down(), &i).is_none(), is(true));
+        assert_that!(
+            ::trace_ray_in_media(&down(), &scene, &[], 0, None),
+            is(equal_to(light))
+        );
+    }
+
+    #[test]
+    fn textured_material_should_be_colored_by_the_uv_of_the_hit() {
+        let (red, blue) = (Color::new(1.0, 0.0, 0.0), Color::new(0.0, 0.0, 1.0));
+        let texture = Texture::new(vec![red, blue], 2, 1);
+        let rect = floor(Material::Textured(texture));
+        let right = Ray::new(Vec3::new(1.75, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+
+        let left = rect.intersects(&down(), 0.0, 100.0).unwrap();
+        let right = rect.intersects(&right, 0.0, 100.0).unwrap();
+
+        assert_that!(
+            left.shape.scatter(&down(), &left).unwrap().0,
+            is(equal_to(red))
+        );
+        assert_that!(
+            right.shape.scatter(&down(), &right).unwrap().0,
+            is(equal_to(blue))
+        );
+    }
+}
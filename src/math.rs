@@ -0,0 +1,169 @@
+use std::f64::consts::PI;
+
+/// The real roots of `a x^2 + b x + c`, in increasing order. The roots are computed so that
+/// the subtraction of nearly equal numbers is avoided, which keeps the small root accurate
+/// when the other one is large.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return if b == 0.0 { vec![] } else { vec![-c / b] };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return vec![];
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        return vec![0.0, 0.0];
+    }
+    let (x0, x1) = (q / a, c / q);
+    vec![x0.min(x1), x0.max(x1)]
+}
+
+/// The real roots of `a x^3 + b x^2 + c x + d`, in increasing order.
+pub fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return solve_quadratic(b, c, d);
+    }
+    let (b, c, d) = (b / a, c / a, d / a);
+    let q = (b * b - 3.0 * c) / 9.0;
+    let r = (2.0 * b * b * b - 9.0 * b * c + 27.0 * d) / 54.0;
+    let shift = b / 3.0;
+
+    let roots = if r * r < q * q * q {
+        // Three real roots, found with the trigonometric method. Rounding may take the cosine
+        // just past one when two of the roots are nearly equal.
+        let theta = (r / (q * q * q).sqrt()).clamp(-1.0, 1.0).acos();
+        let scale = -2.0 * q.sqrt();
+        vec![
+            scale * (theta / 3.0).cos() - shift,
+            scale * ((theta + 2.0 * PI) / 3.0).cos() - shift,
+            scale * ((theta - 2.0 * PI) / 3.0).cos() - shift,
+        ]
+    } else {
+        let s = -r.signum() * (r.abs() + (r * r - q * q * q).sqrt()).cbrt();
+        let t = if s == 0.0 { 0.0 } else { q / s };
+        vec![s + t - shift]
+    };
+    sorted_finite(roots)
+}
+
+/// The real roots of `a x^4 + b x^3 + c x^2 + d x + e`, in increasing order. The quartic is
+/// split into two quadratics with Ferrari's method, and each root is then polished with a few
+/// Newton iterations on the original polynomial.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return solve_cubic(b, c, d, e);
+    }
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+
+    // Substituting x = y - b / 4 gives the depressed quartic y^4 + p y^2 + q y + r.
+    let shift = b / 4.0;
+    let p = c - 6.0 * shift * shift;
+    let q = d - 2.0 * c * shift + 8.0 * shift * shift * shift;
+    let r = e - d * shift + c * shift * shift - 3.0 * shift * shift * shift * shift;
+
+    let mut roots = Vec::new();
+    if q.abs() < 1e-12 {
+        // A quadratic in y^2.
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                let y = z.sqrt();
+                roots.push(y);
+                roots.push(-y);
+            }
+        }
+    } else {
+        // Any positive root m of the resolvent cubic lets the quartic be written as
+        // (y^2 + p / 2 + m)^2 - 2 m (y - q / (4 m))^2.
+        let m = solve_cubic(1.0, p, p * p / 4.0 - r, -q * q / 8.0)
+            .into_iter()
+            .fold(0.0, f64::max);
+        if m <= 0.0 {
+            return vec![];
+        }
+        let s = (2.0 * m).sqrt();
+        roots.extend(solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)));
+        roots.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)));
+    }
+
+    let polynomial = |x: f64| (((x + b) * x + c) * x + d) * x + e;
+    let derivative = |x: f64| ((4.0 * x + 3.0 * b) * x + 2.0 * c) * x + d;
+    let roots: Vec<f64> = roots
+        .into_iter()
+        .map(|y| {
+            let mut x = y - shift;
+            for _ in 0..3 {
+                let next = x - polynomial(x) / derivative(x);
+                if !next.is_finite() {
+                    break;
+                }
+                x = next;
+            }
+            x
+        })
+        .collect();
+    sorted_finite(roots)
+}
+
+/// The roots in increasing order, without any that came out as NaN or infinite.
+fn sorted_finite(mut roots: Vec<f64>) -> Vec<f64> {
+    roots.retain(|root| root.is_finite());
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use math::*;
+
+    fn assert_roots(roots: Vec<f64>, expected: Vec<f64>) {
+        assert_that!(roots.len(), is(equal_to(expected.len())));
+        for (root, expected) in roots.iter().zip(expected.iter()) {
+            assert_that!(
+                (root - expected).abs() < 1e-9 * expected.abs().max(1.0),
+                is(true)
+            );
+        }
+    }
+
+    #[test]
+    fn quadratic_should_keep_the_small_root_accurate() {
+        assert_roots(solve_quadratic(1.0, -1e8, 1.0), vec![1e-8, 1e8]);
+        assert_roots(solve_quadratic(1.0, 0.0, 1.0), vec![]);
+    }
+
+    #[test]
+    fn cubic_should_find_all_real_roots() {
+        assert_roots(solve_cubic(1.0, -6.0, 11.0, -6.0), vec![1.0, 2.0, 3.0]);
+        assert_roots(solve_cubic(2.0, 0.0, 2.0, -4.0), vec![1.0]);
+    }
+
+    #[test]
+    fn quartic_should_find_all_real_roots() {
+        assert_roots(
+            solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0),
+            vec![1.0, 2.0, 3.0, 4.0],
+        );
+        assert_roots(
+            solve_quartic(1.0, 0.0, -5.0, 0.0, 4.0),
+            vec![-2.0, -1.0, 1.0, 2.0],
+        );
+        assert_roots(solve_quartic(1.0, 0.0, 1.0, 0.0, 1.0), vec![]);
+    }
+
+    #[test]
+    fn roots_that_are_not_finite_should_be_dropped() {
+        assert_roots(solve_cubic(1.0, f64::NAN, 0.0, 0.0), vec![]);
+        assert_roots(solve_quartic(1.0, 0.0, f64::NAN, 1.0, 0.0), vec![]);
+    }
+
+    #[test]
+    fn quartic_should_find_roots_that_are_far_apart() {
+        // (x - 0.001)(x - 1)(x - 10)(x - 1000)
+        assert_roots(
+            solve_quartic(1.0, -1011.001, 11011.011, -10011.01, 10.0),
+            vec![0.001, 1.0, 10.0, 1000.0],
+        );
+    }
+}
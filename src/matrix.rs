@@ -1,4 +1,7 @@
 use std::f64::consts::PI;
+use std::ops::Mul;
+
+use vec::Vec3;
 
 #[derive(Clone, Copy)]
 pub enum Axis {
@@ -7,16 +10,17 @@ pub enum Axis {
     Z,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Matrix4 {
     pub m: [[f64; 4]; 4],
 }
 
 impl Matrix4 {
-    fn new(m: [[f64; 4]; 4]) -> Matrix4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Matrix4 {
         Matrix4 { m: m }
     }
 
-    fn create_identity() -> Matrix4 {
+    pub fn create_identity() -> Matrix4 {
         Matrix4::new([
             [1.0, 0.0, 0.0, 0.0],
             [0.0, 1.0, 0.0, 0.0],
@@ -53,6 +57,116 @@ impl Matrix4 {
 
         return matrix;
     }
+
+    pub fn translation(offset: Vec3) -> Matrix4 {
+        let mut matrix = Matrix4::create_identity();
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        matrix
+    }
+
+    pub fn scaling(factors: Vec3) -> Matrix4 {
+        let mut matrix = Matrix4::create_identity();
+        matrix.m[0][0] = factors.x;
+        matrix.m[1][1] = factors.y;
+        matrix.m[2][2] = factors.z;
+        matrix
+    }
+
+    /// Blends every element linearly from this matrix at `t = 0` to `other` at `t = 1`.
+    pub fn interpolate(&self, other: &Matrix4, t: f64) -> Matrix4 {
+        let mut matrix = *self;
+        for row in 0..4 {
+            for column in 0..4 {
+                matrix.m[row][column] += (other.m[row][column] - self.m[row][column]) * t;
+            }
+        }
+        matrix
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut matrix = Matrix4::create_identity();
+        for row in 0..4 {
+            for column in 0..4 {
+                matrix.m[row][column] = self.m[column][row];
+            }
+        }
+        matrix
+    }
+
+    /// Inverts the matrix by Gauss-Jordan elimination, returning `None` if it is singular or
+    /// contains NaN.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut m = self.m;
+        let mut inverse = Matrix4::create_identity().m;
+
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&a, &b| m[a][column].abs().total_cmp(&m[b][column].abs()))
+                .unwrap();
+            if m[pivot][column].abs() < 1e-12 || m[pivot][column].is_nan() {
+                return None;
+            }
+            m.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1.0 / m[column][column];
+            for k in 0..4 {
+                m[column][k] *= scale;
+                inverse[column][k] *= scale;
+            }
+            for row in 0..4 {
+                if row != column {
+                    let factor = m[row][column];
+                    for k in 0..4 {
+                        m[row][k] -= factor * m[column][k];
+                        inverse[row][k] -= factor * inverse[column][k];
+                    }
+                }
+            }
+        }
+        Some(Matrix4::new(inverse))
+    }
+
+    /// The inverse transpose, which transforms normals so that they stay perpendicular to the
+    /// surface, even when the matrix scales non-uniformly or shears.
+    pub fn normal_matrix(&self) -> Option<Matrix4> {
+        self.inverse().map(|inverse| inverse.transpose())
+    }
+
+    /// The determinant of the part that transforms directions, which is how much the matrix
+    /// scales volumes.
+    pub fn determinant(&self) -> f64 {
+        let m = self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Transforms a direction, which unlike a point is not affected by translation.
+    pub fn transform_vector(&self, vec: Vec3) -> Vec3 {
+        let m = self.m;
+        Vec3::new(
+            vec.x * m[0][0] + vec.y * m[0][1] + vec.z * m[0][2],
+            vec.x * m[1][0] + vec.y * m[1][1] + vec.z * m[1][2],
+            vec.x * m[2][0] + vec.y * m[2][1] + vec.z * m[2][2],
+        )
+    }
+}
+
+impl Mul<Matrix4> for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut matrix = Matrix4::new([[0.0; 4]; 4]);
+        for row in 0..4 {
+            for column in 0..4 {
+                matrix.m[row][column] = (0..4).map(|k| self.m[row][k] * other.m[k][column]).sum();
+            }
+        }
+        matrix
+    }
 }
 
 #[cfg(test)]
@@ -61,8 +175,10 @@ mod tests {
     use matrix::Axis::{X, Y, Z};
     use matrix::Matrix4;
     use std::f64::consts::PI;
+    use tests::assert_close;
+    use vec::Vec3;
 
-    // #[test]
+    #[test]
     fn identity_matrix_can_be_created() {
         let m = Matrix4::create_identity();
 
@@ -72,7 +188,7 @@ mod tests {
         assert_that!(m.m[3][3], is(equal_to(1.0)));
     }
 
-    // #[test]
+    #[test]
     fn should_create_rotation_matrix_for_x_axis() {
         let m = Matrix4::rot(X, PI);
 
@@ -82,7 +198,7 @@ mod tests {
         assert_that!(m.m[2][1], is(equal_to(PI.sin())));
     }
 
-    // #[test]
+    #[test]
     fn should_create_rotation_matrix_for_y_axis() {
         let m = Matrix4::rot(Y, PI);
 
@@ -92,7 +208,7 @@ mod tests {
         assert_that!(m.m[2][0], is(equal_to(PI.sin())));
     }
 
-    // #[test]
+    #[test]
     fn should_create_rotation_matrix_for_z_axis() {
         let m = Matrix4::rot(Z, PI);
 
@@ -101,4 +217,96 @@ mod tests {
         assert_that!(m.m[1][1], is(equal_to(PI.cos())));
         assert_that!(m.m[1][0], is(equal_to(PI.sin())));
     }
+
+    #[test]
+    fn translation_should_move_points_but_not_vectors() {
+        let m = Matrix4::translation(Vec3::new(1.0, 2.0, 3.0));
+
+        assert_that!(
+            m * Vec3::new(1.0, 1.0, 1.0),
+            is(equal_to(Vec3::new(2.0, 3.0, 4.0)))
+        );
+        assert_that!(
+            m.transform_vector(Vec3::new(1.0, 1.0, 1.0)),
+            is(equal_to(Vec3::new(1.0, 1.0, 1.0)))
+        );
+    }
+
+    #[test]
+    fn matrix_multiplied_with_its_inverse_should_be_identity() {
+        let m = Matrix4::translation(Vec3::new(1.0, -2.0, 3.0)) * Matrix4::rot(Y, 0.7);
+
+        assert_close(m * m.inverse().unwrap(), Matrix4::create_identity());
+        assert_close(m.inverse().unwrap() * m, Matrix4::create_identity());
+    }
+
+    #[test]
+    fn scaling_should_scale_each_axis() {
+        let m = Matrix4::scaling(Vec3::new(2.0, 3.0, 4.0));
+
+        assert_that!(
+            m * Vec3::new(1.0, 1.0, 1.0),
+            is(equal_to(Vec3::new(2.0, 3.0, 4.0)))
+        );
+    }
+
+    #[test]
+    fn matrices_should_be_composed_right_to_left() {
+        let scale_then_translate = Matrix4::translation(Vec3::new(1.0, 0.0, 0.0))
+            * Matrix4::scaling(Vec3::new(2.0, 2.0, 2.0));
+
+        assert_that!(
+            scale_then_translate * Vec3::new(1.0, 1.0, 1.0),
+            is(equal_to(Vec3::new(3.0, 2.0, 2.0)))
+        );
+    }
+
+    #[test]
+    fn transpose_should_swap_rows_and_columns() {
+        let m = Matrix4::translation(Vec3::new(1.0, 2.0, 3.0)).transpose();
+
+        assert_that!(m.m[3][0], is(equal_to(1.0)));
+        assert_that!(m.m[3][1], is(equal_to(2.0)));
+        assert_that!(m.m[3][2], is(equal_to(3.0)));
+        assert_that!(m.m[0][3], is(equal_to(0.0)));
+    }
+
+    #[test]
+    fn normal_matrix_should_keep_normals_perpendicular_to_the_surface() {
+        let m = Matrix4::scaling(Vec3::new(1.0, 4.0, 1.0));
+        let tangent = Vec3::new(1.0, 1.0, 0.0);
+        let normal = Vec3::new(1.0, -1.0, 0.0);
+
+        let transformed_tangent = m.transform_vector(tangent);
+        let transformed_normal = m.normal_matrix().unwrap().transform_vector(normal);
+
+        assert_that!(
+            transformed_tangent.dot(transformed_normal),
+            is(equal_to(0.0))
+        );
+    }
+
+    #[test]
+    fn singular_matrix_should_not_have_an_inverse() {
+        let m = Matrix4::new([[0.0; 4]; 4]);
+
+        assert_that!(m.inverse().is_none(), is(true));
+    }
+
+    #[test]
+    fn determinant_should_be_how_much_volumes_are_scaled() {
+        let m = Matrix4::translation(Vec3::new(5.0, 6.0, 7.0))
+            * Matrix4::rot(Z, 0.5)
+            * Matrix4::scaling(Vec3::new(2.0, 3.0, 4.0));
+
+        assert_that!((m.determinant() - 24.0).abs() < 1e-9, is(true));
+    }
+
+    #[test]
+    fn matrix_with_nan_should_not_have_an_inverse() {
+        let mut m = Matrix4::create_identity();
+        m.m[1][2] = f64::NAN;
+
+        assert_that!(m.inverse().is_none(), is(true));
+    }
 }
@@ -0,0 +1,265 @@
+use rand::{self, Rng};
+use std::f64::consts::PI;
+use std::ptr;
+use std::rc::Rc;
+
+use prelude::*;
+use quadric::Frame;
+use scatter;
+use scene::Intersection;
+
+/// Something that rays travel through and may scatter inside of, instead of only at surfaces.
+pub trait Medium {
+    /// Samples how far along the ray it gets before interacting with the medium, or `None` if
+    /// it gets through to `t_max` without doing so.
+    fn sample_distance(&self, ray: &Ray, t_max: f64) -> Option<f64>;
+
+    /// Scatters the ray at the point where it interacted with the medium.
+    fn scatter_at(&self, ray: &Ray, point: Vec3) -> Option<(Color, Ray)>;
+
+    /// The fraction of light that gets through the medium along the ray up to `t_max`.
+    fn transmittance(&self, ray: &Ray, t_max: f64) -> f64;
+
+    /// The light given off where the ray interacts with the medium at `point`.
+    fn emission(&self, ray: &Ray, point: Vec3) -> Color {
+        Color::black()
+    }
+
+    /// The shape enclosing the medium in its own space, which is shared by all copies of the
+    /// medium, also when they are seen through transforms.
+    fn boundary(&self) -> &dyn Intersectable;
+}
+
+/// Whether `a` and `b` are copies of the same medium, as the media of two intersections with
+/// its boundary are.
+fn is_same(a: &dyn Medium, b: &dyn Medium) -> bool {
+    ptr::eq(
+        a.boundary() as *const dyn Intersectable as *const u8,
+        b.boundary() as *const dyn Intersectable as *const u8,
+    )
+}
+
+/// The media that a ray is inside of after it hits the boundary of `medium` at the
+/// intersection. Going against the normal takes the ray into the medium, and going along it
+/// takes the ray out of it, wherever it is in the list.
+pub fn cross_boundary<'a>(
+    media: &[&'a dyn Medium],
+    medium: &'a dyn Medium,
+    ray: &Ray,
+    intersection: &Intersection,
+) -> Vec<&'a dyn Medium> {
+    let mut media = media.to_vec();
+    if ray.direction.dot(intersection.normal) < 0.0 {
+        media.push(medium);
+    } else if let Some(index) = media.iter().rposition(|&inside| is_same(inside, medium)) {
+        media.remove(index);
+    }
+    media
+}
+
+/// Samples a direction from the Henyey-Greenstein phase function around `direction`. Light is
+/// mostly scattered forward when `anisotropy` is positive, backward when it is negative, and
+/// evenly in every direction when it is zero.
+pub fn henyey_greenstein(direction: Vec3, anisotropy: f64) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let g = anisotropy;
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * rng.next_f64()
+    } else {
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * rng.next_f64());
+        (1.0 + g * g - s * s) / (2.0 * g)
+    };
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.next_f64();
+    let frame = Frame::new(Vec3::new(0.0, 0.0, 0.0), direction);
+    frame.world(Vec3::new(
+        sin_theta * phi.cos(),
+        cos_theta,
+        sin_theta * phi.sin(),
+    ))
+}
+
+/// Fog with the same density everywhere inside of a closed boundary shape. Rays that hit the
+/// boundary pass through it, and the rendering scatters them inside of the volume on the way.
+#[derive(Clone)]
+pub struct ConstantMedium {
+    pub density: f64,
+    pub albedo: Color,
+    pub anisotropy: f64,
+    boundary: Rc<dyn Intersectable>,
+}
+
+impl ConstantMedium {
+    pub fn new<T>(boundary: T, density: f64, albedo: Color) -> ConstantMedium
+    where
+        T: Intersectable + 'static,
+    {
+        ConstantMedium {
+            density: density,
+            albedo: albedo,
+            anisotropy: 0.0,
+            boundary: Rc::new(boundary),
+        }
+    }
+
+    /// Sets how the medium scatters light, see `henyey_greenstein`.
+    pub fn with_anisotropy(self, anisotropy: f64) -> ConstantMedium {
+        ConstantMedium {
+            anisotropy: anisotropy,
+            ..self
+        }
+    }
+}
+
+impl Medium for ConstantMedium {
+    fn sample_distance(&self, ray: &Ray, t_max: f64) -> Option<f64> {
+        let mut rng = rand::thread_rng();
+        let distance = -(1.0 - rng.next_f64()).ln() / (self.density * ray.direction.length());
+        if distance < t_max {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    fn scatter_at(&self, ray: &Ray, point: Vec3) -> Option<(Color, Ray)> {
+        let direction = henyey_greenstein(ray.direction.normalize(), self.anisotropy);
+        Some((self.albedo, Ray::new(point, direction)))
+    }
+
+    fn transmittance(&self, ray: &Ray, t_max: f64) -> f64 {
+        (-self.density * ray.direction.length() * t_max).exp()
+    }
+
+    fn boundary(&self) -> &dyn Intersectable {
+        &*self.boundary
+    }
+}
+
+impl Intersectable for ConstantMedium {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.boundary
+            .intersects(ray, t_min, t_max)
+            .map(|intersection| Intersection {
+                material: None,
+                shape: Box::new(self.clone()),
+                ..intersection
+            })
+    }
+
+    fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Color, Ray)> {
+        scatter::transmission(ray, intersection)
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        Box::new(ConstantMedium {
+            boundary: Rc::from(self.boundary.move_to(vec)),
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+
+    fn medium(&self) -> Option<&dyn Medium> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use medium::*;
+    use prelude::*;
+
+    fn fog(density: f64) -> ConstantMedium {
+        let boundary = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Color::white());
+        ConstantMedium::new(boundary, density, Color::new(0.5, 0.5, 0.5))
+    }
+
+    #[test]
+    fn rays_should_pass_straight_through_the_boundary() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let i = fog(1.0).intersects(&ray, 0.0, 100.0).unwrap();
+        let (color, passed) = i.shape.scatter(&ray, &i).unwrap();
+
+        assert_that!(i.distance, is(equal_to(4.0)));
+        assert_that!(i.shape.medium().is_some(), is(true));
+        assert_that!(color, is(equal_to(Color::white())));
+        assert_that!(passed.direction, is(equal_to(ray.direction)));
+        assert_that!(passed.origin.z < 1.0, is(true));
+    }
+
+    #[test]
+    fn leaving_a_medium_should_remove_it_and_not_the_innermost_one() {
+        let (outer, inner) = (fog(1.0), fog(2.0));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let exit = outer.intersects(&ray, 0.0, 100.0).unwrap();
+        let medium = exit.shape.medium().unwrap();
+
+        let media = cross_boundary(&[&outer, &inner], medium, &ray, &exit);
+
+        assert_that!(media.len(), is(equal_to(1)));
+        assert_that!(
+            media[0].transmittance(&ray, 1.0),
+            is(equal_to((-2.0f64).exp()))
+        );
+    }
+
+    #[test]
+    fn camera_inside_of_fog_should_start_out_in_it() {
+        let black_fog = ConstantMedium::new(
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Color::white()),
+            100.0,
+            Color::black(),
+        );
+        let scene = Scene::new(vec![Box::new(black_fog)]).with_background(Color::white());
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let outside = Ray::new(Vec3::new(0.0, 1.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_that!(scene.media_at(&ray).len(), is(equal_to(1)));
+        assert_that!(scene.media_at(&outside).len(), is(equal_to(0)));
+        // Getting through the fog without interacting has a chance of exp(-100).
+        assert_that!(
+            ::trace_camera_ray(&ray, &scene, 0),
+            is(equal_to(Color::black()))
+        );
+    }
+
+    #[test]
+    fn free_flight_distances_should_follow_the_density() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0));
+        let samples = 10000;
+
+        let inside = (0..samples)
+            .filter(|_| fog(0.5).sample_distance(&ray, 1.0).is_some())
+            .count();
+
+        // The chance of interacting within 2 units is 1 - exp(-0.5 * 2). The fraction has a
+        // standard deviation below 0.005 with this many samples, so 0.03 is six of them.
+        let expected = 1.0 - (-1.0f64).exp();
+        let fraction = inside as f64 / samples as f64;
+        assert_that!((fraction - expected).abs() < 0.03, is(true));
+    }
+
+    #[test]
+    fn henyey_greenstein_should_scatter_forward_for_positive_anisotropy() {
+        let direction = Vec3::new(1.0, 0.0, 0.0);
+        let samples = 10000;
+        let mean = |g: f64| {
+            (0..samples)
+                .map(|_| henyey_greenstein(direction, g).dot(direction))
+                .sum::<f64>()
+                / samples as f64
+        };
+
+        // The average cosine of the scattering angle is the anisotropy. Its standard deviation
+        // is below 0.006 for these anisotropies with this many samples, so 0.03 is five of them.
+        assert_that!((mean(0.7) - 0.7).abs() < 0.03, is(true));
+        assert_that!(mean(0.0).abs() < 0.03, is(true));
+        assert_that!((mean(-0.5) + 0.5).abs() < 0.03, is(true));
+    }
+}
@@ -0,0 +1,456 @@
+use rand::{self, Rng};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use bvh::Bvh;
+use displacement::{self, Displacement, Tessellation};
+use light;
+use obj;
+use ply;
+use prelude::*;
+use processing::{self, Buffers, Processing};
+use scene::Intersection;
+use stl;
+
+#[derive(Debug)]
+pub enum MeshError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Format(String),
+}
+
+impl MeshError {
+    pub fn parse<S: Into<String>>(line: usize, message: S) -> MeshError {
+        MeshError::Parse {
+            line: line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MeshError::Io(ref error) => write!(f, "could not read mesh: {}", error),
+            MeshError::Parse { line, ref message } => write!(f, "line {}: {}", line, message),
+            MeshError::Format(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for MeshError {}
+
+impl From<io::Error> for MeshError {
+    fn from(error: io::Error) -> MeshError {
+        MeshError::Io(error)
+    }
+}
+
+/// A triangle of a `Mesh`, given as indices into the buffers of the mesh.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Face {
+    pub vertices: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+    pub material: Option<usize>,
+}
+
+impl Face {
+    pub fn new(a: usize, b: usize, c: usize) -> Face {
+        Face {
+            vertices: [a, b, c],
+            normals: None,
+            uvs: None,
+            material: None,
+        }
+    }
+}
+
+/// A collection of triangles sharing their vertex, normal and uv buffers. Cloning a mesh only
+/// clones the handles to the buffers.
+#[derive(Clone)]
+pub struct Mesh {
+    vertices: Rc<Vec<Vec3>>,
+    normals: Rc<Vec<Vec3>>,
+    uvs: Rc<Vec<(f64, f64)>>,
+    colors: Rc<Vec<Color>>,
+    faces: Rc<Vec<Face>>,
+    materials: Rc<Vec<(String, Material)>>,
+    material: Material,
+    bvh: Rc<Bvh>,
+    /// The total area of the faces up to and including each face, for picking faces by
+    /// their area.
+    areas: Rc<Vec<f64>>,
+}
+
+impl Mesh {
+    pub fn new(vertices: Vec<Vec3>, faces: Vec<Face>, material: Material) -> Mesh {
+        Mesh::with_buffers(vertices, vec![], vec![], faces, vec![], material)
+    }
+
+    pub(crate) fn with_buffers(
+        vertices: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f64, f64)>,
+        faces: Vec<Face>,
+        materials: Vec<(String, Material)>,
+        material: Material,
+    ) -> Mesh {
+        let bvh = build_bvh(&vertices, &faces);
+        let areas = cumulative_areas(&vertices, &faces);
+        Mesh {
+            vertices: Rc::new(vertices),
+            normals: Rc::new(normals),
+            uvs: Rc::new(uvs),
+            colors: Rc::new(vec![]),
+            faces: Rc::new(faces),
+            materials: Rc::new(materials),
+            material: material,
+            bvh: Rc::new(bvh),
+            areas: Rc::new(areas),
+        }
+    }
+
+    /// Loads a Wavefront OBJ file, together with the material libraries it refers to.
+    pub fn from_obj<P: AsRef<Path>>(path: P) -> Result<Mesh, MeshError> {
+        obj::load(path.as_ref())
+    }
+
+    /// Loads an ASCII or binary little-endian PLY file. Vertex colors are used as the albedo
+    /// of the surface.
+    pub fn from_ply<P: AsRef<Path>>(path: P) -> Result<Mesh, MeshError> {
+        ply::load(path.as_ref())
+    }
+
+    /// Loads a binary STL file.
+    pub fn from_stl<P: AsRef<Path>>(path: P) -> Result<Mesh, MeshError> {
+        stl::load(path.as_ref())
+    }
+
+    /// Loads an OBJ, PLY or STL file, chosen by the extension of the path, and applies the
+    /// processing to it.
+    pub fn load<P: AsRef<Path>>(path: P, processing: Processing) -> Result<Mesh, MeshError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        let mesh = match extension.as_ref().map(|extension| &extension[..]) {
+            Some("obj") => Mesh::from_obj(path)?,
+            Some("ply") => Mesh::from_ply(path)?,
+            Some("stl") => Mesh::from_stl(path)?,
+            _ => {
+                return Err(MeshError::Format(format!(
+                    "unknown mesh format: {}",
+                    path.display()
+                )))
+            }
+        };
+        Ok(processing.apply(&mesh))
+    }
+
+    /// Replaces the material used by faces that have no material of their own.
+    pub fn with_material(self, material: Material) -> Mesh {
+        Mesh {
+            material: material,
+            ..self
+        }
+    }
+
+    /// Sets a color for each vertex, which is interpolated across the faces and replaces the
+    /// color of their surface. An empty list removes the vertex colors.
+    pub fn with_vertex_colors(self, colors: Vec<Color>) -> Mesh {
+        assert!(colors.is_empty() || colors.len() == self.vertices.len());
+        Mesh {
+            colors: Rc::new(colors),
+            ..self
+        }
+    }
+
+    pub fn vertices(&self) -> &[Vec3] {
+        &self.vertices
+    }
+
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    pub fn uvs(&self) -> &[(f64, f64)] {
+        &self.uvs
+    }
+
+    pub fn vertex_colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    pub fn faces(&self) -> &[Face] {
+        &self.faces
+    }
+
+    pub fn material(&self, name: &str) -> Option<Material> {
+        self.materials
+            .iter()
+            .find(|material| material.0 == name)
+            .map(|material| material.1.clone())
+    }
+
+    pub fn triangle(&self, face: &Face) -> Triangle {
+        let [a, b, c] = face.vertices;
+        let material = match face.material {
+            Some(material) => self.materials[material].1.clone(),
+            None => self.material.clone(),
+        };
+        let mut triangle = Triangle::new(
+            self.vertices[a],
+            self.vertices[b],
+            self.vertices[c],
+            material,
+        );
+        if let Some([a, b, c]) = face.normals {
+            triangle = triangle.with_normals(self.normals[a], self.normals[b], self.normals[c]);
+        }
+        if let Some([a, b, c]) = face.uvs {
+            triangle = triangle.with_uvs(self.uvs[a], self.uvs[b], self.uvs[c]);
+        }
+        if !self.colors.is_empty() {
+            triangle = triangle.with_colors(self.colors[a], self.colors[b], self.colors[c]);
+        }
+        triangle
+    }
+
+    pub fn translated(&self, offset: Vec3) -> Mesh {
+        let vertices: Vec<Vec3> = self
+            .vertices
+            .iter()
+            .map(|&vertex| vertex + offset)
+            .collect();
+        Mesh {
+            bvh: Rc::new(build_bvh(&vertices, &self.faces)),
+            vertices: Rc::new(vertices),
+            ..self.clone()
+        }
+    }
+
+    /// Tessellates the mesh finely enough to follow the displacement, and moves every vertex
+    /// along the smoothed normal of the mesh at its position by the height of the displacement
+    /// times `scale`. The normals of the faces are only used for shading, so that faces meeting
+    /// at an angle don't come apart. Vertex colors are not kept.
+    pub fn displaced(
+        &self,
+        displacement: &Displacement,
+        scale: f64,
+        tessellation: Tessellation,
+    ) -> Mesh {
+        let (vertices, normals, uvs, faces) =
+            displacement::displace(self, displacement, scale, tessellation);
+        self.with_geometry(vertices, normals, uvs, faces)
+    }
+
+    /// Joins vertices that are within `tolerance` of each other, such as the separate copies of
+    /// shared vertices in STL files, and drops the faces that collapse. Normals and uvs are
+    /// left as they are.
+    pub fn welded(&self, tolerance: f64) -> Mesh {
+        let (kept, joined) = processing::weld(&self.vertices, tolerance);
+        let faces = self
+            .faces
+            .iter()
+            .map(|face| Face {
+                vertices: [
+                    joined[face.vertices[0]],
+                    joined[face.vertices[1]],
+                    joined[face.vertices[2]],
+                ],
+                ..*face
+            })
+            .filter(|face| {
+                let [a, b, c] = face.vertices;
+                a != b && b != c && c != a
+            })
+            .collect();
+        let vertices = kept.iter().map(|&vertex| self.vertices[vertex]).collect();
+        let colors = if self.colors.is_empty() {
+            vec![]
+        } else {
+            kept.iter().map(|&vertex| self.colors[vertex]).collect()
+        };
+        self.with_geometry(
+            vertices,
+            (*self.normals).clone(),
+            (*self.uvs).clone(),
+            faces,
+        )
+        .with_vertex_colors(colors)
+    }
+
+    /// Applies `levels` of Loop subdivision, splitting every face into four at each level and
+    /// smoothing the surface. Edges with only one face are kept as smooth boundary curves. The
+    /// faces lose their normals, so this is usually followed by `with_smooth_normals`.
+    pub fn subdivided(&self, levels: u32) -> Mesh {
+        let mut buffers = Buffers {
+            vertices: (*self.vertices).clone(),
+            uvs: (*self.uvs).clone(),
+            colors: (*self.colors).clone(),
+            faces: (*self.faces).clone(),
+        };
+        for _ in 0..levels {
+            buffers = processing::loop_subdivide(&buffers);
+        }
+        self.with_geometry(buffers.vertices, vec![], buffers.uvs, buffers.faces)
+            .with_vertex_colors(buffers.colors)
+    }
+
+    /// Generates normals for the faces without any, weighting the faces around each vertex by
+    /// their angle there. Edges where the faces meet at more than `crease_angle` degrees stay
+    /// sharp.
+    pub fn with_smooth_normals(self, crease_angle: f64) -> Mesh {
+        let (normals, faces) =
+            processing::smooth_normals(&self.vertices, &self.normals, &self.faces, crease_angle);
+        Mesh {
+            normals: Rc::new(normals),
+            faces: Rc::new(faces),
+            ..self
+        }
+    }
+
+    /// A mesh with new buffers but the same materials.
+    fn with_geometry(
+        &self,
+        vertices: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f64, f64)>,
+        faces: Vec<Face>,
+    ) -> Mesh {
+        Mesh::with_buffers(
+            vertices,
+            normals,
+            uvs,
+            faces,
+            (*self.materials).clone(),
+            self.material.clone(),
+        )
+    }
+
+    pub(crate) fn center(&self) -> Vec3 {
+        let sum = self
+            .vertices
+            .iter()
+            .fold(Vec3::new(0.0, 0.0, 0.0), |sum, &vertex| sum + vertex);
+        sum / self.vertices.len().max(1) as f64
+    }
+}
+
+impl Intersectable for Mesh {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.bvh
+            .intersects(ray, t_min, t_max, |index, t_min, t_max| {
+                self.triangle(&self.faces[index])
+                    .intersects(ray, t_min, t_max)
+            })
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        Box::new(self.translated(vec - self.center()))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.material.is_emissive()
+            || self
+                .materials
+                .iter()
+                .any(|material| material.1.is_emissive())
+    }
+
+    /// Picks a face by its area, and then a point on that face, so that points are spread
+    /// evenly over the whole mesh.
+    fn sample_towards(&self, _: Vec3, _: f64) -> Option<Vec3> {
+        let total = *self.areas.last()?;
+        if total <= 0.0 {
+            return None;
+        }
+        let picked = rand::thread_rng().next_f64() * total;
+        let index = self.areas.partition_point(|&area| area < picked);
+        let face = &self.faces[index.min(self.faces.len() - 1)];
+        Some(self.triangle(face).sample_point())
+    }
+
+    fn pdf_towards(&self, origin: Vec3, point: Vec3, _: f64) -> f64 {
+        let total = self.areas.last().cloned().unwrap_or(0.0);
+        light::hit_at(self, origin, point).map_or(0.0, |intersection| {
+            light::area_pdf(origin, &intersection, total)
+        })
+    }
+}
+
+fn cumulative_areas(vertices: &[Vec3], faces: &[Face]) -> Vec<f64> {
+    let mut total = 0.0;
+    faces
+        .iter()
+        .map(|face| {
+            let [a, b, c] = face.vertices;
+            let (a, b, c) = (vertices[a], vertices[b], vertices[c]);
+            total += (b - a).cross(c - a).length() / 2.0;
+            total
+        })
+        .collect()
+}
+
+fn build_bvh(vertices: &[Vec3], faces: &[Face]) -> Bvh {
+    let bounds: Vec<Aabb> = faces
+        .iter()
+        .map(|face| {
+            let [a, b, c] = face.vertices;
+            Aabb::from_points(&[vertices[a], vertices[b], vertices[c]])
+        })
+        .collect();
+    Bvh::new(&bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use mesh::Face;
+    use prelude::*;
+
+    fn quad() -> Mesh {
+        Mesh::new(
+            vec![
+                Vec3::new(-1.0, -1.0, 0.0),
+                Vec3::new(1.0, -1.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(-1.0, 1.0, 0.0),
+            ],
+            vec![Face::new(0, 1, 2), Face::new(0, 2, 3)],
+            Material::Lambertian(Color::white()),
+        )
+    }
+
+    #[test]
+    fn ray_should_intersect_with_every_face_of_mesh() {
+        let mesh = quad();
+        let direction = Vec3::new(0.0, 0.0, -1.0);
+
+        let first = mesh.intersects(&Ray::new(Vec3::new(0.5, -0.5, 1.0), direction), 0.0, 10.0);
+        let second = mesh.intersects(&Ray::new(Vec3::new(-0.5, 0.5, 1.0), direction), 0.0, 10.0);
+
+        assert_that!(first.unwrap().distance, is(equal_to(1.0)));
+        assert_that!(second.unwrap().distance, is(equal_to(1.0)));
+    }
+
+    #[test]
+    fn mesh_should_be_moved_by_its_center() {
+        let mesh = quad().move_to(Vec3::new(0.0, 0.0, -1.0));
+        let ray = Ray::new(Vec3::new(0.5, -0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_that!(
+            mesh.intersects(&ray, 0.0, 10.0).unwrap().distance,
+            is(equal_to(2.0))
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use std::f64;
+use std::rc::Rc;
+
+use math;
+use prelude::*;
+use scene::Intersection;
+
+/// The number of steps taken through the smallest ball when looking for the surface.
+const STEPS_PER_RADIUS: f64 = 8.0;
+const REFINEMENT_STEPS: u32 = 50;
+/// How far from its origin a ray has to get before a hit counts, so that rays leaving the
+/// surface don't hit it again right away.
+const SURFACE_DISTANCE: f64 = 1e-6;
+
+/// How the influence of a ball falls off with the distance from its center, where `r2` is the
+/// squared distance relative to the radius. The influence of every kernel is zero outside of
+/// the radius.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Falloff {
+    /// Wyvill's soft object kernel, `(1 - r2)^3`.
+    Wyvill,
+    /// The steeper kernel `(1 - r2)^2`.
+    Quadratic,
+}
+
+impl Falloff {
+    fn value(&self, r2: f64) -> f64 {
+        let x = 1.0 - r2;
+        match *self {
+            Falloff::Wyvill => x * x * x,
+            Falloff::Quadratic => x * x,
+        }
+    }
+
+    /// The derivative of the kernel with respect to `r2`.
+    fn derivative(&self, r2: f64) -> f64 {
+        let x = 1.0 - r2;
+        match *self {
+            Falloff::Wyvill => -3.0 * x * x,
+            Falloff::Quadratic => -2.0 * x,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ball {
+    pub center: Vec3,
+    pub radius: f64,
+    pub weight: f64,
+}
+
+impl Ball {
+    pub fn new(center: Vec3, radius: f64, weight: f64) -> Ball {
+        Ball {
+            center: center,
+            radius: radius,
+            weight: weight,
+        }
+    }
+}
+
+/// A blobby surface, where the field from all the balls is equal to `threshold`. Balls that
+/// are close enough to each other melt together.
+#[derive(Clone)]
+pub struct Metaballs {
+    pub threshold: f64,
+    pub falloff: Falloff,
+    pub material: Material,
+    balls: Rc<Vec<Ball>>,
+}
+
+impl Metaballs {
+    pub fn new(balls: Vec<Ball>, threshold: f64, material: Material) -> Metaballs {
+        Metaballs {
+            threshold: threshold,
+            falloff: Falloff::Wyvill,
+            material: material,
+            balls: Rc::new(balls),
+        }
+    }
+
+    pub fn with_falloff(self, falloff: Falloff) -> Metaballs {
+        Metaballs {
+            falloff: falloff,
+            ..self
+        }
+    }
+
+    pub fn balls(&self) -> &[Ball] {
+        &self.balls
+    }
+
+    /// The field at `point`, which is larger than the threshold inside of the surface.
+    pub fn field(&self, point: Vec3) -> f64 {
+        self.balls
+            .iter()
+            .map(|ball| {
+                let r2 = (point - ball.center).squared_length() / (ball.radius * ball.radius);
+                if r2 < 1.0 {
+                    ball.weight * self.falloff.value(r2)
+                } else {
+                    0.0
+                }
+            })
+            .sum()
+    }
+
+    fn gradient(&self, point: Vec3) -> Vec3 {
+        self.balls
+            .iter()
+            .fold(Vec3::new(0.0, 0.0, 0.0), |gradient, ball| {
+                let offset = point - ball.center;
+                let radius2 = ball.radius * ball.radius;
+                let r2 = offset.squared_length() / radius2;
+                if r2 < 1.0 {
+                    let scale = ball.weight * self.falloff.derivative(r2) * 2.0 / radius2;
+                    gradient + offset * scale
+                } else {
+                    gradient
+                }
+            })
+    }
+
+    /// The parts of the ray that are inside of the reach of any ball, ordered and merged.
+    fn intervals(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<(f64, f64)> {
+        let mut intervals: Vec<(f64, f64)> = self
+            .balls
+            .iter()
+            .filter_map(|ball| {
+                let translated_origin = ray.origin - ball.center;
+                let roots = math::solve_quadratic(
+                    ray.direction.dot(ray.direction),
+                    2.0 * translated_origin.dot(ray.direction),
+                    translated_origin.dot(translated_origin) - ball.radius * ball.radius,
+                );
+                match (roots.first(), roots.last()) {
+                    (Some(&entry), Some(&exit)) if exit > t_min && entry < t_max => {
+                        Some((entry.max(t_min), exit.min(t_max)))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        intervals.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut merged: Vec<(f64, f64)> = Vec::new();
+        for interval in intervals {
+            match merged.last_mut() {
+                Some(last) if interval.0 <= last.1 => last.1 = last.1.max(interval.1),
+                _ => merged.push(interval),
+            }
+        }
+        merged
+    }
+
+    /// Narrows down the crossing of the threshold between `t0` and `t1` by bisection.
+    fn refine(&self, ray: &Ray, t0: f64, t1: f64) -> f64 {
+        let inside = |t: f64| self.field(ray.point_along_direction(t)) > self.threshold;
+        let (mut t0, mut t1) = (t0, t1);
+        let inside_at_start = inside(t0);
+        for _ in 0..REFINEMENT_STEPS {
+            let middle = (t0 + t1) / 2.0;
+            if inside(middle) == inside_at_start {
+                t0 = middle;
+            } else {
+                t1 = middle;
+            }
+        }
+        t1
+    }
+}
+
+impl Intersectable for Metaballs {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let direction_length = ray.direction.length();
+        let smallest_radius = self
+            .balls
+            .iter()
+            .map(|ball| ball.radius)
+            .fold(f64::MAX, f64::min);
+        let step = smallest_radius / STEPS_PER_RADIUS / direction_length;
+        let t_start = t_min + SURFACE_DISTANCE / direction_length;
+
+        for (start, end) in self.intervals(ray, t_start, t_max) {
+            // Step through the interval until the ray crosses the threshold, in either
+            // direction, and then find the exact crossing.
+            let mut t = start;
+            let mut inside = self.field(ray.point_along_direction(t)) > self.threshold;
+            while t < end {
+                let next = (t + step).min(end);
+                let next_inside = self.field(ray.point_along_direction(next)) > self.threshold;
+                if next_inside != inside {
+                    let delta = self.refine(ray, t, next);
+                    let intersection_point = ray.point_along_direction(delta);
+                    let normal = self.gradient(intersection_point).invert().normalize();
+                    let intersection = Intersection::new(
+                        delta,
+                        intersection_point,
+                        normal,
+                        Box::new(self.clone()),
+                    );
+                    return Some(intersection.with_material(self.material.clone()));
+                }
+                t = next;
+                inside = next_inside;
+            }
+        }
+        None
+    }
+
+    /// Moves every ball so that the center of the metaballs ends up at `vec`.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let offset = match self.bounding_box() {
+            Some(bounds) => vec - bounds.centroid(),
+            None => Vec3::new(0.0, 0.0, 0.0),
+        };
+        let balls = self
+            .balls
+            .iter()
+            .map(|ball| Ball {
+                center: ball.center + offset,
+                ..*ball
+            })
+            .collect();
+        Box::new(Metaballs {
+            balls: Rc::new(balls),
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.balls.iter().fold(None, |bounds, ball| {
+            let extent = Vec3::new(ball.radius, ball.radius, ball.radius);
+            let ball_bounds = Aabb::new(ball.center - extent, ball.center + extent);
+            Some(match bounds {
+                Some(bounds) => ball_bounds.union(&bounds),
+                None => ball_bounds,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use metaballs::Ball;
+    use prelude::*;
+    use tests::assert_close;
+
+    fn metaballs(centers: &[Vec3]) -> Metaballs {
+        let balls = centers
+            .iter()
+            .map(|&center| Ball::new(center, 1.0, 1.0))
+            .collect();
+        Metaballs::new(balls, 0.125, Material::Lambertian(Color::white()))
+    }
+
+    #[test]
+    fn single_ball_should_be_a_sphere_where_the_field_meets_the_threshold() {
+        let ball = metaballs(&[Vec3::new(0.0, 0.0, 0.0)]);
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let intersections = ball.intersections(&ray, 0.0, 100.0);
+
+        // (1 - r^2)^3 = 1 / 8 where r^2 = 1 / 2.
+        assert_that!(intersections.len(), is(equal_to(2)));
+        assert_close(intersections[0].distance, 5.0 - 0.5f64.sqrt());
+        assert_close(intersections[1].distance, 5.0 + 0.5f64.sqrt());
+        assert_close(intersections[0].normal.x, -1.0);
+        assert_close(intersections[1].normal.x, 1.0);
+    }
+
+    #[test]
+    fn balls_close_to_each_other_should_melt_together() {
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let one = metaballs(&[Vec3::new(0.75, 0.0, 0.0)]);
+        let two = metaballs(&[Vec3::new(0.75, 0.0, 0.0), Vec3::new(-0.75, 0.0, 0.0)]);
+
+        let i = two.intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(one.intersects(&ray, 0.0, 100.0).is_none(), is(true));
+        assert_that!(i.distance < 5.0, is(true));
+        assert_close(i.normal.y, 1.0);
+    }
+
+    #[test]
+    fn moving_the_metaballs_should_move_every_ball() {
+        let two = metaballs(&[Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)]);
+        let ray = Ray::new(Vec3::new(-5.0, 3.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let moved = two.move_to(Vec3::new(0.0, 3.0, 0.0));
+
+        assert_close(
+            moved.intersects(&ray, 0.0, 100.0).unwrap().distance,
+            4.0 - 0.5f64.sqrt(),
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use rand::{self, Rng};
+use std::f64::consts::PI;
+
+use prelude::*;
+use quadric::Frame;
+
+/// Roughness below this is taken to be a perfect mirror, where the distribution is too sharp
+/// to be evaluated.
+const SMOOTH_ROUGHNESS: f64 = 1e-4;
+
+/// The GGX, or Trowbridge-Reitz, distribution of the normals of the microscopic facets that a
+/// rough surface is made of. The roughness may differ along the tangent of the surface and the
+/// bitangent, which stretches the highlights in one direction.
+pub(crate) struct Ggx {
+    alpha: (f64, f64),
+    frame: Frame,
+}
+
+impl Ggx {
+    /// The distribution around `normal`, with the first roughness along `tangent`. Surfaces
+    /// without a tangent have no direction to stretch the highlights in, and get the average
+    /// of the two.
+    pub fn new(roughness: (f64, f64), normal: Vec3, tangent: Option<Vec3>) -> Ggx {
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        let (roughness, frame) = match tangent {
+            Some(tangent) => (roughness, Frame::with_tangent(origin, normal, tangent)),
+            None => {
+                let average = (roughness.0 + roughness.1) / 2.0;
+                ((average, average), Frame::new(origin, normal))
+            }
+        };
+        Ggx {
+            alpha: (
+                roughness.0.max(SMOOTH_ROUGHNESS),
+                roughness.1.max(SMOOTH_ROUGHNESS),
+            ),
+            frame: frame,
+        }
+    }
+
+    /// Whether the surface is so smooth that it only reflects in the mirrored direction.
+    pub fn is_smooth(roughness: (f64, f64)) -> bool {
+        roughness.0.max(roughness.1) < SMOOTH_ROUGHNESS
+    }
+
+    /// Picks a facet normal among those that can be seen from `outgoing`, which points away
+    /// from the surface, by their projected area. Returns it in local coordinates.
+    /// See "Sampling the GGX Distribution of Visible Normals" by Eric Heitz.
+    pub fn sample_normal(&self, outgoing: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let (ax, ay) = self.alpha;
+        // The stretched space, where the distribution is a hemisphere.
+        let v = Vec3::new(ax * outgoing.x, outgoing.y, ay * outgoing.z).normalize();
+        let length_squared = v.x * v.x + v.z * v.z;
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(v.z, 0.0, -v.x) / length_squared.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = v.cross(t1);
+
+        let r = rng.next_f64().sqrt();
+        let phi = 2.0 * PI * rng.next_f64();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.y);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let n = t1 * p1 + t2 * p2 + v * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        Vec3::new(ax * n.x, n.y.max(0.0), ay * n.z).normalize()
+    }
+
+    /// The density of facets facing `normal`, in local coordinates.
+    pub fn distribution(&self, normal: Vec3) -> f64 {
+        if normal.y <= 0.0 {
+            return 0.0;
+        }
+        let (ax, ay) = self.alpha;
+        let slope = (normal.x / ax).powi(2) + (normal.z / ay).powi(2) + normal.y * normal.y;
+        1.0 / (PI * ax * ay * slope * slope)
+    }
+
+    /// How much of the facets seen from `direction` are hidden behind other facets, as used by
+    /// the Smith masking function.
+    fn lambda(&self, direction: Vec3) -> f64 {
+        let (ax, ay) = self.alpha;
+        let tangent_squared = (ax * direction.x).powi(2) + (ay * direction.z).powi(2);
+        let cos_squared = direction.y * direction.y;
+        (-1.0 + (1.0 + tangent_squared / cos_squared).sqrt()) / 2.0
+    }
+
+    /// The part of the surface seen from `direction` that isn't hidden.
+    pub fn masking(&self, direction: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(direction))
+    }
+
+    /// The part of the surface seen from both directions, where a facet that is seen from one
+    /// of them is more likely to be seen from the other.
+    pub fn shadowing(&self, outgoing: Vec3, incoming: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(outgoing) + self.lambda(incoming))
+    }
+
+    /// The coordinates of `vec` with the normal along y.
+    pub fn local(&self, vec: Vec3) -> Vec3 {
+        self.frame.local(vec.normalize())
+    }
+
+    pub fn world(&self, vec: Vec3) -> Vec3 {
+        self.frame.world(vec)
+    }
+}
+
+/// How much of the light is reflected by a metal with the complex index of refraction
+/// `eta + ik` at the angle with the cosine `cosine`, for each of red, green and blue.
+pub fn conductor_fresnel(eta: Color, k: Color, cosine: f64) -> Color {
+    Color::new(
+        conductor_reflectance(eta.r, k.r, cosine),
+        conductor_reflectance(eta.g, k.g, cosine),
+        conductor_reflectance(eta.b, k.b, cosine),
+    )
+}
+
+/// Schlick's approximation of the reflectance, which starts out at `color` when the light
+/// comes straight at the surface and goes towards white at grazing angles.
+pub fn schlick_fresnel(color: Color, cosine: f64) -> Color {
+    let weight = (1.0 - cosine.clamp(0.0, 1.0)).powi(5);
+    color * (1.0 - weight) + Color::white() * weight
+}
+
+/// The complex index of refraction `eta + ik` of a metal that reflects `color` when the light
+/// comes straight at it, with the same color tinting its edges. See "Artist Friendly Metallic
+/// Fresnel" by Ole Gulbrandsen.
+pub fn conductor_of_color(color: Color) -> (Color, Color) {
+    let channel = |reflectance: f64| {
+        let r = reflectance.clamp(0.0, 0.99);
+        let eta = r * (1.0 - r) / (1.0 + r) + (1.0 - r) * (1.0 + r.sqrt()) / (1.0 - r.sqrt());
+        let k = ((r * (eta + 1.0).powi(2) - (eta - 1.0).powi(2)) / (1.0 - r))
+            .max(0.0)
+            .sqrt();
+        (eta, k)
+    };
+    let (r, g, b) = (channel(color.r), channel(color.g), channel(color.b));
+    (Color::new(r.0, g.0, b.0), Color::new(r.1, g.1, b.1))
+}
+
+fn conductor_reflectance(eta: f64, k: f64, cosine: f64) -> f64 {
+    let cos_squared = cosine.clamp(0.0, 1.0).powi(2);
+    let sin_squared = 1.0 - cos_squared;
+    let t0 = eta * eta - k * k - sin_squared;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos_squared;
+    let t2 = 2.0 * cosine.clamp(0.0, 1.0) * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+    let t3 = cos_squared * a2_plus_b2 + sin_squared * sin_squared;
+    let t4 = t2 * sin_squared;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+    0.5 * (perpendicular + parallel)
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use microfacet::Ggx;
+    use prelude::*;
+
+    #[test]
+    fn conductor_fresnel_should_match_the_reflectance_at_normal_incidence() {
+        let (eta, k) = (Color::new(0.2, 0.9, 1.1), Color::new(3.9, 2.5, 2.1));
+
+        let fresnel = super::conductor_fresnel(eta, k, 1.0);
+
+        let expected = ((0.2f64 - 1.0).powi(2) + 3.9 * 3.9) / ((0.2f64 + 1.0).powi(2) + 3.9 * 3.9);
+        assert_that!((fresnel.r - expected).abs() < 1e-9, is(true));
+        assert_that!(fresnel.r > fresnel.b, is(true));
+        assert_that!(
+            super::conductor_fresnel(eta, k, 0.0),
+            is(equal_to(Color::white()))
+        );
+    }
+
+    #[test]
+    fn sampled_normals_should_face_the_viewer() {
+        let ggx = Ggx::new((0.5, 0.5), Vec3::new(0.0, 1.0, 0.0), None);
+        let outgoing = Vec3::new(0.6, 0.8, 0.0);
+
+        for _ in 0..1000 {
+            let normal = ggx.sample_normal(outgoing);
+
+            assert_that!(normal.y >= 0.0, is(true));
+            assert_that!(normal.dot(outgoing) >= -1e-9, is(true));
+        }
+    }
+
+    #[test]
+    fn anisotropic_roughness_should_spread_normals_further_along_the_rougher_axis() {
+        let tangent = Vec3::new(0.0, 0.0, 1.0);
+        let ggx = Ggx::new((0.6, 0.05), Vec3::new(0.0, 1.0, 0.0), Some(tangent));
+        let outgoing = Vec3::new(0.0, 1.0, 0.0);
+
+        let (mut along, mut across) = (0.0, 0.0);
+        for _ in 0..1000 {
+            let normal = ggx.world(ggx.sample_normal(outgoing));
+            along += normal.z.abs();
+            across += normal.x.abs();
+        }
+
+        assert_that!(along > 5.0 * across, is(true));
+    }
+}
@@ -0,0 +1,379 @@
+use std::fs;
+use std::path::Path;
+use std::str::SplitWhitespace;
+
+use mesh::{Face, Mesh, MeshError};
+use prelude::*;
+
+const DEFAULT_REFRACTION_INDEX: f64 = 1.5;
+
+pub fn load(path: &Path) -> Result<Mesh, MeshError> {
+    let source = fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    parse(&source, |library| {
+        fs::read_to_string(directory.join(library)).map_err(MeshError::from)
+    })
+}
+
+/// Parses the source of an OBJ file. `read_library` is called with the name of every material
+/// library referenced by `mtllib`, and should return its content.
+pub fn parse<F>(source: &str, mut read_library: F) -> Result<Mesh, MeshError>
+where
+    F: FnMut(&str) -> Result<String, MeshError>,
+{
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut faces = Vec::new();
+    let mut materials: Vec<(String, Material)> = Vec::new();
+    let mut material = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => vertices.push(parse_vec3(&mut tokens, line_number)?),
+            Some("vn") => normals.push(parse_vec3(&mut tokens, line_number)?),
+            Some("vt") => {
+                let u = parse_f64(tokens.next(), line_number)?;
+                let v = match tokens.next() {
+                    Some(token) => parse_f64(Some(token), line_number)?,
+                    None => 0.0,
+                };
+                uvs.push((u, v));
+            }
+            Some("f") => {
+                let corners = tokens
+                    .map(|token| {
+                        parse_corner(
+                            token,
+                            (vertices.len(), uvs.len(), normals.len()),
+                            line_number,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                triangulate(&corners, material, &mut faces, line_number)?;
+            }
+            Some("mtllib") => {
+                for library in tokens {
+                    materials.extend(parse_mtl(&read_library(library)?)?);
+                }
+            }
+            Some("usemtl") => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                match materials.iter().position(|other| other.0 == name) {
+                    Some(position) => material = Some(position),
+                    None => {
+                        return Err(MeshError::parse(
+                            line_number,
+                            format!("unknown material '{}'", name),
+                        ))
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(Mesh::with_buffers(
+        vertices,
+        normals,
+        uvs,
+        faces,
+        materials,
+        Material::Lambertian(Color::new(0.8, 0.8, 0.8)),
+    ))
+}
+
+/// Parses a material library, mapping each material onto the closest `Material`: transparent
+/// materials become refractive, materials with mirror illumination models become reflective,
+/// and everything else is diffuse.
+pub fn parse_mtl(source: &str) -> Result<Vec<(String, Material)>, MeshError> {
+    let mut materials = Vec::new();
+    let mut current: Option<MtlMaterial> = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next();
+        if keyword == Some("newmtl") {
+            if let Some(material) = current.take() {
+                materials.push(material.into_material());
+            }
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err(MeshError::parse(line_number, "material without a name"));
+            }
+            current = Some(MtlMaterial::new(name));
+            continue;
+        }
+
+        let material = match (keyword, current.as_mut()) {
+            (None, _) => continue,
+            (Some(keyword), _) if keyword.starts_with('#') => continue,
+            (Some(_), Some(material)) => material,
+            (Some(keyword), None) => {
+                return Err(MeshError::parse(
+                    line_number,
+                    format!("'{}' before any 'newmtl'", keyword),
+                ))
+            }
+        };
+        match keyword {
+            Some("Kd") => material.diffuse = parse_color(&mut tokens, line_number)?,
+            Some("Ks") => material.specular = Some(parse_color(&mut tokens, line_number)?),
+            Some("Ns") => material.shininess = parse_f64(tokens.next(), line_number)?,
+            Some("Ni") => material.refraction_index = parse_f64(tokens.next(), line_number)?,
+            Some("d") => material.opacity = parse_f64(tokens.next(), line_number)?,
+            Some("Tr") => material.opacity = 1.0 - parse_f64(tokens.next(), line_number)?,
+            Some("illum") => material.illumination = parse_f64(tokens.next(), line_number)? as u32,
+            _ => (),
+        }
+    }
+
+    if let Some(material) = current {
+        materials.push(material.into_material());
+    }
+    Ok(materials)
+}
+
+struct MtlMaterial {
+    name: String,
+    diffuse: Color,
+    specular: Option<Color>,
+    shininess: f64,
+    refraction_index: f64,
+    opacity: f64,
+    illumination: u32,
+}
+
+impl MtlMaterial {
+    fn new(name: String) -> MtlMaterial {
+        MtlMaterial {
+            name: name,
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: None,
+            shininess: 0.0,
+            refraction_index: DEFAULT_REFRACTION_INDEX,
+            opacity: 1.0,
+            illumination: 2,
+        }
+    }
+
+    fn into_material(self) -> (String, Material) {
+        let material = match self.illumination {
+            4 | 6 | 7 | 9 => Material::Dielectric {
+                refraction_index: self.refraction_index,
+            },
+            _ if self.opacity < 1.0 => Material::Dielectric {
+                refraction_index: self.refraction_index,
+            },
+            3 | 5 | 8 => {
+                // Maps the Phong exponent onto the microfacet roughness giving a highlight of
+                // the same width, so that `Ns 0` gives a very rough metal and large exponents
+                // give a sharp mirror.
+                let diffusiveness = (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt();
+                let color = self.specular.unwrap_or(self.diffuse);
+                Material::Metal {
+                    albedo: color,
+                    fuzz: diffusiveness,
+                }
+            }
+            _ => Material::Lambertian(self.diffuse),
+        };
+        (self.name, material)
+    }
+}
+
+type Corner = (usize, Option<usize>, Option<usize>);
+
+fn triangulate(
+    corners: &[Corner],
+    material: Option<usize>,
+    faces: &mut Vec<Face>,
+    line: usize,
+) -> Result<(), MeshError> {
+    if corners.len() < 3 {
+        return Err(MeshError::parse(
+            line,
+            format!("face with {} vertices, expected at least 3", corners.len()),
+        ));
+    }
+    let has_uvs = corners[0].1.is_some();
+    let has_normals = corners[0].2.is_some();
+    if corners
+        .iter()
+        .any(|corner| corner.1.is_some() != has_uvs || corner.2.is_some() != has_normals)
+    {
+        return Err(MeshError::parse(
+            line,
+            "face mixes vertices with and without texture coordinates or normals",
+        ));
+    }
+
+    let (a, a_uv, a_normal) = corners[0];
+    for window in corners[1..].windows(2) {
+        let ((b, b_uv, b_normal), (c, c_uv, c_normal)) = (window[0], window[1]);
+        faces.push(Face {
+            vertices: [a, b, c],
+            normals: if has_normals {
+                Some([a_normal.unwrap(), b_normal.unwrap(), c_normal.unwrap()])
+            } else {
+                None
+            },
+            uvs: if has_uvs {
+                Some([a_uv.unwrap(), b_uv.unwrap(), c_uv.unwrap()])
+            } else {
+                None
+            },
+            material: material,
+        });
+    }
+    Ok(())
+}
+
+/// Parses a face corner on the form `v`, `v/vt`, `v//vn` or `v/vt/vn`. `counts` holds the
+/// number of vertices, uvs and normals read so far, which negative indices are relative to.
+fn parse_corner(
+    token: &str,
+    counts: (usize, usize, usize),
+    line: usize,
+) -> Result<Corner, MeshError> {
+    let mut parts = token.split('/');
+    let vertex = parse_index(parts.next(), counts.0, "vertex", line)?;
+    let uv = match parts.next() {
+        None | Some("") => None,
+        part => Some(parse_index(part, counts.1, "texture coordinate", line)?),
+    };
+    let normal = match parts.next() {
+        None | Some("") => None,
+        part => Some(parse_index(part, counts.2, "normal", line)?),
+    };
+    if parts.next().is_some() {
+        return Err(MeshError::parse(
+            line,
+            format!("malformed face vertex '{}'", token),
+        ));
+    }
+    Ok((vertex, uv, normal))
+}
+
+fn parse_index(
+    token: Option<&str>,
+    count: usize,
+    kind: &str,
+    line: usize,
+) -> Result<usize, MeshError> {
+    let token = token.unwrap_or("");
+    let index: i64 = token
+        .parse()
+        .map_err(|_| MeshError::parse(line, format!("invalid {} index '{}'", kind, token)))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(MeshError::parse(
+            line,
+            format!(
+                "{} index {} is out of range, {} defined",
+                kind, index, count
+            ),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_vec3(tokens: &mut SplitWhitespace, line: usize) -> Result<Vec3, MeshError> {
+    let x = parse_f64(tokens.next(), line)?;
+    let y = parse_f64(tokens.next(), line)?;
+    let z = parse_f64(tokens.next(), line)?;
+    Ok(Vec3::new(x, y, z))
+}
+
+fn parse_color(tokens: &mut SplitWhitespace, line: usize) -> Result<Color, MeshError> {
+    let Vec3 { x, y, z } = parse_vec3(tokens, line)?;
+    Ok(Color::new(x, y, z))
+}
+
+fn parse_f64(token: Option<&str>, line: usize) -> Result<f64, MeshError> {
+    match token {
+        Some(token) => token
+            .parse()
+            .map_err(|_| MeshError::parse(line, format!("invalid number '{}'", token))),
+        None => Err(MeshError::parse(line, "missing number")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use mesh::MeshError;
+    use obj::parse;
+    use prelude::*;
+
+    fn no_libraries(library: &str) -> Result<String, MeshError> {
+        panic!("unexpected material library {}", library)
+    }
+
+    #[test]
+    fn should_triangulate_polygons_as_fans() {
+        let source = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv -1 1 0\nf 1 2 3 4 5\n";
+
+        let mesh = parse(source, no_libraries).unwrap();
+
+        let faces: Vec<_> = mesh.faces().iter().map(|face| face.vertices).collect();
+        assert_that!(faces, is(equal_to(vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]])));
+    }
+
+    #[test]
+    fn should_read_texture_coordinates_and_normals_of_faces() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\n\
+                      f 1/1/1 2/2/1 -1/-1/-1\n";
+
+        let mesh = parse(source, no_libraries).unwrap();
+
+        let face = mesh.faces()[0];
+        assert_that!(face.vertices, is(equal_to([0, 1, 2])));
+        assert_that!(face.uvs, is(equal_to(Some([0, 1, 2]))));
+        assert_that!(face.normals, is(equal_to(Some([0, 0, 0]))));
+    }
+
+    #[test]
+    fn should_assign_materials_from_libraries() {
+        let source =
+            "mtllib scene.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nusemtl glass\nf 1 2 3\n";
+        let library = "newmtl red\nKd 1 0 0\n\nnewmtl glass\nKd 1 1 1\nNi 1.3\nillum 7\n";
+
+        let mesh = parse(source, |name| {
+            assert_that!(name, is(equal_to("scene.mtl")));
+            Ok(library.to_string())
+        })
+        .unwrap();
+
+        assert_that!(mesh.faces()[0].material, is(equal_to(None)));
+        assert_that!(mesh.faces()[1].material, is(equal_to(Some(1))));
+        assert_that!(
+            mesh.material("red").unwrap().albedo(),
+            is(equal_to(Color::red()))
+        );
+    }
+
+    #[test]
+    fn should_report_the_line_of_malformed_faces() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n";
+
+        match parse(source, no_libraries) {
+            Err(MeshError::Parse { line, .. }) => assert_that!(line, is(equal_to(5))),
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn should_fail_on_unknown_materials_and_invalid_numbers() {
+        assert_that!(parse("usemtl missing\n", no_libraries).is_err(), is(true));
+        assert_that!(parse("v 0 zero 0\n", no_libraries).is_err(), is(true));
+        assert_that!(parse("v 0 0 0\nf 1 1\n", no_libraries).is_err(), is(true));
+    }
+}
@@ -0,0 +1,347 @@
+use rand::{self, Rng};
+
+use light;
+use prelude::*;
+use scene::Intersection;
+
+const PARALLEL_EPSILON: f64 = 1e-12;
+
+/// An infinite plane through `point`. Texture coordinates are the distances from `point`
+/// along two directions in the plane, so textures repeat when they are wrapped.
+#[derive(Clone)]
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub material: Material,
+    tangent: Vec3,
+    bitangent: Vec3,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: Material) -> Plane {
+        let normal = normal.normalize();
+        let helper = if normal.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let tangent = helper.cross(normal).normalize();
+        Plane {
+            point: point,
+            normal: normal,
+            material: material,
+            tangent: tangent,
+            bitangent: normal.cross(tangent),
+        }
+    }
+}
+
+impl Intersectable for Plane {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let denominator = self.normal.dot(ray.direction);
+        if denominator.abs() < PARALLEL_EPSILON {
+            return None;
+        }
+        let distance = (self.point - ray.origin).dot(self.normal) / denominator;
+        if distance < t_max && distance > t_min {
+            let intersection_point = ray.point_along_direction(distance);
+            let offset = intersection_point - self.point;
+            let intersection = Intersection::new(
+                distance,
+                intersection_point,
+                self.normal,
+                Box::new(self.clone()),
+            );
+            Some(
+                intersection
+                    .with_uv(offset.dot(self.tangent), offset.dot(self.bitangent))
+                    .with_tangent(self.tangent)
+                    .with_material(self.material.clone()),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        Box::new(Plane {
+            point: vec,
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+/// A rectangle perpendicular to one of the axes, spanning `a` and `b` along the two other
+/// axes in the order x, y, z. The normal points along the positive axis unless it is flipped.
+#[derive(Clone)]
+pub struct Rect {
+    axis: u32,
+    a: (f64, f64),
+    b: (f64, f64),
+    k: f64,
+    normal_sign: f64,
+    pub material: Material,
+}
+
+impl Rect {
+    pub fn new(axis: Axis, a: (f64, f64), b: (f64, f64), k: f64, material: Material) -> Rect {
+        let axis = match axis {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        };
+        Rect {
+            axis: axis,
+            a: (a.0.min(a.1), a.0.max(a.1)),
+            b: (b.0.min(b.1), b.0.max(b.1)),
+            k: k,
+            normal_sign: 1.0,
+            material: material,
+        }
+    }
+
+    pub fn xy(x: (f64, f64), y: (f64, f64), z: f64, material: Material) -> Rect {
+        Rect::new(Axis::Z, x, y, z, material)
+    }
+
+    pub fn xz(x: (f64, f64), z: (f64, f64), y: f64, material: Material) -> Rect {
+        Rect::new(Axis::Y, x, z, y, material)
+    }
+
+    pub fn yz(y: (f64, f64), z: (f64, f64), x: f64, material: Material) -> Rect {
+        Rect::new(Axis::X, y, z, x, material)
+    }
+
+    /// The same rectangle facing the other way.
+    pub fn flipped(self) -> Rect {
+        Rect {
+            normal_sign: -self.normal_sign,
+            ..self
+        }
+    }
+
+    fn other_axes(&self) -> (u32, u32) {
+        match self.axis {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        }
+    }
+
+    /// Builds a vector from its components along the normal axis and the two other axes.
+    fn vec(&self, k: f64, a: f64, b: f64) -> Vec3 {
+        let (a_axis, b_axis) = self.other_axes();
+        let mut components = [0.0; 3];
+        components[self.axis as usize] = k;
+        components[a_axis as usize] = a;
+        components[b_axis as usize] = b;
+        Vec3::new(components[0], components[1], components[2])
+    }
+}
+
+impl Intersectable for Rect {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let (a_axis, b_axis) = self.other_axes();
+        let distance = (self.k - ray.origin[self.axis]) / ray.direction[self.axis];
+        if !(distance < t_max && distance > t_min) {
+            return None;
+        }
+        let intersection_point = ray.point_along_direction(distance);
+        let (a, b) = (intersection_point[a_axis], intersection_point[b_axis]);
+        if a < self.a.0 || a > self.a.1 || b < self.b.0 || b > self.b.1 {
+            return None;
+        }
+        let normal = self.vec(self.normal_sign, 0.0, 0.0);
+        let intersection =
+            Intersection::new(distance, intersection_point, normal, Box::new(self.clone()));
+        let (u, v) = (
+            (a - self.a.0) / (self.a.1 - self.a.0),
+            (b - self.b.0) / (self.b.1 - self.b.0),
+        );
+        Some(
+            intersection
+                .with_uv(u, v)
+                .with_tangent(self.vec(0.0, 1.0, 0.0))
+                .with_material(self.material.clone()),
+        )
+    }
+
+    /// Moves the center of the rectangle to `vec`.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let (a_axis, b_axis) = self.other_axes();
+        let (half_a, half_b) = ((self.a.1 - self.a.0) / 2.0, (self.b.1 - self.b.0) / 2.0);
+        let (a, b) = (vec[a_axis], vec[b_axis]);
+        Box::new(Rect {
+            a: (a - half_a, a + half_a),
+            b: (b - half_b, b + half_b),
+            k: vec[self.axis],
+            ..self.clone()
+        })
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn sample_towards(&self, _: Vec3, _: f64) -> Option<Vec3> {
+        let mut rng = rand::thread_rng();
+        let a = self.a.0 + rng.next_f64() * (self.a.1 - self.a.0);
+        let b = self.b.0 + rng.next_f64() * (self.b.1 - self.b.0);
+        Some(self.vec(self.k, a, b))
+    }
+
+    fn pdf_towards(&self, origin: Vec3, point: Vec3, _: f64) -> f64 {
+        let area = (self.a.1 - self.a.0) * (self.b.1 - self.b.0);
+        light::hit_at(self, origin, point).map_or(0.0, |intersection| {
+            light::area_pdf(origin, &intersection, area)
+        })
+    }
+
+    /// The box is given a small thickness along the normal axis, so that it is not empty.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let thickness = 1e-4;
+        Some(Aabb::new(
+            self.vec(self.k - thickness, self.a.0, self.b.0),
+            self.vec(self.k + thickness, self.a.1, self.b.1),
+        ))
+    }
+}
+
+/// An axis-aligned box made from six rectangles facing outwards.
+#[derive(Clone)]
+pub struct Cuboid {
+    pub min: Vec3,
+    pub max: Vec3,
+    sides: Vec<Rect>,
+}
+
+impl Cuboid {
+    pub fn new(min: Vec3, max: Vec3, material: Material) -> Cuboid {
+        let (x, y, z) = ((min.x, max.x), (min.y, max.y), (min.z, max.z));
+        Cuboid {
+            min: min,
+            max: max,
+            sides: vec![
+                Rect::yz(y, z, min.x, material.clone()).flipped(),
+                Rect::yz(y, z, max.x, material.clone()),
+                Rect::xz(x, z, min.y, material.clone()).flipped(),
+                Rect::xz(x, z, max.y, material.clone()),
+                Rect::xy(x, y, min.z, material.clone()).flipped(),
+                Rect::xy(x, y, max.z, material),
+            ],
+        }
+    }
+}
+
+impl Intersectable for Cuboid {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let mut intersection: Option<Intersection> = None;
+        let mut closest_so_far: f64 = t_max;
+
+        for side in self.sides.iter() {
+            if let Some(other_intersection) = side.intersects(ray, t_min, closest_so_far) {
+                closest_so_far = other_intersection.distance;
+                intersection = Some(other_intersection);
+            }
+        }
+        intersection
+    }
+
+    /// Moves the center of the box to `vec`.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let offset = vec - (self.min + self.max) * 0.5;
+        Box::new(Cuboid::new(
+            self.min + offset,
+            self.max + offset,
+            self.sides[0].material.clone(),
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+
+    fn material() -> Material {
+        Material::Lambertian(Color::white())
+    }
+
+    #[test]
+    fn ray_should_intersect_with_plane() {
+        let plane = Plane::new(
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            material(),
+        );
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        let i = plane.intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(i.distance, is(equal_to(2.0)));
+        assert_that!(i.normal, is(equal_to(Vec3::new(0.0, 1.0, 0.0))));
+        assert_that!(plane.bounding_box().is_none(), is(true));
+    }
+
+    #[test]
+    fn ray_parallel_to_plane_should_miss() {
+        let plane = Plane::new(
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            material(),
+        );
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        assert_that!(plane.intersects(&ray, 0.0, 100.0).is_none(), is(true));
+    }
+
+    #[test]
+    fn ray_should_intersect_with_rectangle_and_get_uv() {
+        let rect = Rect::xz((0.0, 2.0), (0.0, 4.0), 1.0, material());
+        let ray = Ray::new(Vec3::new(0.5, 3.0, 1.0), Vec3::new(0.0, -1.0, 0.0));
+
+        let i = rect.intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(i.distance, is(equal_to(2.0)));
+        assert_that!(i.normal, is(equal_to(Vec3::new(0.0, 1.0, 0.0))));
+        assert_that!(i.uv, is(equal_to((0.25, 0.25))));
+    }
+
+    #[test]
+    fn ray_should_miss_outside_of_rectangle() {
+        let rect = Rect::xy((0.0, 1.0), (0.0, 1.0), 0.0, material());
+        let ray = Ray::new(Vec3::new(1.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_that!(rect.intersects(&ray, 0.0, 100.0).is_none(), is(true));
+    }
+
+    #[test]
+    fn cuboid_should_have_outward_facing_normals() {
+        let cuboid = Cuboid::new(
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            material(),
+        );
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let intersections = cuboid.intersections(&ray, 0.0, 100.0);
+
+        let normals: Vec<Vec3> = intersections.iter().map(|i| i.normal).collect();
+        assert_that!(
+            normals,
+            is(equal_to(vec![
+                Vec3::new(-1.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0)
+            ]))
+        );
+        assert_that!(intersections[1].distance, is(equal_to(6.0)));
+    }
+}
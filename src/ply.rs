@@ -0,0 +1,476 @@
+use std::fs;
+use std::path::Path;
+use std::str::{self, SplitWhitespace};
+
+use mesh::{Face, Mesh, MeshError};
+use prelude::*;
+
+pub fn load(path: &Path) -> Result<Mesh, MeshError> {
+    parse(&fs::read(path)?)
+}
+
+/// Parses an ASCII or binary little-endian PLY file. Vertex positions, normals, texture
+/// coordinates and colors are read from the `vertex` element, and polygons from the `face`
+/// element, in whichever order they are declared. Any other element is skipped.
+pub fn parse(bytes: &[u8]) -> Result<Mesh, MeshError> {
+    let (header, body) = split_header(bytes)?;
+    let (format, elements) = parse_header(header)?;
+    let mut reader = match format {
+        Format::Ascii => {
+            let body = str::from_utf8(body)
+                .map_err(|_| MeshError::Format("ASCII body is not valid UTF-8".to_string()))?;
+            Reader::Ascii(body.split_whitespace())
+        }
+        Format::BinaryLittleEndian => Reader::Binary(body),
+    };
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut polygons = Vec::new();
+
+    for element in elements.iter() {
+        for index in 0..element.count {
+            let values = element
+                .properties
+                .iter()
+                .map(|property| reader.read_property(property))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|message| {
+                    MeshError::Format(format!("{} {}: {}", element.name, index, message))
+                })?;
+            match element.name.as_str() {
+                "vertex" => {
+                    let scalar = |names: &[&str]| element.scalar(&values, names);
+                    match (scalar(&["x"]), scalar(&["y"]), scalar(&["z"])) {
+                        (Some(x), Some(y), Some(z)) => vertices.push(Vec3::new(x, y, z)),
+                        _ => {
+                            return Err(MeshError::Format(format!(
+                                "vertex {} is missing a coordinate",
+                                index
+                            )))
+                        }
+                    }
+                    if let (Some(x), Some(y), Some(z)) =
+                        (scalar(&["nx"]), scalar(&["ny"]), scalar(&["nz"]))
+                    {
+                        normals.push(Vec3::new(x, y, z));
+                    }
+                    if let (Some(u), Some(v)) = (
+                        scalar(&["u", "s", "texture_u", "texture_s"]),
+                        scalar(&["v", "t", "texture_v", "texture_t"]),
+                    ) {
+                        uvs.push((u, v));
+                    }
+                    if let (Some(r), Some(g), Some(b)) = (
+                        element.color(&values, "red"),
+                        element.color(&values, "green"),
+                        element.color(&values, "blue"),
+                    ) {
+                        colors.push(Color::new(r, g, b));
+                    }
+                }
+                "face" => {
+                    let indices = element
+                        .list(&values, &["vertex_indices", "vertex_index"])
+                        .ok_or_else(|| {
+                            MeshError::Format(format!("face {} has no vertex indices", index))
+                        })?;
+                    polygons.push(indices.to_vec());
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // The faces may come before the vertices, so their indices are checked once all are read.
+    let vertex_count = vertices.len();
+    let mut faces = Vec::new();
+    for (index, indices) in polygons.iter().enumerate() {
+        triangulate(indices, vertex_count, &mut faces)
+            .map_err(|message| MeshError::Format(format!("face {}: {}", index, message)))?;
+    }
+
+    // Attributes are only used when every vertex has them, and share the vertex indices.
+    for face in faces.iter_mut() {
+        if normals.len() == vertex_count {
+            face.normals = Some(face.vertices);
+        }
+        if uvs.len() == vertex_count {
+            face.uvs = Some(face.vertices);
+        }
+    }
+    let colors = if colors.len() == vertex_count {
+        colors
+    } else {
+        vec![]
+    };
+
+    Ok(Mesh::with_buffers(
+        vertices,
+        normals,
+        uvs,
+        faces,
+        vec![],
+        Material::Lambertian(Color::new(0.8, 0.8, 0.8)),
+    )
+    .with_vertex_colors(colors))
+}
+
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Type {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl Type {
+    fn parse(name: &str) -> Option<Type> {
+        match name {
+            "char" | "int8" => Some(Type::Int8),
+            "uchar" | "uint8" => Some(Type::UInt8),
+            "short" | "int16" => Some(Type::Int16),
+            "ushort" | "uint16" => Some(Type::UInt16),
+            "int" | "int32" => Some(Type::Int32),
+            "uint" | "uint32" => Some(Type::UInt32),
+            "float" | "float32" => Some(Type::Float32),
+            "double" | "float64" => Some(Type::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            Type::Int8 | Type::UInt8 => 1,
+            Type::Int16 | Type::UInt16 => 2,
+            Type::Int32 | Type::UInt32 | Type::Float32 => 4,
+            Type::Float64 => 8,
+        }
+    }
+
+    /// The value of a color channel of this type at full intensity, which is the largest value
+    /// of integer types.
+    fn full_intensity(&self) -> f64 {
+        match *self {
+            Type::Int8 => i8::MAX as f64,
+            Type::UInt8 => u8::MAX as f64,
+            Type::Int16 => i16::MAX as f64,
+            Type::UInt16 => u16::MAX as f64,
+            Type::Int32 => i32::MAX as f64,
+            Type::UInt32 => u32::MAX as f64,
+            Type::Float32 | Type::Float64 => 1.0,
+        }
+    }
+}
+
+enum Property {
+    Scalar(String, Type),
+    List(String, Type, Type),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match *self {
+            Property::Scalar(ref name, _) | Property::List(ref name, _, _) => name,
+        }
+    }
+}
+
+enum Value {
+    Scalar(f64),
+    List(Vec<f64>),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn position(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|property| names.contains(&property.name()))
+    }
+
+    fn scalar(&self, values: &[Value], names: &[&str]) -> Option<f64> {
+        match self.position(names).map(|index| &values[index]) {
+            Some(&Value::Scalar(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn list<'a>(&self, values: &'a [Value], names: &[&str]) -> Option<&'a [f64]> {
+        match self.position(names).map(|index| &values[index]) {
+            Some(Value::List(list)) => Some(list),
+            _ => None,
+        }
+    }
+
+    /// Reads a color channel, normalizing integer channels by the largest value of their type,
+    /// such as 255 for `uchar`.
+    fn color(&self, values: &[Value], name: &str) -> Option<f64> {
+        let value = self.scalar(values, &[name])?;
+        match self.properties[self.position(&[name])?] {
+            Property::Scalar(_, kind) => Some(value / kind.full_intensity()),
+            Property::List(..) => None,
+        }
+    }
+}
+
+enum Reader<'a> {
+    Ascii(SplitWhitespace<'a>),
+    Binary(&'a [u8]),
+}
+
+impl<'a> Reader<'a> {
+    fn read_property(&mut self, property: &Property) -> Result<Value, String> {
+        match *property {
+            Property::Scalar(_, kind) => Ok(Value::Scalar(self.read(kind)?)),
+            Property::List(_, count_kind, kind) => {
+                let count = self.read(count_kind)?;
+                if count < 0.0 {
+                    return Err(format!("negative list length {}", count));
+                }
+                let list = (0..count as usize)
+                    .map(|_| self.read(kind))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::List(list))
+            }
+        }
+    }
+
+    fn read(&mut self, kind: Type) -> Result<f64, String> {
+        match *self {
+            Reader::Ascii(ref mut tokens) => {
+                let token = tokens.next().ok_or("unexpected end of file")?;
+                token
+                    .parse()
+                    .map_err(|_| format!("invalid number '{}'", token))
+            }
+            Reader::Binary(ref mut bytes) => {
+                if bytes.len() < kind.size() {
+                    return Err("unexpected end of file".to_string());
+                }
+                let (value, rest) = bytes.split_at(kind.size());
+                *bytes = rest;
+                Ok(read_little_endian(value, kind))
+            }
+        }
+    }
+}
+
+fn read_little_endian(bytes: &[u8], kind: Type) -> f64 {
+    let mut buffer = [0u8; 8];
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    match kind {
+        Type::Int8 => bytes[0] as i8 as f64,
+        Type::UInt8 => bytes[0] as f64,
+        Type::Int16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+        Type::UInt16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+        Type::Int32 => i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+        Type::UInt32 => u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+        Type::Float32 => f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+        Type::Float64 => f64::from_le_bytes(buffer),
+    }
+}
+
+fn split_header(bytes: &[u8]) -> Result<(&str, &[u8]), MeshError> {
+    const END: &[u8] = b"end_header";
+    let end = bytes
+        .windows(END.len())
+        .position(|window| window == END)
+        .ok_or_else(|| MeshError::Format("missing 'end_header'".to_string()))?;
+    let body = match bytes[end..].iter().position(|&byte| byte == b'\n') {
+        Some(newline) => end + newline + 1,
+        None => bytes.len(),
+    };
+    let header = str::from_utf8(&bytes[..end])
+        .map_err(|_| MeshError::Format("header is not valid UTF-8".to_string()))?;
+    Ok((header, &bytes[body..]))
+}
+
+fn parse_header(header: &str) -> Result<(Format, Vec<Element>), MeshError> {
+    let mut lines = header.lines().enumerate();
+    match lines.next() {
+        Some((_, line)) if line.trim() == "ply" => (),
+        _ => return Err(MeshError::parse(1, "missing 'ply' magic number")),
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for (index, line) in lines {
+        let line_number = index + 1;
+        let error = |message: String| MeshError::parse(line_number, message);
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("format") => {
+                format = match tokens.next() {
+                    Some("ascii") => Some(Format::Ascii),
+                    Some("binary_little_endian") => Some(Format::BinaryLittleEndian),
+                    other => {
+                        return Err(error(format!(
+                            "unsupported format '{}'",
+                            other.unwrap_or("")
+                        )))
+                    }
+                }
+            }
+            Some("element") => {
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| error("unnamed element".to_string()))?;
+                let count = tokens
+                    .next()
+                    .and_then(|count| count.parse().ok())
+                    .ok_or_else(|| error(format!("invalid count for element '{}'", name)))?;
+                elements.push(Element {
+                    name: name.to_string(),
+                    count: count,
+                    properties: vec![],
+                });
+            }
+            Some("property") => {
+                let parse_type = |name: Option<&str>| {
+                    let name = name.unwrap_or("");
+                    Type::parse(name).ok_or_else(|| error(format!("unknown type '{}'", name)))
+                };
+                let property = match tokens.next() {
+                    Some("list") => {
+                        let count_kind = parse_type(tokens.next())?;
+                        let kind = parse_type(tokens.next())?;
+                        let name = tokens.next().unwrap_or("").to_string();
+                        Property::List(name, count_kind, kind)
+                    }
+                    kind => {
+                        let kind = parse_type(kind)?;
+                        Property::Scalar(tokens.next().unwrap_or("").to_string(), kind)
+                    }
+                };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return Err(error("property before any element".to_string())),
+                }
+            }
+            Some("comment") | Some("obj_info") | None => (),
+            Some(keyword) => return Err(error(format!("unknown keyword '{}'", keyword))),
+        }
+    }
+
+    match format {
+        Some(format) => Ok((format, elements)),
+        None => Err(MeshError::parse(2, "missing 'format'")),
+    }
+}
+
+fn triangulate(indices: &[f64], vertex_count: usize, faces: &mut Vec<Face>) -> Result<(), String> {
+    if indices.len() < 3 {
+        return Err(format!("{} vertices, expected at least 3", indices.len()));
+    }
+    let indices = indices
+        .iter()
+        .map(|&index| {
+            if index >= 0.0 && (index as usize) < vertex_count {
+                Ok(index as usize)
+            } else {
+                Err(format!(
+                    "vertex index {} is out of range, {} defined",
+                    index, vertex_count
+                ))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for window in indices[1..].windows(2) {
+        faces.push(Face::new(indices[0], window[0], window[1]));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use ply::parse;
+    use prelude::*;
+
+    #[test]
+    fn should_parse_ascii_ply_with_vertex_colors() {
+        let source = "ply\nformat ascii 1.0\ncomment made by hand\n\
+                      element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                      property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                      element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                      0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n\
+                      4 0 1 2 3\n";
+
+        let mesh = parse(source.as_bytes()).unwrap();
+
+        let faces: Vec<_> = mesh.faces().iter().map(|face| face.vertices).collect();
+        assert_that!(faces, is(equal_to(vec![[0, 1, 2], [0, 2, 3]])));
+        assert_that!(mesh.vertices()[2], is(equal_to(Vec3::new(1.0, 1.0, 0.0))));
+        assert_that!(mesh.vertex_colors()[1], is(equal_to(Color::green())));
+    }
+
+    #[test]
+    fn should_parse_binary_little_endian_ply() {
+        let mut bytes = b"ply\nformat binary_little_endian 1.0\nelement vertex 3\n\
+                          property float x\nproperty float y\nproperty float z\n\
+                          element face 1\nproperty list uchar uint vertex_indices\nend_header\n"
+            .to_vec();
+        for &value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.push(3);
+        for &index in [0u32, 1, 2].iter() {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+
+        let mesh = parse(&bytes).unwrap();
+
+        assert_that!(mesh.vertices()[1], is(equal_to(Vec3::new(1.0, 0.0, 0.0))));
+        assert_that!(mesh.faces()[0].vertices, is(equal_to([0, 1, 2])));
+    }
+
+    #[test]
+    fn should_parse_faces_declared_before_vertices_with_ushort_colors() {
+        let source = "ply\nformat ascii 1.0\n\
+                      element face 1\nproperty list uchar int vertex_indices\n\
+                      element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                      property ushort red\nproperty ushort green\nproperty ushort blue\n\
+                      end_header\n\
+                      3 0 1 2\n\
+                      0 0 0 65535 0 0\n1 0 0 0 65535 0\n0 1 0 0 0 65535\n";
+
+        let mesh = parse(source.as_bytes()).unwrap();
+
+        assert_that!(mesh.faces()[0].vertices, is(equal_to([0, 1, 2])));
+        assert_that!(mesh.vertices()[2], is(equal_to(Vec3::new(0.0, 1.0, 0.0))));
+        assert_that!(mesh.vertex_colors()[1], is(equal_to(Color::green())));
+    }
+
+    #[test]
+    fn should_fail_on_truncated_or_malformed_files() {
+        let header = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\n\
+                      property float y\nproperty float z\nend_header\n";
+
+        assert_that!(
+            parse(format!("{}0 0 0\n", header).as_bytes()).is_err(),
+            is(true)
+        );
+        assert_that!(
+            parse(b"ply\nformat binary_big_endian 1.0\nend_header\n").is_err(),
+            is(true)
+        );
+        assert_that!(parse(b"not a ply file").is_err(), is(true));
+    }
+}
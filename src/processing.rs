@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::ops::{Add, Mul};
+
+use mesh::Face;
+use prelude::*;
+
+/// Cleanup and smoothing applied to a mesh, either when loading it with `Mesh::load` or with
+/// `apply`. Vertices are welded first, then the mesh is subdivided, and normals are generated
+/// last for the faces that have none.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Processing {
+    pub weld_tolerance: Option<f64>,
+    pub subdivisions: u32,
+    pub crease_angle: Option<f64>,
+}
+
+impl Processing {
+    pub fn new() -> Processing {
+        Processing::default()
+    }
+
+    /// Joins vertices closer than `tolerance` to each other, see `Mesh::welded`.
+    pub fn with_welding(self, tolerance: f64) -> Processing {
+        Processing {
+            weld_tolerance: Some(tolerance),
+            ..self
+        }
+    }
+
+    /// Applies this many levels of Loop subdivision, see `Mesh::subdivided`.
+    pub fn with_subdivisions(self, levels: u32) -> Processing {
+        Processing {
+            subdivisions: levels,
+            ..self
+        }
+    }
+
+    /// Generates smooth normals, see `Mesh::with_smooth_normals`.
+    pub fn with_smooth_normals(self, crease_angle: f64) -> Processing {
+        Processing {
+            crease_angle: Some(crease_angle),
+            ..self
+        }
+    }
+
+    pub fn apply(&self, mesh: &Mesh) -> Mesh {
+        let mut mesh = mesh.clone();
+        if let Some(tolerance) = self.weld_tolerance {
+            mesh = mesh.welded(tolerance);
+        }
+        if self.subdivisions > 0 {
+            mesh = mesh.subdivided(self.subdivisions);
+        }
+        if let Some(crease_angle) = self.crease_angle {
+            mesh = mesh.with_smooth_normals(crease_angle);
+        }
+        mesh
+    }
+}
+
+/// Finds the vertices that are within `tolerance` of an earlier vertex. Returns the indices of
+/// the vertices that are kept, and for every vertex the index among those it is joined into.
+pub(crate) fn weld(vertices: &[Vec3], tolerance: f64) -> (Vec<usize>, Vec<usize>) {
+    let size = tolerance.max(f64::EPSILON);
+    let cell = |vertex: Vec3| {
+        (
+            (vertex.x / size).floor() as i64,
+            (vertex.y / size).floor() as i64,
+            (vertex.z / size).floor() as i64,
+        )
+    };
+    let mut grid: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+    let mut kept: Vec<usize> = Vec::new();
+    let mut joined = Vec::with_capacity(vertices.len());
+    for (index, &vertex) in vertices.iter().enumerate() {
+        let (x, y, z) = cell(vertex);
+        let mut found = None;
+        'search: for dx in -1..2 {
+            for dy in -1..2 {
+                for dz in -1..2 {
+                    for &candidate in grid.get(&(x + dx, y + dy, z + dz)).into_iter().flatten() {
+                        if (vertices[kept[candidate]] - vertex).length() <= tolerance {
+                            found = Some(candidate);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+        joined.push(found.unwrap_or_else(|| {
+            kept.push(index);
+            grid.entry((x, y, z)).or_default().push(kept.len() - 1);
+            kept.len() - 1
+        }));
+    }
+    (kept, joined)
+}
+
+/// Normals for the corners of the faces that have none. The normal of a corner is the average
+/// of the normals of the faces around its vertex, weighted by their angle at the vertex, but
+/// only faces within `crease_angle` degrees of the face of the corner take part. Returns the
+/// normals with the new ones added after the given ones, and the faces referring to them.
+pub(crate) fn smooth_normals(
+    vertices: &[Vec3],
+    normals: &[Vec3],
+    faces: &[Face],
+    crease_angle: f64,
+) -> (Vec<Vec3>, Vec<Face>) {
+    let face_normals: Vec<Option<Vec3>> = faces
+        .iter()
+        .map(|face| {
+            let [a, b, c] = face.vertices;
+            let normal = (vertices[b] - vertices[a]).cross(vertices[c] - vertices[a]);
+            if normal.squared_length() > 0.0 {
+                Some(normal.normalize())
+            } else {
+                None
+            }
+        })
+        .collect();
+    let mut around: Vec<Vec<(usize, f64)>> = vec![vec![]; vertices.len()];
+    for (index, face) in faces.iter().enumerate() {
+        if face_normals[index].is_none() {
+            continue;
+        }
+        for corner in 0..3 {
+            let vertex = face.vertices[corner];
+            let next = vertices[face.vertices[(corner + 1) % 3]] - vertices[vertex];
+            let previous = vertices[face.vertices[(corner + 2) % 3]] - vertices[vertex];
+            let cos_angle = next.normalize().dot(previous.normalize());
+            around[vertex].push((index, cos_angle.clamp(-1.0, 1.0).acos()));
+        }
+    }
+
+    let min_cos = crease_angle.to_radians().cos();
+    let mut normals = normals.to_vec();
+    let mut indices: HashMap<[u64; 3], usize> = HashMap::new();
+    let faces = faces
+        .iter()
+        .enumerate()
+        .map(|(index, face)| {
+            let normal = match (face.normals, face_normals[index]) {
+                (None, Some(normal)) => normal,
+                _ => return *face,
+            };
+            let mut corners = [0; 3];
+            for (corner, &vertex) in corners.iter_mut().zip(face.vertices.iter()) {
+                let smooth = around[vertex]
+                    .iter()
+                    .filter_map(|&(other, angle)| {
+                        let other = face_normals[other].unwrap();
+                        if other.dot(normal) >= min_cos {
+                            Some(other * angle)
+                        } else {
+                            None
+                        }
+                    })
+                    .fold(Vec3::new(0.0, 0.0, 0.0), |sum, normal| sum + normal)
+                    .normalize();
+                let key = [smooth.x.to_bits(), smooth.y.to_bits(), smooth.z.to_bits()];
+                *corner = *indices.entry(key).or_insert_with(|| {
+                    normals.push(smooth);
+                    normals.len() - 1
+                });
+            }
+            Face {
+                normals: Some(corners),
+                ..*face
+            }
+        })
+        .collect();
+    (normals, faces)
+}
+
+fn edge(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+/// The neighbours of a vertex, and those of them that it shares a boundary edge with.
+struct Neighbours {
+    all: Vec<usize>,
+    boundary: Vec<usize>,
+}
+
+/// New position of an existing vertex, using the Loop weights inside of the mesh and the
+/// cubic B-spline weights along its boundary. Vertices where more than two boundary edges
+/// meet are corners and stay where they are.
+fn even<T>(values: &[T], vertex: usize, neighbours: &Neighbours) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f64, Output = T>,
+{
+    let value = values[vertex];
+    let sum = |vertices: &[usize]| {
+        vertices
+            .iter()
+            .fold(value * 0.0, |sum, &neighbour| sum + values[neighbour])
+    };
+    match neighbours.boundary.len() {
+        0 if !neighbours.all.is_empty() => {
+            let n = neighbours.all.len() as f64;
+            let beta = if neighbours.all.len() == 3 {
+                3.0 / 16.0
+            } else {
+                3.0 / (8.0 * n)
+            };
+            value * (1.0 - n * beta) + sum(&neighbours.all) * beta
+        }
+        2 => value * 0.75 + sum(&neighbours.boundary) * 0.125,
+        _ => value,
+    }
+}
+
+/// Position of the new vertex on an edge, from its end points and the corners opposite of it.
+fn odd<T>(values: &[T], (a, b): (usize, usize), opposite: &[usize]) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f64, Output = T>,
+{
+    match *opposite {
+        [c, d] => (values[a] + values[b]) * 0.375 + (values[c] + values[d]) * 0.125,
+        _ => (values[a] + values[b]) * 0.5,
+    }
+}
+
+/// The vertices, uvs, vertex colors and faces of a mesh.
+pub(crate) struct Buffers {
+    pub vertices: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Color>,
+    pub faces: Vec<Face>,
+}
+
+/// One level of Loop subdivision: every face is split into four, and the vertices are moved
+/// towards a smooth surface. Uvs are interpolated linearly within each face, and normals are
+/// dropped since they no longer fit the surface.
+pub(crate) fn loop_subdivide(mesh: &Buffers) -> Buffers {
+    let count = mesh.vertices.len();
+    let mut opposites: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for face in &mesh.faces {
+        let [a, b, c] = face.vertices;
+        for &(a, b, c) in [(a, b, c), (b, c, a), (c, a, b)].iter() {
+            opposites.entry(edge(a, b)).or_default().push(c);
+        }
+    }
+    let mut edges: Vec<(usize, usize)> = opposites.keys().cloned().collect();
+    edges.sort();
+
+    let mut neighbours: Vec<Neighbours> = (0..count)
+        .map(|_| Neighbours {
+            all: vec![],
+            boundary: vec![],
+        })
+        .collect();
+    for &(a, b) in &edges {
+        neighbours[a].all.push(b);
+        neighbours[b].all.push(a);
+        if opposites[&(a, b)].len() != 2 {
+            neighbours[a].boundary.push(b);
+            neighbours[b].boundary.push(a);
+        }
+    }
+    let middle: HashMap<(usize, usize), usize> = edges
+        .iter()
+        .enumerate()
+        .map(|(index, &edge)| (edge, count + index))
+        .collect();
+
+    let subdivide = |values: &[Vec3]| -> Vec<Vec3> {
+        (0..count)
+            .map(|vertex| even(values, vertex, &neighbours[vertex]))
+            .chain(
+                edges
+                    .iter()
+                    .map(|&edge| odd(values, edge, &opposites[&edge])),
+            )
+            .collect()
+    };
+    let vertices = subdivide(&mesh.vertices);
+    let colors = if mesh.colors.is_empty() {
+        vec![]
+    } else {
+        (0..count)
+            .map(|vertex| even(&mesh.colors, vertex, &neighbours[vertex]))
+            .chain(
+                edges
+                    .iter()
+                    .map(|&edge| odd(&mesh.colors, edge, &opposites[&edge])),
+            )
+            .collect()
+    };
+
+    let mut uvs = mesh.uvs.clone();
+    let mut faces = Vec::with_capacity(mesh.faces.len() * 4);
+    for face in &mesh.faces {
+        let [a, b, c] = face.vertices;
+        let (ab, bc, ca) = (
+            middle[&edge(a, b)],
+            middle[&edge(b, c)],
+            middle[&edge(c, a)],
+        );
+        let corners = [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]];
+        let uv_corners = face.uvs.map(|[a, b, c]| {
+            let start = uvs.len();
+            for &(p, q) in [(a, b), (b, c), (c, a)].iter() {
+                uvs.push(((uvs[p].0 + uvs[q].0) * 0.5, (uvs[p].1 + uvs[q].1) * 0.5));
+            }
+            let (ab, bc, ca) = (start, start + 1, start + 2);
+            [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+        });
+        for (index, &vertices) in corners.iter().enumerate() {
+            faces.push(Face {
+                vertices: vertices,
+                normals: None,
+                uvs: uv_corners.map(|uvs| uvs[index]),
+                material: face.material,
+            });
+        }
+    }
+    Buffers {
+        vertices: vertices,
+        uvs: uvs,
+        colors: colors,
+        faces: faces,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use mesh::Face;
+    use prelude::*;
+    use tests::assert_close;
+
+    fn cube() -> Mesh {
+        let vertices = (0..8)
+            .map(|i| {
+                let coordinate = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+                Vec3::new(coordinate(1), coordinate(2), coordinate(4))
+            })
+            .collect();
+        let sides = [
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+        ];
+        let faces = sides
+            .iter()
+            .flat_map(|&[a, b, c, d]| vec![Face::new(a, b, c), Face::new(a, c, d)])
+            .collect();
+        Mesh::new(vertices, faces, Material::Lambertian(Color::white()))
+    }
+
+    fn octahedron() -> Mesh {
+        let vertices = vec![
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        ];
+        let faces = vec![
+            Face::new(0, 2, 4),
+            Face::new(2, 1, 4),
+            Face::new(1, 3, 4),
+            Face::new(3, 0, 4),
+            Face::new(2, 0, 5),
+            Face::new(1, 2, 5),
+            Face::new(3, 1, 5),
+            Face::new(0, 3, 5),
+        ];
+        Mesh::new(vertices, faces, Material::Lambertian(Color::white()))
+    }
+
+    fn corner_normal(mesh: &Mesh, vertex: usize) -> Vec3 {
+        let face = mesh
+            .faces()
+            .iter()
+            .find(|face| face.vertices[0] == vertex)
+            .unwrap();
+        mesh.normals()[face.normals.unwrap()[0]]
+    }
+
+    #[test]
+    fn normals_should_be_smooth_below_the_crease_angle() {
+        let corner = Vec3::new(-1.0, -1.0, -1.0).normalize();
+
+        let hard = cube().with_smooth_normals(60.0);
+        let smooth = cube().with_smooth_normals(100.0);
+
+        assert_that!(hard.normals().len(), is(equal_to(6)));
+        assert_that!(smooth.normals().len(), is(equal_to(8)));
+        assert_that!(corner_normal(&hard, 0).dot(corner) < 0.6, is(true));
+        assert_close(corner_normal(&smooth, 0), corner);
+    }
+
+    #[test]
+    fn welding_should_join_close_vertices_and_drop_collapsed_faces() {
+        let mesh = Mesh::new(
+            vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(1.0, 0.0, 1e-6),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(0.0, 1.0, -1e-6),
+                Vec3::new(1.0, 1e-6, 0.0),
+            ],
+            vec![Face::new(0, 1, 2), Face::new(3, 4, 5), Face::new(1, 3, 6)],
+            Material::Lambertian(Color::white()),
+        );
+
+        let welded = mesh.welded(1e-4);
+
+        assert_that!(welded.vertices().len(), is(equal_to(4)));
+        assert_that!(welded.faces().len(), is(equal_to(2)));
+        assert_that!(welded.faces()[1].vertices, is(equal_to([1, 3, 2])));
+    }
+
+    #[test]
+    fn loop_subdivision_should_round_off_closed_meshes() {
+        let subdivided = octahedron().subdivided(1);
+
+        assert_that!(subdivided.faces().len(), is(equal_to(32)));
+        assert_that!(subdivided.vertices().len(), is(equal_to(18)));
+        assert_close(subdivided.vertices()[0], Vec3::new(0.625, 0.0, 0.0));
+        let middle = subdivided
+            .vertices()
+            .iter()
+            .any(|&vertex| (vertex - Vec3::new(0.375, 0.375, 0.0)).length() < 1e-9);
+        assert_that!(middle, is(true));
+    }
+
+    #[test]
+    fn loop_subdivision_should_keep_boundaries_and_uvs() {
+        let face = |a, b, c| Face {
+            uvs: Some([a, b, c]),
+            ..Face::new(a, b, c)
+        };
+        let quad = Mesh::with_buffers(
+            vec![
+                Vec3::new(-1.0, -1.0, 0.0),
+                Vec3::new(1.0, -1.0, 0.0),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::new(-1.0, 1.0, 0.0),
+            ],
+            vec![],
+            vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            vec![face(0, 1, 2), face(0, 2, 3)],
+            vec![],
+            Material::Lambertian(Color::white()),
+        );
+
+        let subdivided = quad.subdivided(1);
+        let hit = subdivided.intersects(
+            &Ray::new(Vec3::new(0.5, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0)),
+            0.0,
+            10.0,
+        );
+
+        assert_that!(subdivided.faces().len(), is(equal_to(8)));
+        assert_close(subdivided.vertices()[0], Vec3::new(-0.75, -0.75, 0.0));
+        assert_that!(subdivided.vertices().iter().all(|v| v.z == 0.0), is(true));
+        assert_that!(subdivided.uvs().len(), is(equal_to(10)));
+        assert_that!(hit.is_some(), is(true));
+    }
+
+    #[test]
+    fn processing_should_weld_before_generating_normals() {
+        let split = Mesh::new(
+            vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, -1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ],
+            vec![Face::new(0, 1, 2), Face::new(3, 4, 5)],
+            Material::Lambertian(Color::white()),
+        );
+
+        let unwelded = Processing::new().with_smooth_normals(180.0).apply(&split);
+        let welded = Processing::new()
+            .with_welding(1e-6)
+            .with_smooth_normals(180.0)
+            .apply(&split);
+
+        // The two shared vertices get the same normal, in between those of the faces.
+        assert_that!(unwelded.normals().len(), is(equal_to(2)));
+        assert_that!(welded.normals().len(), is(equal_to(3)));
+    }
+}
@@ -0,0 +1,601 @@
+use std::f64::consts::PI;
+
+use math;
+use prelude::*;
+use scene::Intersection;
+
+/// An orthonormal frame around the axis of a shape, used to find heights along the axis and
+/// angles around it.
+#[derive(Clone, Copy)]
+pub(crate) struct Frame {
+    pub origin: Vec3,
+    pub axis: Vec3,
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+}
+
+impl Frame {
+    pub fn new(origin: Vec3, axis: Vec3) -> Frame {
+        let axis = axis.normalize();
+        let helper = if axis.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let tangent = helper.cross(axis).normalize();
+        Frame {
+            origin: origin,
+            axis: axis,
+            tangent: tangent,
+            bitangent: axis.cross(tangent),
+        }
+    }
+
+    /// A frame around `axis` with the part of `tangent` that is perpendicular to the axis as
+    /// its tangent, or any tangent if that part is too small to have a direction.
+    pub fn with_tangent(origin: Vec3, axis: Vec3, tangent: Vec3) -> Frame {
+        let axis = axis.normalize();
+        let tangent = tangent - axis * tangent.dot(axis);
+        if tangent.squared_length() < 1e-12 {
+            return Frame::new(origin, axis);
+        }
+        let tangent = tangent.normalize();
+        Frame {
+            origin: origin,
+            axis: axis,
+            tangent: tangent,
+            bitangent: axis.cross(tangent),
+        }
+    }
+
+    /// The direction in which `turn` increases at `point`.
+    pub fn around(&self, point: Vec3) -> Vec3 {
+        self.axis.cross(self.radial(point))
+    }
+
+    pub fn height(&self, point: Vec3) -> f64 {
+        (point - self.origin).dot(self.axis)
+    }
+
+    /// The part of `point` that is perpendicular to the axis.
+    pub fn radial(&self, point: Vec3) -> Vec3 {
+        let offset = point - self.origin;
+        offset - self.axis * offset.dot(self.axis)
+    }
+
+    /// The angle around the axis, scaled to lie between 0 and 1.
+    pub fn turn(&self, point: Vec3) -> f64 {
+        let offset = point - self.origin;
+        (offset.dot(self.bitangent).atan2(offset.dot(self.tangent)) + PI) / (2.0 * PI)
+    }
+
+    /// The coordinates of `vec` along the tangent, the axis and the bitangent.
+    pub fn local(&self, vec: Vec3) -> Vec3 {
+        Vec3::new(
+            vec.dot(self.tangent),
+            vec.dot(self.axis),
+            vec.dot(self.bitangent),
+        )
+    }
+
+    pub fn world(&self, vec: Vec3) -> Vec3 {
+        self.tangent * vec.x + self.axis * vec.y + self.bitangent * vec.z
+    }
+
+    pub fn moved_to(&self, origin: Vec3) -> Frame {
+        Frame {
+            origin: origin,
+            ..*self
+        }
+    }
+}
+
+/// The box around a disc, which only extends along the axes the disc is tilted away from.
+fn disc_bounds(center: Vec3, normal: Vec3, radius: f64) -> Aabb {
+    let extent = |n: f64| radius * (1.0 - n * n).max(0.0).sqrt() + 1e-4;
+    let extent = Vec3::new(extent(normal.x), extent(normal.y), extent(normal.z));
+    Aabb::new(center - extent, center + extent)
+}
+
+fn sphere_bounds(center: Vec3, radius: f64) -> Aabb {
+    let extent = Vec3::new(radius, radius, radius);
+    Aabb::new(center - extent, center + extent)
+}
+
+/// A flat, round disc facing in the direction of `normal`.
+#[derive(Clone)]
+pub struct Disc {
+    pub radius: f64,
+    pub material: Material,
+    frame: Frame,
+}
+
+impl Disc {
+    pub fn new(center: Vec3, normal: Vec3, radius: f64, material: Material) -> Disc {
+        Disc {
+            radius: radius,
+            material: material,
+            frame: Frame::new(center, normal),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.frame.origin
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.frame.axis
+    }
+}
+
+impl Intersectable for Disc {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let denominator = self.frame.axis.dot(ray.direction);
+        if denominator == 0.0 {
+            return None;
+        }
+        let delta = -self.frame.height(ray.origin) / denominator;
+        if !(delta < t_max && delta > t_min) {
+            return None;
+        }
+        let intersection_point = ray.point_along_direction(delta);
+        let offset = (intersection_point - self.frame.origin) / self.radius;
+        if offset.squared_length() > 1.0 {
+            return None;
+        }
+        let intersection = Intersection::new(
+            delta,
+            intersection_point,
+            self.frame.axis,
+            Box::new(self.clone()),
+        );
+        let (u, v) = (
+            (offset.dot(self.frame.tangent) + 1.0) / 2.0,
+            (offset.dot(self.frame.bitangent) + 1.0) / 2.0,
+        );
+        Some(
+            intersection
+                .with_uv(u, v)
+                .with_tangent(self.frame.tangent)
+                .with_material(self.material.clone()),
+        )
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        Box::new(Disc {
+            frame: self.frame.moved_to(vec),
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(disc_bounds(self.frame.origin, self.frame.axis, self.radius))
+    }
+}
+
+/// A cylinder standing on the disc at `base`, reaching `height` along `axis`. The ends are
+/// closed by caps unless they are turned off, which leaves an open tube.
+#[derive(Clone)]
+pub struct Cylinder {
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+    caps: bool,
+    frame: Frame,
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3, axis: Vec3, radius: f64, height: f64, material: Material) -> Cylinder {
+        Cylinder {
+            radius: radius,
+            height: height,
+            material: material,
+            caps: true,
+            frame: Frame::new(base, axis),
+        }
+    }
+
+    pub fn with_caps(self, caps: bool) -> Cylinder {
+        Cylinder { caps: caps, ..self }
+    }
+
+    fn top(&self) -> Vec3 {
+        self.frame.origin + self.frame.axis * self.height
+    }
+
+    fn cap_discs(&self) -> Vec<Disc> {
+        if !self.caps {
+            return vec![];
+        }
+        vec![
+            Disc::new(
+                self.frame.origin,
+                self.frame.axis.invert(),
+                self.radius,
+                self.material.clone(),
+            ),
+            Disc::new(
+                self.top(),
+                self.frame.axis,
+                self.radius,
+                self.material.clone(),
+            ),
+        ]
+    }
+
+    fn create_intersection(&self, delta: f64, ray: &Ray) -> Intersection {
+        let intersection_point = ray.point_along_direction(delta);
+        let normal = self.frame.radial(intersection_point) / self.radius;
+        Intersection::new(delta, intersection_point, normal, Box::new(self.clone()))
+            .with_uv(
+                self.frame.turn(intersection_point),
+                self.frame.height(intersection_point) / self.height,
+            )
+            .with_tangent(self.frame.around(intersection_point))
+            .with_material(self.material.clone())
+    }
+}
+
+impl Intersectable for Cylinder {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let origin = self.frame.radial(ray.origin);
+        let direction = ray.direction - self.frame.axis * ray.direction.dot(self.frame.axis);
+        let a: f64 = direction.dot(direction);
+        let b: f64 = origin.dot(direction);
+        let c: f64 = origin.dot(origin) - self.radius * self.radius;
+
+        let mut intersection: Option<Intersection> = None;
+        let mut closest_so_far: f64 = t_max;
+        for delta in math::solve_quadratic(a, 2.0 * b, c) {
+            let height = self.frame.height(ray.point_along_direction(delta));
+            if delta < closest_so_far && delta > t_min && height >= 0.0 && height <= self.height {
+                closest_so_far = delta;
+                intersection = Some(self.create_intersection(delta, ray));
+                break;
+            }
+        }
+        for cap in self.cap_discs() {
+            if let Some(other_intersection) = cap.intersects(ray, t_min, closest_so_far) {
+                closest_so_far = other_intersection.distance;
+                intersection = Some(other_intersection);
+            }
+        }
+        intersection
+    }
+
+    /// Moves the center of the cylinder to `vec`.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        Box::new(Cylinder {
+            frame: self
+                .frame
+                .moved_to(vec - self.frame.axis * (self.height / 2.0)),
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let base = disc_bounds(self.frame.origin, self.frame.axis, self.radius);
+        let top = disc_bounds(self.top(), self.frame.axis, self.radius);
+        Some(base.union(&top))
+    }
+}
+
+/// A cone with its base on the disc at `base`, narrowing to a point at `height` along `axis`.
+/// The base is closed by a cap unless it is turned off.
+#[derive(Clone)]
+pub struct Cone {
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+    caps: bool,
+    frame: Frame,
+}
+
+impl Cone {
+    pub fn new(base: Vec3, axis: Vec3, radius: f64, height: f64, material: Material) -> Cone {
+        Cone {
+            radius: radius,
+            height: height,
+            material: material,
+            caps: true,
+            frame: Frame::new(base, axis),
+        }
+    }
+
+    pub fn with_caps(self, caps: bool) -> Cone {
+        Cone { caps: caps, ..self }
+    }
+
+    fn apex(&self) -> Vec3 {
+        self.frame.origin + self.frame.axis * self.height
+    }
+
+    fn create_intersection(&self, delta: f64, ray: &Ray) -> Intersection {
+        let intersection_point = ray.point_along_direction(delta);
+        let slope = self.radius / self.height;
+        let height = self.frame.height(intersection_point);
+        let normal = (self.frame.radial(intersection_point).normalize() + self.frame.axis * slope)
+            .normalize();
+        Intersection::new(delta, intersection_point, normal, Box::new(self.clone()))
+            .with_uv(self.frame.turn(intersection_point), height / self.height)
+            .with_tangent(self.frame.around(intersection_point))
+            .with_material(self.material.clone())
+    }
+}
+
+impl Intersectable for Cone {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        // Relative to the apex, the surface is where the distance from the axis equals the
+        // slope times the distance along it.
+        let slope = self.radius / self.height;
+        let k = 1.0 + slope * slope;
+        let translated_origin = ray.origin - self.apex();
+        let (origin_along, direction_along) = (
+            translated_origin.dot(self.frame.axis),
+            ray.direction.dot(self.frame.axis),
+        );
+        let a: f64 = ray.direction.dot(ray.direction) - k * direction_along * direction_along;
+        let b: f64 = translated_origin.dot(ray.direction) - k * origin_along * direction_along;
+        let c: f64 = translated_origin.dot(translated_origin) - k * origin_along * origin_along;
+
+        let mut intersection: Option<Intersection> = None;
+        let mut closest_so_far: f64 = t_max;
+        for delta in math::solve_quadratic(a, 2.0 * b, c) {
+            let height = self.frame.height(ray.point_along_direction(delta));
+            if delta < closest_so_far && delta > t_min && height >= 0.0 && height <= self.height {
+                closest_so_far = delta;
+                intersection = Some(self.create_intersection(delta, ray));
+                break;
+            }
+        }
+        if self.caps {
+            let cap = Disc::new(
+                self.frame.origin,
+                self.frame.axis.invert(),
+                self.radius,
+                self.material.clone(),
+            );
+            if let Some(other_intersection) = cap.intersects(ray, t_min, closest_so_far) {
+                intersection = Some(other_intersection);
+            }
+        }
+        intersection
+    }
+
+    /// Moves the center of the base to `vec`.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        Box::new(Cone {
+            frame: self.frame.moved_to(vec),
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(disc_bounds(self.frame.origin, self.frame.axis, self.radius).expand(self.apex()))
+    }
+}
+
+/// A cylinder between the points `a` and `b`, rounded off by half spheres at both ends.
+#[derive(Clone)]
+pub struct Capsule {
+    pub radius: f64,
+    pub material: Material,
+    length: f64,
+    frame: Frame,
+}
+
+impl Capsule {
+    pub fn new(a: Vec3, b: Vec3, radius: f64, material: Material) -> Capsule {
+        Capsule {
+            radius: radius,
+            material: material,
+            length: (b - a).length(),
+            frame: Frame::new(a, b - a),
+        }
+    }
+
+    fn end(&self) -> Vec3 {
+        self.frame.origin + self.frame.axis * self.length
+    }
+
+    fn create_intersection(&self, delta: f64, ray: &Ray, center: Vec3) -> Intersection {
+        let intersection_point = ray.point_along_direction(delta);
+        let normal = (intersection_point - center) / self.radius;
+        let height = self.frame.height(intersection_point) + self.radius;
+        Intersection::new(delta, intersection_point, normal, Box::new(self.clone()))
+            .with_uv(
+                self.frame.turn(intersection_point),
+                height / (self.length + 2.0 * self.radius),
+            )
+            .with_tangent(self.frame.around(intersection_point))
+            .with_material(self.material.clone())
+    }
+}
+
+impl Intersectable for Capsule {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let origin = self.frame.radial(ray.origin);
+        let direction = ray.direction - self.frame.axis * ray.direction.dot(self.frame.axis);
+        let a: f64 = direction.dot(direction);
+        let b: f64 = origin.dot(direction);
+        let c: f64 = origin.dot(origin) - self.radius * self.radius;
+        let mut candidates = Vec::new();
+        for delta in math::solve_quadratic(a, 2.0 * b, c) {
+            candidates.push((delta, None));
+        }
+
+        for &center in [self.frame.origin, self.end()].iter() {
+            let translated_origin = ray.origin - center;
+            let a: f64 = ray.direction.dot(ray.direction);
+            let b: f64 = translated_origin.dot(ray.direction);
+            let c: f64 = translated_origin.dot(translated_origin) - self.radius * self.radius;
+            for delta in math::solve_quadratic(a, 2.0 * b, c) {
+                candidates.push((delta, Some(center)));
+            }
+        }
+
+        // Each part only counts where it makes up the surface of the capsule: the side between
+        // the two end points, and each half sphere beyond its own end.
+        candidates
+            .into_iter()
+            .filter(|&(delta, center)| {
+                let height = self.frame.height(ray.point_along_direction(delta));
+                let on_surface = match center {
+                    None => height >= 0.0 && height <= self.length,
+                    Some(center) if center == self.frame.origin => height < 0.0,
+                    Some(_) => height > self.length,
+                };
+                delta < t_max && delta > t_min && on_surface
+            })
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .map(|(delta, center)| {
+                let center = center.unwrap_or_else(|| {
+                    self.frame.origin
+                        + self.frame.axis * self.frame.height(ray.point_along_direction(delta))
+                });
+                self.create_intersection(delta, ray, center)
+            })
+    }
+
+    /// Moves the point halfway between the two ends to `vec`.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        Box::new(Capsule {
+            frame: self
+                .frame
+                .moved_to(vec - self.frame.axis * (self.length / 2.0)),
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let a = sphere_bounds(self.frame.origin, self.radius);
+        let b = sphere_bounds(self.end(), self.radius);
+        Some(a.union(&b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+    use tests::assert_close;
+
+    fn material() -> Material {
+        Material::Lambertian(Color::white())
+    }
+
+    fn distances(shape: &dyn Intersectable, ray: &Ray) -> Vec<f64> {
+        shape
+            .intersections(ray, 0.0, 100.0)
+            .iter()
+            .map(|intersection| intersection.distance)
+            .collect()
+    }
+
+    fn upright_cylinder() -> Cylinder {
+        Cylinder::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            2.0,
+            material(),
+        )
+    }
+
+    #[test]
+    fn ray_should_hit_the_side_of_a_cylinder() {
+        let ray = Ray::new(Vec3::new(-5.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let i = upright_cylinder().intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(i.distance, is(equal_to(4.0)));
+        assert_close(i.normal, Vec3::new(-1.0, 0.0, 0.0));
+        assert_that!(i.uv.1, is(equal_to(0.5)));
+        assert_that!(
+            distances(&upright_cylinder(), &ray),
+            is(equal_to(vec![4.0, 6.0]))
+        );
+    }
+
+    #[test]
+    fn ray_should_hit_the_caps_of_a_cylinder_unless_it_is_open() {
+        let ray = Ray::new(Vec3::new(0.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        let i = upright_cylinder().intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(i.distance, is(equal_to(3.0)));
+        assert_close(i.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert_that!(
+            distances(&upright_cylinder(), &ray),
+            is(equal_to(vec![3.0, 5.0]))
+        );
+        assert_that!(
+            upright_cylinder()
+                .with_caps(false)
+                .intersects(&ray, 0.0, 100.0)
+                .is_none(),
+            is(true)
+        );
+    }
+
+    #[test]
+    fn ray_should_only_hit_the_finite_part_of_a_cone() {
+        let cone = Cone::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            1.0,
+            material(),
+        );
+        let through_the_middle = Ray::new(Vec3::new(-5.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let above_the_apex = Ray::new(Vec3::new(-5.0, 1.5, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let i = cone.intersects(&through_the_middle, 0.0, 100.0).unwrap();
+
+        assert_that!(i.distance, is(equal_to(4.5)));
+        assert_close(i.normal, Vec3::new(-1.0, 1.0, 0.0) / 2.0f64.sqrt());
+        assert_that!(
+            cone.intersects(&above_the_apex, 0.0, 100.0).is_none(),
+            is(true)
+        );
+    }
+
+    #[test]
+    fn ray_should_hit_a_disc_inside_of_its_radius() {
+        let disc = Disc::new(
+            Vec3::new(0.0, 0.0, -2.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            1.0,
+            material(),
+        );
+        let hit = Ray::new(Vec3::new(0.5, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let miss = Ray::new(Vec3::new(0.8, 0.8, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_that!(
+            disc.intersects(&hit, 0.0, 100.0).unwrap().distance,
+            is(equal_to(2.0))
+        );
+        assert_that!(disc.intersects(&miss, 0.0, 100.0).is_none(), is(true));
+    }
+
+    #[test]
+    fn ray_along_the_axis_should_hit_the_rounded_ends_of_a_capsule() {
+        let capsule = Capsule::new(
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.5,
+            material(),
+        );
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        let i = capsule.intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(i.distance, is(equal_to(3.5)));
+        assert_close(i.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert_that!(distances(&capsule, &ray), is(equal_to(vec![3.5, 6.5])));
+        assert_that!(
+            capsule.bounding_box().unwrap().max,
+            is(equal_to(Vec3::new(0.5, 1.5, 0.5)))
+        );
+    }
+}
@@ -4,6 +4,8 @@ use vec::Vec3;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// When the ray is sent out, for shapes that move while the shutter of the camera is open.
+    pub time: f64,
 }
 
 impl Ray {
@@ -11,9 +13,14 @@ impl Ray {
         Ray {
             origin: origin,
             direction: direction,
+            time: 0.0,
         }
     }
 
+    pub fn with_time(self, time: f64) -> Ray {
+        Ray { time: time, ..self }
+    }
+
     pub fn point_along_direction(&self, delta: f64) -> Vec3 {
         panic!(
             "Step 3a) Calculate the point along the direction of the ray. Hint: Remember to \
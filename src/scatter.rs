@@ -1,31 +1,105 @@
-use bmp;
 use rand::{self, Rng};
+use std::f64::consts::PI;
 
 use color::Color;
+use microfacet::{self, Ggx};
 use ray::Ray;
 use scene::*;
 use vec::Vec3;
 
 const INTERSECTION_ORIGIN_OFFSET: f64 = 0.00000001;
+/// How often light is reflected off the surface of a hair fiber, and how often it passes
+/// straight through it. The rest is reflected off the inside of the fiber.
+const HAIR_REFLECTION: f64 = 0.2;
+const HAIR_TRANSMISSION: f64 = 0.5;
+/// The number of steps taken around the cones of the hair lobes when evaluating them.
+const HAIR_CONE_STEPS: usize = 64;
+
+// Every scatter function comes with a `_pdf` function, the density per solid angle of it
+// scattering the ray in `direction`, and an `_eval` function, the color it scatters in
+// `direction` times the cosine to the normal. Dividing the one by the other gives the color
+// returned when that direction is picked. Surfaces that only scatter in a few exact
+// directions, like mirrors and glass, can't be evaluated for any other direction, so their
+// density is zero and their color is black.
 
 pub fn diffusive(attenuation: Color, intersection: &Intersection) -> Option<(Color, Ray)> {
     Some((attenuation, scatter_ray(intersection)))
 }
 
+pub fn diffusive_pdf(intersection: &Intersection, direction: Vec3) -> f64 {
+    direction.normalize().dot(intersection.normal).max(0.0) / PI
+}
+
+pub fn diffusive_eval(attenuation: Color, intersection: &Intersection, direction: Vec3) -> Color {
+    attenuation * diffusive_pdf(intersection, direction)
+}
+
+/// Reflection off a metal of the color `attenuation`, with facets spread by the GGX
+/// distribution where `diffusiveness` is the roughness. No diffusiveness gives a mirror.
 pub fn reflection(
     attenuation: Color,
     diffusiveness: f64,
     ray: &Ray,
     intersection: &Intersection,
 ) -> Option<(Color, Ray)> {
-    let reflected =
-        reflect(ray.direction, intersection.normal) + diffusiveness * random_point_in_unit_sphere();
-    let origin = reflection_origin(intersection);
-    if reflected.dot(intersection.normal) > 0.0 {
-        Some((attenuation, Ray::new(origin, reflected)))
-    } else {
-        None
-    }
+    let fresnel = |cosine| microfacet::schlick_fresnel(attenuation, cosine);
+    microfacet(fresnel, (diffusiveness, diffusiveness), ray, intersection)
+}
+
+pub fn reflection_pdf(
+    diffusiveness: f64,
+    ray: &Ray,
+    intersection: &Intersection,
+    direction: Vec3,
+) -> f64 {
+    microfacet_pdf((diffusiveness, diffusiveness), ray, intersection, direction)
+}
+
+pub fn reflection_eval(
+    attenuation: Color,
+    diffusiveness: f64,
+    ray: &Ray,
+    intersection: &Intersection,
+    direction: Vec3,
+) -> Color {
+    let fresnel = |cosine| microfacet::schlick_fresnel(attenuation, cosine);
+    let roughness = (diffusiveness, diffusiveness);
+    microfacet_eval(fresnel, roughness, ray, intersection, direction)
+}
+
+/// Reflection off a metal with the complex index of refraction `eta + ik`, which gives the
+/// color of the metal and how it changes with the angle. The roughness is given along the
+/// tangent and the bitangent around the normal, and may differ between them.
+pub fn conductor(
+    eta: Color,
+    k: Color,
+    roughness: (f64, f64),
+    ray: &Ray,
+    intersection: &Intersection,
+) -> Option<(Color, Ray)> {
+    let fresnel = |cosine| microfacet::conductor_fresnel(eta, k, cosine);
+    microfacet(fresnel, roughness, ray, intersection)
+}
+
+pub fn conductor_pdf(
+    roughness: (f64, f64),
+    ray: &Ray,
+    intersection: &Intersection,
+    direction: Vec3,
+) -> f64 {
+    microfacet_pdf(roughness, ray, intersection, direction)
+}
+
+pub fn conductor_eval(
+    eta: Color,
+    k: Color,
+    roughness: (f64, f64),
+    ray: &Ray,
+    intersection: &Intersection,
+    direction: Vec3,
+) -> Color {
+    let fresnel = |cosine| microfacet::conductor_fresnel(eta, k, cosine);
+    microfacet_eval(fresnel, roughness, ray, intersection, direction)
 }
 
 pub fn refraction(
@@ -66,22 +140,267 @@ pub fn refraction(
     }
 }
 
-pub fn texture(texture: &bmp::Image, intersection: &Intersection) -> Option<(Color, Ray)> {
-    panic!(
-        "Step 6b) Calculate the (u, v) coordinates of the surface normal in the intersection, \
-            similarily to how you did it in Step 5. Then, convert the respective pixel from the \
-            texture to a Color. You can use the scatter_ray() function below to calculate the Ray."
+pub fn refraction_pdf(_: f64, _: &Ray, _: &Intersection, _: Vec3) -> f64 {
+    0.0
+}
+
+pub fn refraction_eval(_: f64, _: &Ray, _: &Intersection, _: Vec3) -> Color {
+    Color::black()
+}
+
+/// Lets the ray pass through the surface unchanged, for surfaces that only mark where
+/// something else begins.
+pub fn transmission(ray: &Ray, intersection: &Intersection) -> Option<(Color, Ray)> {
+    let origin = if ray.direction.dot(intersection.normal) < 0.0 {
+        refraction_origin(intersection.intersection_point, intersection.normal)
+    } else {
+        reflection_origin(intersection)
+    };
+    Some((Color::white(), Ray::new(origin, ray.direction)))
+}
+
+pub fn transmission_pdf(_: &Ray, _: &Intersection, _: Vec3) -> f64 {
+    0.0
+}
+
+pub fn transmission_eval(_: &Ray, _: &Intersection, _: Vec3) -> Color {
+    Color::black()
+}
+
+/// Scattering from a hair fiber going along `tangent`, split into the three lobes of the
+/// Marschner model. Light reflected off the fiber keeps its color and leaves on the cone around
+/// the tangent that mirrors the incoming direction. Light that passes through the fiber goes on
+/// forward, and light reflected off the inside of it comes back out on the cone, and both of
+/// them are colored by the fiber. The `roughness` widens every lobe.
+pub fn hair(
+    attenuation: Color,
+    roughness: f64,
+    tangent: Vec3,
+    ray: &Ray,
+    intersection: &Intersection,
+) -> Option<(Color, Ray)> {
+    let mut rng = rand::thread_rng();
+    let fiber = Fiber::new(tangent, ray, intersection);
+
+    let lobe = rng.next_f64();
+    let (color, scattered) = if lobe < HAIR_REFLECTION {
+        let angle = (rng.next_f64() - 0.5) * PI;
+        (Color::white(), fiber.cone(angle))
+    } else if lobe < HAIR_REFLECTION + HAIR_TRANSMISSION {
+        (attenuation, fiber.direction)
+    } else {
+        let angle = rng.next_f64() * 2.0 * PI;
+        (attenuation * attenuation, fiber.cone(angle))
+    };
+    let scattered = (scattered + roughness * random_point_in_unit_sphere()).normalize();
+
+    let origin = if scattered.dot(intersection.normal) > 0.0 {
+        reflection_origin(intersection)
+    } else {
+        refraction_origin(intersection.intersection_point, intersection.normal)
+    };
+    Some((color, Ray::new(origin, scattered)))
+}
+
+/// The lobes on the cones are summed up in steps around the cones, as they have no closed
+/// form. Without roughness every lobe is infinitely thin, and can't be evaluated.
+pub fn hair_pdf(
+    roughness: f64,
+    tangent: Vec3,
+    ray: &Ray,
+    intersection: &Intersection,
+    direction: Vec3,
+) -> f64 {
+    let (reflected, transmitted, internal) =
+        hair_lobes(roughness, tangent, ray, intersection, direction);
+    HAIR_REFLECTION * reflected
+        + HAIR_TRANSMISSION * transmitted
+        + (1.0 - HAIR_REFLECTION - HAIR_TRANSMISSION) * internal
+}
+
+pub fn hair_eval(
+    attenuation: Color,
+    roughness: f64,
+    tangent: Vec3,
+    ray: &Ray,
+    intersection: &Intersection,
+    direction: Vec3,
+) -> Color {
+    let (reflected, transmitted, internal) =
+        hair_lobes(roughness, tangent, ray, intersection, direction);
+    Color::white() * (HAIR_REFLECTION * reflected)
+        + attenuation * (HAIR_TRANSMISSION * transmitted)
+        + attenuation * attenuation * ((1.0 - HAIR_REFLECTION - HAIR_TRANSMISSION) * internal)
+}
+
+/// The densities of the three lobes of `hair` in `direction`.
+fn hair_lobes(
+    roughness: f64,
+    tangent: Vec3,
+    ray: &Ray,
+    intersection: &Intersection,
+    direction: Vec3,
+) -> (f64, f64, f64) {
+    if roughness <= 0.0 {
+        return (0.0, 0.0, 0.0);
+    }
+    let fiber = Fiber::new(tangent, ray, intersection);
+    let direction = direction.normalize();
+    let around_cone = |start: f64, width: f64| {
+        let step = width / HAIR_CONE_STEPS as f64;
+        let sum: f64 = (0..HAIR_CONE_STEPS)
+            .map(|i| fiber.cone(start + (i as f64 + 0.5) * step))
+            .map(|center| ball_pdf(center, roughness, direction))
+            .sum();
+        sum / HAIR_CONE_STEPS as f64
+    };
+    (
+        around_cone(-0.5 * PI, PI),
+        ball_pdf(fiber.direction, roughness, direction),
+        around_cone(0.0, 2.0 * PI),
     )
 }
 
+/// The directions around a hair fiber that light hitting it may leave along.
+struct Fiber {
+    tangent: Vec3,
+    direction: Vec3,
+    along: f64,
+    across: Vec3,
+    around: Vec3,
+}
+
+impl Fiber {
+    fn new(tangent: Vec3, ray: &Ray, intersection: &Intersection) -> Fiber {
+        let tangent = tangent.normalize();
+        let direction = ray.direction.normalize();
+
+        // The fiber is round, so light bounced off of it keeps going along the tangent at the
+        // same rate, but may leave in any direction around it.
+        let along = direction.dot(tangent);
+        let across = direction - tangent * along;
+        let across = if across.squared_length() > 0.0 {
+            across.normalize()
+        } else {
+            intersection.normal.invert()
+        };
+        Fiber {
+            tangent: tangent,
+            direction: direction,
+            along: along,
+            across: across,
+            around: tangent.cross(across),
+        }
+    }
+
+    /// The direction at `angle` around the cone that mirrors the incoming direction.
+    fn cone(&self, angle: f64) -> Vec3 {
+        let perpendicular = self.across.invert() * angle.cos() + self.around * angle.sin();
+        self.tangent * self.along + perpendicular * (1.0 - self.along * self.along).max(0.0).sqrt()
+    }
+}
+
+// Offsetting by a point on the unit sphere, rather than inside it, spreads the rays by the
+// cosine to the normal, like a true Lambertian surface.
 fn scatter_ray(intersection: &Intersection) -> Ray {
-    let target =
-        intersection.intersection_point + intersection.normal + random_point_in_unit_sphere();
+    let target = intersection.intersection_point
+        + intersection.normal
+        + random_point_in_unit_sphere().normalize();
     let origin = reflection_origin(intersection);
     let direction = (target - origin).normalize();
     Ray::new(origin, direction)
 }
 
+/// Reflects the ray off a facet picked among those that can be seen from it, colored by the
+/// `fresnel` reflectance at the cosine between the ray and the facet. Light that is reflected
+/// into the surface, or onto facets hidden from it, is lost.
+fn microfacet<F: Fn(f64) -> Color>(
+    fresnel: F,
+    roughness: (f64, f64),
+    ray: &Ray,
+    intersection: &Intersection,
+) -> Option<(Color, Ray)> {
+    let origin = reflection_origin(intersection);
+    if Ggx::is_smooth(roughness) {
+        let reflected = reflect(ray.direction, intersection.normal);
+        let cosine = reflected.dot(intersection.normal);
+        return if cosine > 0.0 {
+            Some((fresnel(cosine), Ray::new(origin, reflected)))
+        } else {
+            None
+        };
+    }
+    let ggx = Ggx::new(roughness, intersection.normal, intersection.tangent);
+    let outgoing = ggx.local(ray.direction.invert());
+    if outgoing.y <= 0.0 {
+        return None;
+    }
+    let facet = ggx.sample_normal(outgoing);
+    let cosine = outgoing.dot(facet);
+    let incoming = facet * (2.0 * cosine) - outgoing;
+    if incoming.y <= 0.0 {
+        return None;
+    }
+    let visible = ggx.shadowing(outgoing, incoming) / ggx.masking(outgoing);
+    Some((
+        fresnel(cosine) * visible,
+        Ray::new(origin, ggx.world(incoming)),
+    ))
+}
+
+fn microfacet_pdf(
+    roughness: (f64, f64),
+    ray: &Ray,
+    intersection: &Intersection,
+    direction: Vec3,
+) -> f64 {
+    if Ggx::is_smooth(roughness) {
+        return 0.0;
+    }
+    let ggx = Ggx::new(roughness, intersection.normal, intersection.tangent);
+    let (outgoing, incoming) = (ggx.local(ray.direction.invert()), ggx.local(direction));
+    if outgoing.y <= 0.0 || incoming.y <= 0.0 {
+        return 0.0;
+    }
+    let facet = (outgoing + incoming).normalize();
+    ggx.masking(outgoing) * ggx.distribution(facet) / (4.0 * outgoing.y)
+}
+
+fn microfacet_eval<F: Fn(f64) -> Color>(
+    fresnel: F,
+    roughness: (f64, f64),
+    ray: &Ray,
+    intersection: &Intersection,
+    direction: Vec3,
+) -> Color {
+    if Ggx::is_smooth(roughness) {
+        return Color::black();
+    }
+    let ggx = Ggx::new(roughness, intersection.normal, intersection.tangent);
+    let (outgoing, incoming) = (ggx.local(ray.direction.invert()), ggx.local(direction));
+    if outgoing.y <= 0.0 || incoming.y <= 0.0 {
+        return Color::black();
+    }
+    let facet = (outgoing + incoming).normalize();
+    let visible = ggx.distribution(facet) * ggx.shadowing(outgoing, incoming);
+    fresnel(outgoing.dot(facet)) * (visible / (4.0 * outgoing.y))
+}
+
+/// The density per solid angle of the direction towards a point picked evenly within the
+/// ball around `center`, seen from the origin. This is how far the ray in `direction` goes
+/// through the ball, weighted by the squared distance.
+fn ball_pdf(center: Vec3, radius: f64, direction: Vec3) -> f64 {
+    let direction = direction.normalize();
+    let b = direction.dot(center);
+    let discriminant = b * b - center.squared_length() + radius * radius;
+    if discriminant <= 0.0 {
+        return 0.0;
+    }
+    let near = (b - discriminant.sqrt()).max(0.0);
+    let far = (b + discriminant.sqrt()).max(0.0);
+    (far.powi(3) - near.powi(3)) / (4.0 * PI * radius.powi(3))
+}
+
 fn random_point_in_unit_sphere() -> Vec3 {
     let mut rng = rand::thread_rng();
     loop {
@@ -93,7 +412,7 @@ fn random_point_in_unit_sphere() -> Vec3 {
     }
 }
 
-fn reflection_origin(intersection: &Intersection) -> Vec3 {
+pub(crate) fn reflection_origin(intersection: &Intersection) -> Vec3 {
     intersection.intersection_point + intersection.normal * INTERSECTION_ORIGIN_OFFSET
 }
 
@@ -121,3 +440,121 @@ fn shlick_approximation(cosine: f64, refraction_index: f64) -> f64 {
     let r0 = r0 * r0;
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+    use rand::{self, Rng};
+    use scene::Intersection;
+    use std::f64::consts::PI;
+
+    fn hit(ray: &Ray) -> Intersection {
+        let floor = Rect::xz(
+            (-1.0, 1.0),
+            (-1.0, 1.0),
+            0.0,
+            Material::Lambertian(Color::white()),
+        );
+        floor.intersects(ray, 0.0, 100.0).unwrap()
+    }
+
+    fn down() -> Ray {
+        Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.3, -1.0, 0.0))
+    }
+
+    /// Sums up `pdf` over every direction, by averaging it over directions picked evenly.
+    fn integrate<F: Fn(Vec3) -> f64>(pdf: F) -> f64 {
+        let mut rng = rand::thread_rng();
+        let samples = 100000;
+        let sum: f64 = (0..samples)
+            .map(|_| {
+                let z = 2.0 * rng.next_f64() - 1.0;
+                let phi = 2.0 * PI * rng.next_f64();
+                let r = (1.0 - z * z).sqrt();
+                pdf(Vec3::new(r * phi.cos(), r * phi.sin(), z))
+            })
+            .sum();
+        4.0 * PI * sum / samples as f64
+    }
+
+    #[test]
+    fn diffusive_color_should_be_its_evaluation_over_its_density() {
+        let ray = down();
+        let i = hit(&ray);
+        let albedo = Color::new(0.2, 0.4, 0.8);
+
+        for _ in 0..100 {
+            let (color, scattered) = super::diffusive(albedo, &i).unwrap();
+            let eval = super::diffusive_eval(albedo, &i, scattered.direction);
+            let pdf = super::diffusive_pdf(&i, scattered.direction);
+
+            assert_that!((eval.b / pdf - color.b).abs() < 1e-9, is(true));
+        }
+        assert_that!(
+            (integrate(|d| super::diffusive_pdf(&i, d)) - 1.0).abs() < 0.05,
+            is(true)
+        );
+    }
+
+    #[test]
+    fn rough_reflection_should_be_its_evaluation_over_its_density() {
+        let ray = down();
+        let i = hit(&ray);
+        let albedo = Color::new(0.9, 0.6, 0.3);
+
+        for _ in 0..100 {
+            if let Some((color, scattered)) = super::reflection(albedo, 0.4, &ray, &i) {
+                let eval = super::reflection_eval(albedo, 0.4, &ray, &i, scattered.direction);
+                let pdf = super::reflection_pdf(0.4, &ray, &i, scattered.direction);
+
+                assert_that!((eval.g / pdf - color.g).abs() < 1e-9, is(true));
+            }
+        }
+        // Straight up is the mirrored direction, where the density is the highest.
+        let straight = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let up = super::reflection_pdf(0.4, &straight, &i, Vec3::new(0.0, 1.0, 0.0));
+        let aside = super::reflection_pdf(0.4, &straight, &i, Vec3::new(0.4, 1.0, 0.0));
+        assert_that!(up > aside, is(true));
+        // The rays lost into the surface are those that hit facets tilted by more than 45
+        // degrees, which make up α² / (1 + α²) of the visible facets.
+        let total = integrate(|d| super::reflection_pdf(0.4, &straight, &i, d));
+        assert_that!((total - (1.0 - 0.16 / 1.16)).abs() < 0.03, is(true));
+    }
+
+    #[test]
+    fn mirrors_and_glass_should_not_be_evaluated() {
+        let ray = down();
+        let i = hit(&ray);
+        let (_, mirrored) = super::reflection(Color::white(), 0.0, &ray, &i).unwrap();
+
+        assert_that!(
+            super::reflection_pdf(0.0, &ray, &i, mirrored.direction),
+            is(equal_to(0.0))
+        );
+        assert_that!(
+            super::refraction_eval(1.5, &ray, &i, mirrored.direction),
+            is(equal_to(Color::black()))
+        );
+        assert_that!(
+            super::transmission_pdf(&ray, &i, ray.direction),
+            is(equal_to(0.0))
+        );
+    }
+
+    #[test]
+    fn hair_lobes_should_add_up_to_one() {
+        let ray = down();
+        let i = hit(&ray);
+        let tangent = Vec3::new(0.0, 0.0, 1.0);
+
+        for _ in 0..100 {
+            let (_, scattered) = super::hair(Color::white(), 0.3, tangent, &ray, &i).unwrap();
+            let pdf = super::hair_pdf(0.3, tangent, &ray, &i, scattered.direction);
+
+            assert_that!(pdf > 0.0, is(true));
+        }
+        let total = integrate(|d| super::hair_pdf(0.3, tangent, &ray, &i, d));
+        assert_that!((total - 1.0).abs() < 0.05, is(true));
+    }
+}
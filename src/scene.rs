@@ -1,20 +1,148 @@
+use rand::{self, Rng};
+use std::f64;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use bvh::ShapeBvh;
+use light;
+use medium::Medium;
 use prelude::*;
-use scatter;
 
 pub trait Intersectable {
     fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection>;
 
-    fn scatter(&self, _: &Ray, _: &Intersection) -> Option<(Color, Ray)> {
-        None
+    /// Scatters the ray where it hit the shape, by the material of the intersection unless
+    /// the shape does something else.
+    fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Color, Ray)> {
+        intersection
+            .material
+            .as_ref()
+            .and_then(|material| material.scatter(ray, intersection))
+    }
+
+    /// The density per solid angle of `scatter` sending the ray in `direction`.
+    fn scatter_pdf(&self, ray: &Ray, intersection: &Intersection, direction: Vec3) -> f64 {
+        intersection
+            .material
+            .as_ref()
+            .map_or(0.0, |material| material.pdf(ray, intersection, direction))
+    }
+
+    /// The color that `scatter` sends in `direction`, times the cosine to the normal. It is
+    /// black for surfaces that only scatter in exact directions, such as mirrors.
+    fn scatter_eval(&self, ray: &Ray, intersection: &Intersection, direction: Vec3) -> Color {
+        intersection
+            .material
+            .as_ref()
+            .map_or(Color::black(), |material| {
+                material.eval(ray, intersection, direction)
+            })
     }
 
     fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable>;
+
+    /// The box enclosing the shape, or `None` if the shape is unbounded.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// The medium inside of the shape, for shapes that are the boundary of a volume that rays
+    /// may scatter inside of.
+    fn medium(&self) -> Option<&dyn Medium> {
+        None
+    }
+
+    /// Whether the shape gives off light and can pick points on itself with `sample_towards`,
+    /// so that the rendering can aim rays straight at it.
+    fn is_emitter(&self) -> bool {
+        false
+    }
+
+    /// A random point on the part of the shape that can be seen from `origin`, where the shape
+    /// is at `time` if it moves.
+    fn sample_towards(&self, _: Vec3, _: f64) -> Option<Vec3> {
+        None
+    }
+
+    /// The density of `sample_towards` picking `point` from `origin` at `time`, per solid
+    /// angle. It is zero unless `point` is on the part of the shape that can be seen from
+    /// `origin`.
+    fn pdf_towards(&self, _: Vec3, _: Vec3, _: f64) -> f64 {
+        0.0
+    }
+
+    /// Every intersection along the ray between `t_min` and `t_max`, ordered by distance.
+    /// Together with the direction of the geometric normals, this gives the intervals where
+    /// the ray is inside of the shape.
+    fn intersections(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<Intersection> {
+        let mut intersections: Vec<Intersection> = Vec::new();
+        let mut t = t_min;
+        while let Some(intersection) = self.intersects(ray, t, t_max) {
+            if intersection.distance <= t {
+                break;
+            }
+            t = intersection.distance;
+            intersections.push(intersection);
+        }
+        intersections
+    }
+}
+
+/// Lets shapes be shared, for instance as the geometry of several `Instance`s.
+impl<T: Intersectable + ?Sized> Intersectable for Rc<T> {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        (**self).intersects(ray, t_min, t_max)
+    }
+
+    fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Color, Ray)> {
+        (**self).scatter(ray, intersection)
+    }
+
+    fn scatter_pdf(&self, ray: &Ray, intersection: &Intersection, direction: Vec3) -> f64 {
+        (**self).scatter_pdf(ray, intersection, direction)
+    }
+
+    fn scatter_eval(&self, ray: &Ray, intersection: &Intersection, direction: Vec3) -> Color {
+        (**self).scatter_eval(ray, intersection, direction)
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        (**self).move_to(vec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn medium(&self) -> Option<&dyn Medium> {
+        (**self).medium()
+    }
+
+    fn is_emitter(&self) -> bool {
+        (**self).is_emitter()
+    }
+
+    fn sample_towards(&self, origin: Vec3, time: f64) -> Option<Vec3> {
+        (**self).sample_towards(origin, time)
+    }
+
+    fn pdf_towards(&self, origin: Vec3, point: Vec3, time: f64) -> f64 {
+        (**self).pdf_towards(origin, point, time)
+    }
+
+    fn intersections(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<Intersection> {
+        (**self).intersections(ray, t_min, t_max)
+    }
 }
 
 pub struct Intersection {
     pub distance: f64,
     pub intersection_point: Vec3,
     pub normal: Vec3,
+    pub geometric_normal: Vec3,
+    pub uv: (f64, f64),
+    /// The direction on the surface along which u increases, for shapes that have one.
+    /// Anisotropic materials stretch their highlights along it.
+    pub tangent: Option<Vec3>,
+    pub material: Option<Material>,
     pub shape: Box<dyn Intersectable>,
 }
 
@@ -29,89 +157,196 @@ impl Intersection {
             distance: distance,
             intersection_point: intersection_point,
             normal: normal,
+            geometric_normal: normal,
+            uv: (0.0, 0.0),
+            tangent: None,
+            material: None,
             shape: shape,
         }
     }
+
+    /// Sets the true normal of the surface, for shapes where the shading normal (`normal`)
+    /// is interpolated and may differ from it.
+    pub fn with_geometric_normal(self, geometric_normal: Vec3) -> Intersection {
+        Intersection {
+            geometric_normal: geometric_normal,
+            ..self
+        }
+    }
+
+    pub fn with_uv(self, u: f64, v: f64) -> Intersection {
+        Intersection { uv: (u, v), ..self }
+    }
+
+    pub fn with_tangent(self, tangent: Vec3) -> Intersection {
+        Intersection {
+            tangent: Some(tangent),
+            ..self
+        }
+    }
+
+    /// Sets the material of the surface that was hit, which the ray scatters by.
+    pub fn with_material(self, material: Material) -> Intersection {
+        Intersection {
+            material: Some(material),
+            ..self
+        }
+    }
 }
 
 pub struct Scene {
-    pub shapes: Vec<Box<dyn Intersectable>>,
+    shapes: Vec<Box<dyn Intersectable>>,
+    /// The color seen where rays leave the scene, or `None` for the sky.
+    pub background: Option<Color>,
+    pub lights: Vec<Light>,
+    bvh: ShapeBvh,
+    emitters: Vec<usize>,
 }
 
 impl Scene {
+    /// Creates a scene, building a bounding volume hierarchy over all the bounded shapes.
     pub fn new(shapes: Vec<Box<dyn Intersectable>>) -> Scene {
-        Scene { shapes: shapes }
+        let emitters = (0..shapes.len())
+            .filter(|&index| shapes[index].is_emitter())
+            .collect();
+        Scene {
+            background: None,
+            lights: vec![],
+            bvh: ShapeBvh::new(&shapes),
+            emitters: emitters,
+            shapes: shapes,
+        }
+    }
+
+    /// Replaces the sky by a single color, such as black for a room that is only lit by the
+    /// emitters inside of it.
+    pub fn with_background(self, color: Color) -> Scene {
+        Scene {
+            background: Some(color),
+            ..self
+        }
+    }
+
+    pub fn with_lights(self, lights: Vec<Light>) -> Scene {
+        Scene {
+            lights: lights,
+            ..self
+        }
+    }
+
+    /// The shapes of the scene. They can't be changed afterwards, since the bounding volume
+    /// hierarchy is built over them, so make a new scene instead.
+    pub fn shapes(&self) -> &[Box<dyn Intersectable>] {
+        &self.shapes
+    }
+
+    /// Whether any of the shapes give off light that can be sampled, see
+    /// `Intersectable::is_emitter`.
+    pub fn has_emitters(&self) -> bool {
+        !self.emitters.is_empty()
+    }
+
+    /// A random point on a random emitter, as seen from `origin` at `time`.
+    pub fn sample_emitter(&self, origin: Vec3, time: f64) -> Option<Vec3> {
+        if self.emitters.is_empty() {
+            return None;
+        }
+        let index = rand::thread_rng().gen_range(0, self.emitters.len());
+        self.shapes[self.emitters[index]].sample_towards(origin, time)
+    }
+
+    /// The media that the origin of the ray is inside of at the time of the ray, such as the
+    /// fog around a camera placed in it, in the order of the shapes. Only media among the
+    /// shapes of the scene are found, and not those inside of groups.
+    pub fn media_at(&self, ray: &Ray) -> Vec<&dyn Medium> {
+        self.shapes
+            .iter()
+            .filter_map(|shape| shape.medium().map(|medium| (shape, medium)))
+            .filter(|&(shape, _)| is_inside(&**shape, ray))
+            .map(|(_, medium)| medium)
+            .collect()
+    }
+
+    /// The density of `sample_emitter` picking `point` from `origin` at `time`, per solid
+    /// angle.
+    pub fn emitter_pdf(&self, origin: Vec3, point: Vec3, time: f64) -> f64 {
+        if self.emitters.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .emitters
+            .iter()
+            .map(|&index| self.shapes[index].pdf_towards(origin, point, time))
+            .sum();
+        sum / self.emitters.len() as f64
     }
 }
 
 impl Intersectable for Scene {
     fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
-        let mut intersection: Option<Intersection> = None;
-        let mut closest_so_far: f64 = t_max;
-
-        for shape in self.shapes.iter() {
-            match shape.intersects(ray, t_min, closest_so_far) {
-                Some(other_intersection) => {
-                    closest_so_far = other_intersection.distance;
-                    intersection = Some(other_intersection);
-                }
-                None => (),
-            }
-        }
-        intersection
+        self.bvh.intersects(&self.shapes, ray, t_min, t_max)
     }
 
     fn move_to(&self, _: Vec3) -> Box<dyn Intersectable> {
         Box::new(Scene::new(vec![]))
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+}
+
+/// Whether the origin of `ray` is inside of the closed `shape` at the time of the ray, which it
+/// is when a ray from it leaves the shape the first time it hits it.
+fn is_inside(shape: &dyn Intersectable, ray: &Ray) -> bool {
+    // Any direction will do, but one that is not along an axis is less likely to graze an
+    // edge of the shape.
+    let ray = Ray::new(ray.origin, Vec3::new(0.48, 0.6, 0.64)).with_time(ray.time);
+    shape
+        .intersects(&ray, 0.0, f64::MAX)
+        .is_some_and(|intersection| ray.direction.dot(intersection.normal) > 0.0)
 }
 
 #[derive(Clone)]
 pub struct Sphere {
     pub origin: Vec3,
     pub radius: f64,
-    pub color: Color,
-    diffusiveness: Option<f64>,
-    refraction_index: Option<f64>,
+    pub material: Material,
 }
 
 impl Sphere {
     pub fn new(origin: Vec3, radius: f64, color: Color) -> Sphere {
-        Sphere {
-            origin: origin,
-            radius: radius,
-            color: color,
-            diffusiveness: None,
-            refraction_index: None,
-        }
+        Sphere::with_material(origin, radius, Material::Lambertian(color))
     }
 
     pub fn reflective(origin: Vec3, radius: f64, color: Color, diffusiveness: f64) -> Sphere {
-        Sphere {
-            origin: origin,
-            radius: radius,
-            color: color,
-            diffusiveness: Some(diffusiveness),
-            refraction_index: None,
-        }
+        let material = Material::Metal {
+            albedo: color,
+            fuzz: diffusiveness,
+        };
+        Sphere::with_material(origin, radius, material)
     }
 
     pub fn refractive(origin: Vec3, radius: f64, color: Color, refraction_index: f64) -> Sphere {
+        let material = Material::Dielectric {
+            refraction_index: refraction_index,
+        };
+        Sphere::with_material(origin, radius, material)
+    }
+
+    pub fn with_material(origin: Vec3, radius: f64, material: Material) -> Sphere {
         Sphere {
             origin: origin,
             radius: radius,
-            color: color,
-            diffusiveness: None,
-            refraction_index: Some(refraction_index),
+            material: material,
         }
     }
 
     pub fn texture(origin: Vec3, radius: f64, texture: &'static str) -> Sphere {
         panic!(
-            "Step 6a) open the image located at the `texture` path, and add a new field to \
-                the Sphere struct. The new field should be a reference counted pointer to the \
-                texture image. Additionally, add a new Sphere to the scene by using the \
-                Sphere::texture(path) constructor."
+            "Step 6a) load the image located at the `texture` path with `Texture::load`, and \
+                create a Sphere with a `Material::Textured` material. Additionally, add a new \
+                Sphere to the scene by using the Sphere::texture(path) constructor."
         );
     }
 }
@@ -139,28 +374,52 @@ impl Intersectable for Sphere {
         }
     }
 
-    fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Color, Ray)> {
-        // Step 6b)
-        // Add a new if-expression to handle the case when a Sphere has a texture, then
-        // call (and implement) the scatter::texture() function.
-        if let Some(diffusiveness) = self.diffusiveness {
-            scatter::reflection(self.color, diffusiveness, ray, intersection)
-        } else if let Some(refraction_index) = self.refraction_index {
-            scatter::refraction(refraction_index, ray, intersection)
-        } else {
-            scatter::diffusive(self.color, intersection)
-        }
-    }
-
     fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
         Box::new(Sphere {
             origin: vec,
-            radius: self.radius,
-            color: self.color,
-            diffusiveness: self.diffusiveness,
-            refraction_index: self.refraction_index,
+            ..self.clone()
         })
     }
+
+    fn is_emitter(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    /// Picks a direction within the cone that the sphere fills as seen from `origin`, or a
+    /// point anywhere on the sphere when `origin` is inside of it.
+    fn sample_towards(&self, origin: Vec3, _: f64) -> Option<Vec3> {
+        let to_center = self.origin - origin;
+        let radius_squared = self.radius * self.radius;
+        if to_center.squared_length() <= radius_squared {
+            let direction = light::uniform_in_cone(Vec3::new(0.0, 1.0, 0.0), -1.0);
+            return Some(self.origin + direction * self.radius.abs());
+        }
+        let cos_max = (1.0 - radius_squared / to_center.squared_length()).sqrt();
+        let direction = light::uniform_in_cone(to_center, cos_max);
+        self.intersects(&Ray::new(origin, direction), 0.0, f64::MAX)
+            .map(|intersection| intersection.intersection_point)
+    }
+
+    fn pdf_towards(&self, origin: Vec3, point: Vec3, _: f64) -> f64 {
+        let intersection = match light::hit_at(self, origin, point) {
+            Some(intersection) => intersection,
+            None => return 0.0,
+        };
+        let to_center = self.origin - origin;
+        let radius_squared = self.radius * self.radius;
+        if to_center.squared_length() <= radius_squared {
+            light::area_pdf(origin, &intersection, 4.0 * PI * radius_squared)
+        } else {
+            let cos_max = (1.0 - radius_squared / to_center.squared_length()).sqrt();
+            1.0 / (2.0 * PI * (1.0 - cos_max))
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let radius = self.radius.abs();
+        let extent = Vec3::new(radius, radius, radius);
+        Some(Aabb::new(self.origin - extent, self.origin + extent))
+    }
 }
 
 fn create_intersection(sphere: &Sphere, delta: f64, ray: &Ray) -> Option<Intersection> {
@@ -169,10 +428,20 @@ fn create_intersection(sphere: &Sphere, delta: f64, ray: &Ray) -> Option<Interse
         "Step 3b) Calculate the surface normal. Hint: The formula is \
                                  available in the README"
     );
-    Some(Intersection::new(
+    // Step 6b)
+    // Project the surface normal to (u, v) coordinates and add them to the intersection with
+    // `with_uv`, so that a `Material::Textured` knows where to look up its color.
+    let intersection = Intersection::new(
         delta,
         intersection_point,
         surface_normal,
         Box::new(sphere.clone()),
-    ))
+    );
+    // The tangent goes around the y axis, along the lines of latitude.
+    let offset = intersection_point - sphere.origin;
+    Some(
+        intersection
+            .with_tangent(Vec3::new(-offset.z, 0.0, offset.x))
+            .with_material(sphere.material.clone()),
+    )
 }
@@ -0,0 +1,410 @@
+use std::rc::Rc;
+
+use fractal;
+use prelude::*;
+use scene::Intersection;
+
+const MAX_STEPS: u32 = 512;
+const HIT_DISTANCE: f64 = 1e-5;
+const GRADIENT_STEP: f64 = 1e-5;
+
+/// A tree of signed distance functions, giving the distance from a point to the closest
+/// surface, negative inside of the shape. Primitives are placed with `translate`, and combined
+/// with the other operations.
+#[derive(Clone, Debug)]
+pub enum Sdf {
+    Sphere {
+        center: Vec3,
+        radius: f64,
+    },
+    Cuboid {
+        center: Vec3,
+        half_size: Vec3,
+    },
+    /// A torus lying in the xz-plane.
+    Torus {
+        center: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    /// The Mandelbulb fractal, centered at the origin. It fits inside of a sphere that shrinks
+    /// from radius 2 for power 2 towards the unit sphere for higher powers, see
+    /// `fractal::mandelbulb_radius`.
+    Mandelbulb {
+        power: f64,
+        iterations: u32,
+    },
+    /// The Menger sponge fractal, filling the cube from -1 to 1.
+    MengerSponge {
+        depth: u32,
+    },
+    Union(Box<Sdf>, Box<Sdf>),
+    /// A union where the shapes blend into each other within the distance `k`.
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f64),
+    Subtraction(Box<Sdf>, Box<Sdf>),
+    /// Repeats the shape in cells of the given size around the origin. An axis with a period
+    /// of zero is not repeated.
+    Repetition(Box<Sdf>, Vec3),
+    /// Twists the shape around the y-axis, by the given number of radians per unit.
+    Twist(Box<Sdf>, f64),
+    Translation(Box<Sdf>, Vec3),
+    /// Scales the shape uniformly around the origin.
+    Scale(Box<Sdf>, f64),
+}
+
+impl Sdf {
+    pub fn sphere(center: Vec3, radius: f64) -> Sdf {
+        Sdf::Sphere {
+            center: center,
+            radius: radius,
+        }
+    }
+
+    pub fn cuboid(center: Vec3, half_size: Vec3) -> Sdf {
+        Sdf::Cuboid {
+            center: center,
+            half_size: half_size,
+        }
+    }
+
+    pub fn torus(center: Vec3, major_radius: f64, minor_radius: f64) -> Sdf {
+        Sdf::Torus {
+            center: center,
+            major_radius: major_radius,
+            minor_radius: minor_radius,
+        }
+    }
+
+    pub fn mandelbulb(power: f64, iterations: u32) -> Sdf {
+        Sdf::Mandelbulb {
+            power: power,
+            iterations: iterations,
+        }
+    }
+
+    pub fn menger_sponge(depth: u32) -> Sdf {
+        Sdf::MengerSponge { depth: depth }
+    }
+
+    pub fn union(self, other: Sdf) -> Sdf {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: Sdf, k: f64) -> Sdf {
+        Sdf::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn subtract(self, other: Sdf) -> Sdf {
+        Sdf::Subtraction(Box::new(self), Box::new(other))
+    }
+
+    pub fn repeat(self, period: Vec3) -> Sdf {
+        Sdf::Repetition(Box::new(self), period)
+    }
+
+    pub fn twist(self, rate: f64) -> Sdf {
+        Sdf::Twist(Box::new(self), rate)
+    }
+
+    pub fn translate(self, offset: Vec3) -> Sdf {
+        Sdf::Translation(Box::new(self), offset)
+    }
+
+    pub fn scale(self, factor: f64) -> Sdf {
+        Sdf::Scale(Box::new(self), factor)
+    }
+
+    pub fn distance(&self, point: Vec3) -> f64 {
+        match *self {
+            Sdf::Sphere { center, radius } => (point - center).length() - radius,
+            Sdf::Cuboid { center, half_size } => {
+                let offset = point - center;
+                let q = Vec3::new(
+                    offset.x.abs() - half_size.x,
+                    offset.y.abs() - half_size.y,
+                    offset.z.abs() - half_size.z,
+                );
+                let outside = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+                outside.length() + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            Sdf::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let offset = point - center;
+                let ring = (offset.x * offset.x + offset.z * offset.z).sqrt() - major_radius;
+                (ring * ring + offset.y * offset.y).sqrt() - minor_radius
+            }
+            Sdf::Mandelbulb { power, iterations } => {
+                fractal::mandelbulb_distance(point, power, iterations)
+            }
+            Sdf::MengerSponge { depth } => fractal::menger_sponge_distance(point, depth),
+            Sdf::Union(ref a, ref b) => a.distance(point).min(b.distance(point)),
+            Sdf::SmoothUnion(ref a, ref b, k) => {
+                let (a, b) = (a.distance(point), b.distance(point));
+                let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+                b + (a - b) * h - k * h * (1.0 - h)
+            }
+            Sdf::Subtraction(ref a, ref b) => a.distance(point).max(-b.distance(point)),
+            Sdf::Repetition(ref sdf, period) => {
+                let repeat = |x: f64, period: f64| {
+                    if period > 0.0 {
+                        x - period * (x / period).round()
+                    } else {
+                        x
+                    }
+                };
+                sdf.distance(Vec3::new(
+                    repeat(point.x, period.x),
+                    repeat(point.y, period.y),
+                    repeat(point.z, period.z),
+                ))
+            }
+            Sdf::Twist(ref sdf, rate) => {
+                let (sin, cos) = (rate * point.y).sin_cos();
+                let twisted = Vec3::new(
+                    cos * point.x - sin * point.z,
+                    point.y,
+                    sin * point.x + cos * point.z,
+                );
+                // Twisting stretches space further away from the axis, so the distance is
+                // scaled down to keep the steps of the sphere tracing from overshooting.
+                let radius = (point.x * point.x + point.z * point.z).sqrt();
+                sdf.distance(twisted) / (1.0 + (rate * radius).powi(2)).sqrt()
+            }
+            Sdf::Translation(ref sdf, offset) => sdf.distance(point - offset),
+            Sdf::Scale(ref sdf, factor) => sdf.distance(point / factor) * factor,
+        }
+    }
+
+    /// The box enclosing the shape, or `None` if it is repeated without end.
+    pub fn bounds(&self) -> Option<Aabb> {
+        match *self {
+            Sdf::Sphere { center, radius } => {
+                let extent = Vec3::new(radius, radius, radius);
+                Some(Aabb::new(center - extent, center + extent))
+            }
+            Sdf::Cuboid { center, half_size } => {
+                Some(Aabb::new(center - half_size, center + half_size))
+            }
+            Sdf::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let radius = major_radius + minor_radius;
+                let extent = Vec3::new(radius, minor_radius, radius);
+                Some(Aabb::new(center - extent, center + extent))
+            }
+            Sdf::Mandelbulb { power, .. } => {
+                let radius = fractal::mandelbulb_radius(power);
+                let extent = Vec3::new(radius, radius, radius);
+                Some(Aabb::new(extent.invert(), extent))
+            }
+            Sdf::MengerSponge { .. } => Some(Aabb::new(
+                Vec3::new(-1.0, -1.0, -1.0),
+                Vec3::new(1.0, 1.0, 1.0),
+            )),
+            Sdf::Union(ref a, ref b) => match (a.bounds(), b.bounds()) {
+                (Some(a), Some(b)) => Some(a.union(&b)),
+                _ => None,
+            },
+            Sdf::SmoothUnion(ref a, ref b, k) => match (a.bounds(), b.bounds()) {
+                (Some(a), Some(b)) => {
+                    let bounds = a.union(&b);
+                    let extent = Vec3::new(k, k, k);
+                    Some(Aabb::new(bounds.min - extent, bounds.max + extent))
+                }
+                _ => None,
+            },
+            Sdf::Subtraction(ref a, _) => a.bounds(),
+            Sdf::Repetition(..) => None,
+            Sdf::Twist(ref sdf, _) => sdf.bounds().map(|bounds| {
+                let radius = bounds
+                    .corners()
+                    .iter()
+                    .map(|corner| (corner.x * corner.x + corner.z * corner.z).sqrt())
+                    .fold(0.0, f64::max);
+                Aabb::new(
+                    Vec3::new(-radius, bounds.min.y, -radius),
+                    Vec3::new(radius, bounds.max.y, radius),
+                )
+            }),
+            Sdf::Translation(ref sdf, offset) => sdf
+                .bounds()
+                .map(|bounds| Aabb::new(bounds.min + offset, bounds.max + offset)),
+            Sdf::Scale(ref sdf, factor) => sdf
+                .bounds()
+                .map(|bounds| Aabb::from_points(&[bounds.min * factor, bounds.max * factor])),
+        }
+    }
+
+    /// The normal of the surface, from the gradient of the distance found by central
+    /// differences.
+    pub fn normal(&self, point: Vec3) -> Vec3 {
+        let difference =
+            |offset: Vec3| self.distance(point + offset) - self.distance(point - offset);
+        Vec3::new(
+            difference(Vec3::new(GRADIENT_STEP, 0.0, 0.0)),
+            difference(Vec3::new(0.0, GRADIENT_STEP, 0.0)),
+            difference(Vec3::new(0.0, 0.0, GRADIENT_STEP)),
+        )
+        .normalize()
+    }
+}
+
+/// A shape given by a signed distance function, which is intersected by sphere tracing: the
+/// ray is advanced by the distance to the closest surface until it is close enough to count
+/// as a hit.
+#[derive(Clone)]
+pub struct SdfShape {
+    sdf: Rc<Sdf>,
+    pub material: Material,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Sdf, material: Material) -> SdfShape {
+        SdfShape {
+            sdf: Rc::new(sdf),
+            material: material,
+        }
+    }
+
+    pub fn sdf(&self) -> &Sdf {
+        &self.sdf
+    }
+}
+
+impl Intersectable for SdfShape {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        // The bounds are grown a little, so that hits right on the edge of the box are found.
+        let margin = Vec3::new(HIT_DISTANCE, HIT_DISTANCE, HIT_DISTANCE) * 2.0;
+        let (start, end) = match self.sdf.bounds() {
+            Some(bounds) => {
+                Aabb::new(bounds.min - margin, bounds.max + margin).clip(ray, t_min, t_max)?
+            }
+            None => (t_min, t_max),
+        };
+        let direction_length = ray.direction.length();
+
+        // A ray leaving the surface, as scattered rays do, starts out close to it. Hits only
+        // count once the ray has been further away from the surface than the hit distance.
+        let mut left_surface = start > t_min;
+        let mut t = start;
+        for _ in 0..MAX_STEPS {
+            let distance = self.sdf.distance(ray.point_along_direction(t)).abs();
+            if distance < HIT_DISTANCE {
+                if left_surface {
+                    let intersection_point = ray.point_along_direction(t);
+                    let normal = self.sdf.normal(intersection_point);
+                    let intersection =
+                        Intersection::new(t, intersection_point, normal, Box::new(self.clone()));
+                    return Some(intersection.with_material(self.material.clone()));
+                }
+                t += HIT_DISTANCE / direction_length;
+            } else {
+                left_surface = true;
+                t += distance / direction_length;
+            }
+            if t >= end {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Moves the center of the shape to `vec`, or the origin of an unbounded shape.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let offset = match self.sdf.bounds() {
+            Some(bounds) => vec - bounds.centroid(),
+            None => vec,
+        };
+        Box::new(SdfShape::new(
+            (*self.sdf).clone().translate(offset),
+            self.material.clone(),
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.sdf.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+    use tests::assert_close_within;
+
+    fn shape(sdf: Sdf) -> SdfShape {
+        SdfShape::new(sdf, Material::Lambertian(Color::white()))
+    }
+
+    fn ray() -> Ray {
+        Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn sphere_tracing_should_find_the_surface_of_a_sphere() {
+        let sphere = shape(Sdf::sphere(Vec3::new(0.0, 0.0, 0.0), 1.0));
+
+        let i = sphere.intersects(&ray(), 0.0, 100.0).unwrap();
+
+        assert_close_within(i.distance, 4.0, 1e-4);
+        assert_close_within(i.normal.x, -1.0, 1e-4);
+        assert_that!(sphere.intersects(&ray(), 0.0, 3.0).is_none(), is(true));
+    }
+
+    #[test]
+    fn ray_starting_on_the_surface_should_find_the_way_out() {
+        let sphere = shape(Sdf::sphere(Vec3::new(0.0, 0.0, 0.0), 1.0));
+        let ray = Ray::new(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let i = sphere.intersects(&ray, 1e-8, 100.0).unwrap();
+
+        assert_close_within(i.distance, 2.0, 1e-4);
+        assert_close_within(i.normal.x, 1.0, 1e-4);
+    }
+
+    #[test]
+    fn subtraction_should_hollow_out_the_shape() {
+        let sdf = Sdf::cuboid(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0))
+            .subtract(Sdf::sphere(Vec3::new(-1.0, 0.0, 0.0), 0.5));
+
+        let i = shape(sdf).intersects(&ray(), 0.0, 100.0).unwrap();
+
+        assert_close_within(i.distance, 4.5, 1e-4);
+        assert_close_within(i.normal.x, -1.0, 1e-4);
+    }
+
+    #[test]
+    fn smooth_union_should_fill_the_gap_between_shapes() {
+        let a = Sdf::sphere(Vec3::new(0.0, 1.1, 0.0), 1.0);
+        let b = Sdf::sphere(Vec3::new(0.0, -1.1, 0.0), 1.0);
+
+        assert_that!(
+            a.clone()
+                .union(b.clone())
+                .distance(Vec3::new(0.0, 0.0, 0.0))
+                > 0.0,
+            is(true)
+        );
+        assert_that!(
+            a.smooth_union(b, 0.5).distance(Vec3::new(0.0, 0.0, 0.0)) < 0.0,
+            is(true)
+        );
+    }
+
+    #[test]
+    fn repeated_shapes_should_be_unbounded() {
+        let spheres =
+            shape(Sdf::sphere(Vec3::new(0.0, 0.0, 0.0), 0.5).repeat(Vec3::new(0.0, 0.0, 4.0)));
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 8.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let i = spheres.intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_close_within(i.distance, 4.5, 1e-4);
+        assert_that!(spheres.bounding_box().is_none(), is(true));
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::Path;
+
+use mesh::{Face, Mesh, MeshError};
+use prelude::*;
+
+const HEADER_SIZE: usize = 80;
+const TRIANGLE_SIZE: usize = 50;
+
+pub fn load(path: &Path) -> Result<Mesh, MeshError> {
+    parse(&fs::read(path)?)
+}
+
+/// Parses a binary STL file. Every facet gets its own three vertices, and the stored facet
+/// normals are ignored in favour of the winding order of the vertices.
+pub fn parse(bytes: &[u8]) -> Result<Mesh, MeshError> {
+    if bytes.len() < HEADER_SIZE + 4 {
+        return Err(MeshError::Format(
+            "file is too short to be a binary STL".to_string(),
+        ));
+    }
+    let count = read_u32(&bytes[HEADER_SIZE..]) as usize;
+    let expected = HEADER_SIZE + 4 + count * TRIANGLE_SIZE;
+    if bytes.len() != expected {
+        return Err(MeshError::Format(format!(
+            "expected {} bytes for {} triangles, found {}",
+            expected,
+            count,
+            bytes.len()
+        )));
+    }
+
+    let mut vertices = Vec::with_capacity(count * 3);
+    let mut faces = Vec::with_capacity(count);
+    for triangle in bytes[HEADER_SIZE + 4..].chunks(TRIANGLE_SIZE) {
+        // Skips the facet normal, and the attribute byte count after the vertices.
+        for vertex in triangle[12..48].chunks(12) {
+            vertices.push(Vec3::new(
+                read_f32(&vertex[0..]),
+                read_f32(&vertex[4..]),
+                read_f32(&vertex[8..]),
+            ));
+        }
+        let first = vertices.len() - 3;
+        faces.push(Face::new(first, first + 1, first + 2));
+    }
+
+    Ok(Mesh::new(
+        vertices,
+        faces,
+        Material::Lambertian(Color::new(0.8, 0.8, 0.8)),
+    ))
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_f32(bytes: &[u8]) -> f64 {
+    f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+    use stl::parse;
+
+    fn stl(triangles: &[[f32; 9]]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 80];
+        bytes.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+        for triangle in triangles {
+            bytes.extend_from_slice(&[0u8; 12]);
+            for value in triangle.iter() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0u8; 2]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn should_parse_binary_stl() {
+        let bytes = stl(&[
+            [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+        ]);
+
+        let mesh = parse(&bytes).unwrap();
+
+        assert_that!(mesh.vertices().len(), is(equal_to(6)));
+        assert_that!(mesh.vertices()[4], is(equal_to(Vec3::new(1.0, 1.0, 0.0))));
+        assert_that!(mesh.faces()[1].vertices, is(equal_to([3, 4, 5])));
+    }
+
+    #[test]
+    fn should_fail_when_triangle_count_does_not_match_size() {
+        let mut bytes = stl(&[[0.0; 9]]);
+        bytes.pop();
+
+        assert_that!(parse(&bytes).is_err(), is(true));
+        assert_that!(parse(b"solid ascii").is_err(), is(true));
+    }
+}
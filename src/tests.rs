@@ -1,3 +1,63 @@
+use std::fmt::Debug;
+
+use prelude::*;
+
+/// Values that the tests compare up to a tolerance, since they are computed with rounding.
+pub trait Close: Debug {
+    /// How far apart the two values are.
+    fn difference(&self, other: &Self) -> f64;
+}
+
+impl Close for f64 {
+    fn difference(&self, other: &f64) -> f64 {
+        (self - other).abs()
+    }
+}
+
+impl Close for Vec3 {
+    fn difference(&self, other: &Vec3) -> f64 {
+        (*self - *other).length()
+    }
+}
+
+impl Close for Color {
+    fn difference(&self, other: &Color) -> f64 {
+        (self.r - other.r)
+            .abs()
+            .max((self.g - other.g).abs())
+            .max((self.b - other.b).abs())
+    }
+}
+
+impl Close for Matrix4 {
+    fn difference(&self, other: &Matrix4) -> f64 {
+        let mut difference: f64 = 0.0;
+        for row in 0..4 {
+            for column in 0..4 {
+                difference = difference.max((self.m[row][column] - other.m[row][column]).abs());
+            }
+        }
+        difference
+    }
+}
+
+/// Asserts that `a` and `b` are the same but for rounding.
+pub fn assert_close<T: Close>(a: T, b: T) {
+    assert_close_within(a, b, 1e-9);
+}
+
+/// Asserts that `a` and `b` are no further apart than `tolerance`, for values that are found
+/// by stepping or iterating towards them.
+pub fn assert_close_within<T: Close>(a: T, b: T, tolerance: f64) {
+    assert!(
+        a.difference(&b) < tolerance,
+        "{:?} is not within {} of {:?}",
+        a,
+        tolerance,
+        b
+    );
+}
+
 mod step_1 {
     use hamcrest::prelude::*;
     use prelude::*;
@@ -0,0 +1,57 @@
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use image;
+use prelude::*;
+
+/// An image mapped onto a surface by its uv coordinates, with (0, 0) in the lower left
+/// corner and (1, 1) in the upper right. The image repeats outside of that.
+#[derive(Clone)]
+pub struct Texture {
+    width: usize,
+    height: usize,
+    pixels: Rc<Vec<Color>>,
+}
+
+impl Texture {
+    /// A texture from colors given row by row from the top.
+    pub fn new(pixels: Vec<Color>, width: usize, height: usize) -> Texture {
+        assert_eq!(
+            pixels.len(),
+            width * height,
+            "a texture needs one color per pixel"
+        );
+        Texture {
+            width: width,
+            height: height,
+            pixels: Rc::new(pixels),
+        }
+    }
+
+    /// Loads a BMP or PNG image. The colors are squared to undo the gamma of the image, the
+    /// same way the rendered image is gamma corrected.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Texture> {
+        let (pixels, width, height) = image::read_image(path.as_ref())?;
+        let pixels = pixels
+            .iter()
+            .map(|&[r, g, b]| Color::new(r * r, g * g, b * b))
+            .collect();
+        Ok(Texture::new(pixels, width, height))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The color of the pixel that `uv` falls within.
+    pub fn color_at(&self, uv: (f64, f64)) -> Color {
+        let x = (uv.0.rem_euclid(1.0) * self.width as f64) as usize;
+        let y = ((1.0 - uv.1.rem_euclid(1.0)) * self.height as f64) as usize;
+        self.pixels[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+}
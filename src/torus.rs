@@ -0,0 +1,167 @@
+use std::f64::consts::PI;
+
+use math;
+use prelude::*;
+use quadric::Frame;
+use scene::Intersection;
+
+/// A ring around `axis`, where `major_radius` is the distance from the center to the middle of
+/// the tube and `minor_radius` is the radius of the tube.
+#[derive(Clone)]
+pub struct Torus {
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+    frame: Frame,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vec3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: Material,
+    ) -> Torus {
+        Torus {
+            major_radius: major_radius,
+            minor_radius: minor_radius,
+            material: material,
+            frame: Frame::new(center, axis),
+        }
+    }
+
+    fn create_intersection(&self, delta: f64, ray: &Ray) -> Intersection {
+        let intersection_point = ray.point_along_direction(delta);
+        let local = self.frame.local(intersection_point - self.frame.origin);
+        let distance_from_axis = (local.x * local.x + local.z * local.z).sqrt();
+        // The normal points away from the closest point on the circle in the middle of the tube.
+        let tube_center =
+            Vec3::new(local.x, 0.0, local.z) * (self.major_radius / distance_from_axis);
+        let normal = self.frame.world((local - tube_center) / self.minor_radius);
+        let u = (local.z.atan2(local.x) + PI) / (2.0 * PI);
+        let v = (local.y.atan2(distance_from_axis - self.major_radius) + PI) / (2.0 * PI);
+        Intersection::new(delta, intersection_point, normal, Box::new(self.clone()))
+            .with_uv(u, v)
+            .with_tangent(self.frame.around(intersection_point))
+            .with_material(self.material.clone())
+    }
+}
+
+impl Intersectable for Torus {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        // Starting the ray at its entry into the bounding sphere keeps the coefficients of the
+        // quartic small, which matters for rays from far away.
+        let bounding_radius = self.major_radius + self.minor_radius;
+        let translated_origin = ray.origin - self.frame.origin;
+        let entry = math::solve_quadratic(
+            ray.direction.dot(ray.direction),
+            2.0 * translated_origin.dot(ray.direction),
+            translated_origin.dot(translated_origin) - bounding_radius * bounding_radius,
+        );
+        let offset = match entry.first() {
+            Some(&entry) => entry.max(0.0),
+            None => return None,
+        };
+
+        let origin = self.frame.local(translated_origin + ray.direction * offset);
+        let direction = self.frame.local(ray.direction);
+        let (major, minor) = (self.major_radius, self.minor_radius);
+        let direction_squared = direction.dot(direction);
+        let along = origin.dot(direction);
+        let e = origin.dot(origin) - major * major - minor * minor;
+        let four_major_squared = 4.0 * major * major;
+
+        let roots = math::solve_quartic(
+            direction_squared * direction_squared,
+            4.0 * direction_squared * along,
+            2.0 * direction_squared * e
+                + 4.0 * along * along
+                + four_major_squared * direction.y * direction.y,
+            4.0 * along * e + 2.0 * four_major_squared * origin.y * direction.y,
+            e * e - four_major_squared * (minor * minor - origin.y * origin.y),
+        );
+        roots
+            .into_iter()
+            .map(|root| root + offset)
+            .find(|&delta| delta < t_max && delta > t_min)
+            .map(|delta| self.create_intersection(delta, ray))
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        Box::new(Torus {
+            frame: self.frame.moved_to(vec),
+            ..self.clone()
+        })
+    }
+
+    /// The torus is a circle swept by a sphere, so the box is the box around the circle grown
+    /// by the radius of the tube.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let axis = self.frame.axis;
+        let (major, minor) = (self.major_radius, self.minor_radius);
+        let extent = |n: f64| major * (1.0 - n * n).max(0.0).sqrt() + minor;
+        let extent = Vec3::new(extent(axis.x), extent(axis.y), extent(axis.z));
+        Some(Aabb::new(
+            self.frame.origin - extent,
+            self.frame.origin + extent,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+    use tests::assert_close;
+
+    fn torus() -> Torus {
+        Torus::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            2.0,
+            0.5,
+            Material::Lambertian(Color::white()),
+        )
+    }
+
+    #[test]
+    fn ray_through_the_center_should_hit_the_tube_four_times() {
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let intersections = torus().intersections(&ray, 0.0, 100.0);
+
+        assert_that!(intersections.len(), is(equal_to(4)));
+        for (intersection, &expected) in intersections.iter().zip([2.5, 3.5, 6.5, 7.5].iter()) {
+            assert_close(intersection.distance, expected);
+        }
+        assert_close(intersections[0].normal.x, -1.0);
+        assert_close(intersections[1].normal.x, 1.0);
+    }
+
+    #[test]
+    fn ray_through_the_hole_should_miss() {
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        assert_that!(torus().intersects(&ray, 0.0, 100.0).is_none(), is(true));
+    }
+
+    #[test]
+    fn ray_from_far_away_should_hit_the_top_of_the_tube() {
+        let ray = Ray::new(Vec3::new(2.0, 1e4, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        let i = torus().intersects(&ray, 0.0, f64::MAX).unwrap();
+
+        assert_close(i.distance, 1e4 - 0.5);
+        assert_close(i.normal.y, 1.0);
+        assert_close(i.uv.1, 0.75);
+    }
+
+    #[test]
+    fn torus_should_be_bounded_by_a_flat_box() {
+        let bounds = torus().bounding_box().unwrap();
+
+        assert_that!(bounds.min, is(equal_to(Vec3::new(-2.5, -0.5, -2.5))));
+        assert_that!(bounds.max, is(equal_to(Vec3::new(2.5, 0.5, 2.5))));
+    }
+}
@@ -0,0 +1,655 @@
+use std::rc::Rc;
+
+use medium::Medium;
+use prelude::*;
+use scene::Intersection;
+
+/// Wraps a shape in an affine transform, so that it can be rotated, scaled or sheared. Rays
+/// are transformed into the space of the shape, and hits are transformed back.
+#[derive(Clone)]
+pub struct Transformed<T: Intersectable> {
+    shape: T,
+    transform: Matrix4,
+    inverse: Matrix4,
+    normal_matrix: Matrix4,
+}
+
+impl<T: Intersectable> Transformed<T> {
+    /// Wraps the shape in the transform, or returns `None` if the transform can't be inverted,
+    /// as when it scales the shape flat.
+    pub fn new(shape: T, transform: Matrix4) -> Option<Transformed<T>> {
+        let inverse = transform.inverse()?;
+        Some(Transformed {
+            shape: shape,
+            transform: transform,
+            inverse: inverse,
+            normal_matrix: inverse.transpose(),
+        })
+    }
+
+    pub fn shape(&self) -> &T {
+        &self.shape
+    }
+
+    pub fn transform(&self) -> Matrix4 {
+        self.transform
+    }
+
+    fn object_ray(&self, ray: &Ray) -> Ray {
+        object_ray(&self.inverse, ray)
+    }
+
+    fn to_world(&self, ray: &Ray, intersection: Intersection) -> Intersection {
+        to_world(
+            &self.transform,
+            &self.inverse,
+            &self.normal_matrix,
+            ray,
+            intersection,
+        )
+    }
+
+    fn world_medium(&self) -> WorldMedium<'_> {
+        WorldMedium {
+            medium: self
+                .shape
+                .medium()
+                .expect("only shapes with a medium are used as one"),
+            transform: self.transform,
+            inverse: self.inverse,
+        }
+    }
+}
+
+// The direction is not normalized, so that distances along the ray are the same in both
+// spaces.
+fn object_ray(inverse: &Matrix4, ray: &Ray) -> Ray {
+    Ray {
+        origin: *inverse * ray.origin,
+        direction: inverse.transform_vector(ray.direction),
+        ..*ray
+    }
+}
+
+// Tangents lie in the surface, so they are transformed like the surface itself rather than
+// like the normals. Media work in the space of their boundary, so the boundaries of media are
+// kept behind the transform for the rendering to see them through it.
+fn to_world(
+    transform: &Matrix4,
+    inverse: &Matrix4,
+    normal_matrix: &Matrix4,
+    ray: &Ray,
+    intersection: Intersection,
+) -> Intersection {
+    let transform_normal = |normal: Vec3| normal_matrix.transform_vector(normal).normalize();
+    let shape: Box<dyn Intersectable> = if intersection.shape.medium().is_some() {
+        Box::new(Transformed::<Rc<dyn Intersectable>> {
+            shape: Rc::from(intersection.shape),
+            transform: *transform,
+            inverse: *inverse,
+            normal_matrix: *normal_matrix,
+        })
+    } else {
+        intersection.shape
+    };
+    Intersection {
+        shape: shape,
+        intersection_point: ray.point_along_direction(intersection.distance),
+        normal: transform_normal(intersection.normal),
+        geometric_normal: transform_normal(intersection.geometric_normal),
+        tangent: intersection
+            .tangent
+            .map(|tangent| transform.transform_vector(tangent)),
+        ..intersection
+    }
+}
+
+/// The density of `shape` picking `point` from `origin` per solid angle, when the shape is
+/// seen through a transform. The transform stretches the solid angle around the direction by
+/// the cube of how much it stretches the direction, over how much it scales volumes.
+fn pdf_towards<T: Intersectable>(
+    shape: &T,
+    inverse: &Matrix4,
+    origin: Vec3,
+    point: Vec3,
+    time: f64,
+) -> f64 {
+    let (object_origin, object_point) = (*inverse * origin, *inverse * point);
+    let pdf = shape.pdf_towards(object_origin, object_point, time);
+    if pdf == 0.0 {
+        return 0.0;
+    }
+    let stretch = (point - origin).length() / (object_point - object_origin).length();
+    pdf * inverse.determinant().abs() * stretch.powi(3)
+}
+
+/// A medium seen through a transform. The rays are taken into the space of the medium, which
+/// keeps the distances along them, and the scattered rays are taken back out.
+struct WorldMedium<'a> {
+    medium: &'a dyn Medium,
+    transform: Matrix4,
+    inverse: Matrix4,
+}
+
+impl<'a> Medium for WorldMedium<'a> {
+    fn sample_distance(&self, ray: &Ray, t_max: f64) -> Option<f64> {
+        self.medium
+            .sample_distance(&object_ray(&self.inverse, ray), t_max)
+    }
+
+    fn scatter_at(&self, ray: &Ray, point: Vec3) -> Option<(Color, Ray)> {
+        self.medium
+            .scatter_at(&object_ray(&self.inverse, ray), self.inverse * point)
+            .map(|(color, scattered)| {
+                let ray = Ray {
+                    origin: self.transform * scattered.origin,
+                    direction: self
+                        .transform
+                        .transform_vector(scattered.direction)
+                        .normalize(),
+                    ..scattered
+                };
+                (color, ray)
+            })
+    }
+
+    fn transmittance(&self, ray: &Ray, t_max: f64) -> f64 {
+        self.medium
+            .transmittance(&object_ray(&self.inverse, ray), t_max)
+    }
+
+    fn emission(&self, ray: &Ray, point: Vec3) -> Color {
+        self.medium
+            .emission(&object_ray(&self.inverse, ray), self.inverse * point)
+    }
+
+    fn boundary(&self) -> &dyn Intersectable {
+        self.medium.boundary()
+    }
+}
+
+impl<T: Intersectable> Medium for Transformed<T> {
+    fn sample_distance(&self, ray: &Ray, t_max: f64) -> Option<f64> {
+        self.world_medium().sample_distance(ray, t_max)
+    }
+
+    fn scatter_at(&self, ray: &Ray, point: Vec3) -> Option<(Color, Ray)> {
+        self.world_medium().scatter_at(ray, point)
+    }
+
+    fn transmittance(&self, ray: &Ray, t_max: f64) -> f64 {
+        self.world_medium().transmittance(ray, t_max)
+    }
+
+    fn emission(&self, ray: &Ray, point: Vec3) -> Color {
+        self.world_medium().emission(ray, point)
+    }
+
+    fn boundary(&self) -> &dyn Intersectable {
+        self.world_medium().medium.boundary()
+    }
+}
+
+impl<T: Intersectable + Clone + 'static> Intersectable for Transformed<T> {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.shape
+            .intersects(&self.object_ray(ray), t_min, t_max)
+            .map(|intersection| self.to_world(ray, intersection))
+    }
+
+    /// Moves the origin of the shape to `vec`, keeping the rest of the transform. Only the
+    /// translation of the inverse changes with it, so the inverse is kept as well.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let inverse_offset = self.inverse.transform_vector(vec).invert();
+        let (mut transform, mut inverse) = (self.transform, self.inverse);
+        for row in 0..3 {
+            transform.m[row as usize][3] = vec[row];
+            inverse.m[row as usize][3] = inverse_offset[row];
+        }
+        Box::new(Transformed {
+            shape: self.shape.clone(),
+            transform: transform,
+            inverse: inverse,
+            normal_matrix: self.normal_matrix,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.shape.bounding_box().map(|bounds| {
+            let corners: Vec<Vec3> = bounds
+                .corners()
+                .iter()
+                .map(|&corner| self.transform * corner)
+                .collect();
+            Aabb::from_points(&corners)
+        })
+    }
+
+    /// Scatters by the shape, since transformed boundaries of media end up in intersections.
+    fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Color, Ray)> {
+        self.shape.scatter(ray, intersection)
+    }
+
+    fn scatter_pdf(&self, ray: &Ray, intersection: &Intersection, direction: Vec3) -> f64 {
+        self.shape.scatter_pdf(ray, intersection, direction)
+    }
+
+    fn scatter_eval(&self, ray: &Ray, intersection: &Intersection, direction: Vec3) -> Color {
+        self.shape.scatter_eval(ray, intersection, direction)
+    }
+
+    fn medium(&self) -> Option<&dyn Medium> {
+        self.shape.medium().map(|_| self as &dyn Medium)
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.shape.is_emitter()
+    }
+
+    fn sample_towards(&self, origin: Vec3, time: f64) -> Option<Vec3> {
+        self.shape
+            .sample_towards(self.inverse * origin, time)
+            .map(|point| self.transform * point)
+    }
+
+    fn pdf_towards(&self, origin: Vec3, point: Vec3, time: f64) -> f64 {
+        pdf_towards(&self.shape, &self.inverse, origin, point, time)
+    }
+
+    fn intersections(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<Intersection> {
+        self.shape
+            .intersections(&self.object_ray(ray), t_min, t_max)
+            .into_iter()
+            .map(|intersection| self.to_world(ray, intersection))
+            .collect()
+    }
+}
+
+/// A shape that moves while the shutter of the camera is open, given by its transform at a
+/// few points in time. Rays see the shape with the transform blended between the two closest
+/// of them at the time of the ray, and before the first or after the last one it stands still.
+/// The matrices are blended element by element, so rotations should be split into small steps.
+/// Keys at a NaN time are left out.
+#[derive(Clone)]
+pub struct Moving<T: Intersectable> {
+    shape: T,
+    keys: Vec<(f64, Matrix4)>,
+}
+
+impl<T: Intersectable> Moving<T> {
+    pub fn new(shape: T, mut keys: Vec<(f64, Matrix4)>) -> Moving<T> {
+        keys.retain(|key| !key.0.is_nan());
+        assert!(
+            !keys.is_empty(),
+            "a moving shape needs at least one transform"
+        );
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Moving {
+            shape: shape,
+            keys: keys,
+        }
+    }
+
+    /// Moves the shape in a straight line from `start` at time 0 to `end` at time 1.
+    pub fn between(shape: T, start: Vec3, end: Vec3) -> Moving<T> {
+        Moving::new(
+            shape,
+            vec![
+                (0.0, Matrix4::translation(start)),
+                (1.0, Matrix4::translation(end)),
+            ],
+        )
+    }
+
+    pub fn shape(&self) -> &T {
+        &self.shape
+    }
+
+    pub fn transform_at(&self, time: f64) -> Matrix4 {
+        let next = self.keys.iter().position(|&(key_time, _)| key_time > time);
+        match next {
+            Some(0) => self.keys[0].1,
+            Some(next) => {
+                let (start_time, start) = self.keys[next - 1];
+                let (end_time, end) = self.keys[next];
+                start.interpolate(&end, (time - start_time) / (end_time - start_time))
+            }
+            None => self.keys[self.keys.len() - 1].1,
+        }
+    }
+
+    /// The transform at `time` and its inverse, or `None` if it can't be inverted then.
+    fn transforms_at(&self, time: f64) -> Option<(Matrix4, Matrix4)> {
+        let transform = self.transform_at(time);
+        transform.inverse().map(|inverse| (transform, inverse))
+    }
+
+    fn world_medium(&self, time: f64) -> Option<WorldMedium<'_>> {
+        let (transform, inverse) = self.transforms_at(time)?;
+        Some(WorldMedium {
+            medium: self
+                .shape
+                .medium()
+                .expect("only shapes with a medium are used as one"),
+            transform: transform,
+            inverse: inverse,
+        })
+    }
+}
+
+/// The medium is seen where it is at the time of the ray.
+impl<T: Intersectable> Medium for Moving<T> {
+    fn sample_distance(&self, ray: &Ray, t_max: f64) -> Option<f64> {
+        self.world_medium(ray.time)?.sample_distance(ray, t_max)
+    }
+
+    fn scatter_at(&self, ray: &Ray, point: Vec3) -> Option<(Color, Ray)> {
+        self.world_medium(ray.time)?.scatter_at(ray, point)
+    }
+
+    fn transmittance(&self, ray: &Ray, t_max: f64) -> f64 {
+        self.world_medium(ray.time)
+            .map_or(1.0, |medium| medium.transmittance(ray, t_max))
+    }
+
+    fn emission(&self, ray: &Ray, point: Vec3) -> Color {
+        self.world_medium(ray.time)
+            .map_or(Color::black(), |medium| medium.emission(ray, point))
+    }
+
+    fn boundary(&self) -> &dyn Intersectable {
+        self.shape
+            .medium()
+            .expect("only shapes with a medium are used as one")
+            .boundary()
+    }
+}
+
+impl<T: Intersectable + Clone + 'static> Intersectable for Moving<T> {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let (transform, inverse) = self.transforms_at(ray.time)?;
+        let normal_matrix = inverse.transpose();
+        self.shape
+            .intersects(&object_ray(&inverse, ray), t_min, t_max)
+            .map(|intersection| to_world(&transform, &inverse, &normal_matrix, ray, intersection))
+    }
+
+    fn intersections(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<Intersection> {
+        let (transform, inverse) = match self.transforms_at(ray.time) {
+            Some(transforms) => transforms,
+            None => return Vec::new(),
+        };
+        let normal_matrix = inverse.transpose();
+        self.shape
+            .intersections(&object_ray(&inverse, ray), t_min, t_max)
+            .into_iter()
+            .map(|intersection| to_world(&transform, &inverse, &normal_matrix, ray, intersection))
+            .collect()
+    }
+
+    fn medium(&self) -> Option<&dyn Medium> {
+        self.shape.medium().map(|_| self as &dyn Medium)
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.shape.is_emitter()
+    }
+
+    fn sample_towards(&self, origin: Vec3, time: f64) -> Option<Vec3> {
+        let (transform, inverse) = self.transforms_at(time)?;
+        self.shape
+            .sample_towards(inverse * origin, time)
+            .map(|point| transform * point)
+    }
+
+    fn pdf_towards(&self, origin: Vec3, point: Vec3, time: f64) -> f64 {
+        self.transforms_at(time).map_or(0.0, |(_, inverse)| {
+            pdf_towards(&self.shape, &inverse, origin, point, time)
+        })
+    }
+
+    /// Moves the whole path, so that the shape starts out with its origin at `vec`.
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let start = self.keys[0].1;
+        let offset = vec - Vec3::new(start.m[0][3], start.m[1][3], start.m[2][3]);
+        let keys = self
+            .keys
+            .iter()
+            .map(|&(time, transform)| (time, Matrix4::translation(offset) * transform))
+            .collect();
+        Box::new(Moving::new(self.shape.clone(), keys))
+    }
+
+    /// Every point of the shape moves in straight lines between where the transforms put it,
+    /// so the boxes around it at every transform hold it the whole time.
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.shape.bounding_box().map(|bounds| {
+            let corners: Vec<Vec3> = self
+                .keys
+                .iter()
+                .flat_map(|&(_, transform)| {
+                    bounds
+                        .corners()
+                        .iter()
+                        .map(|&corner| transform * corner)
+                        .collect::<Vec<Vec3>>()
+                })
+                .collect();
+            Aabb::from_points(&corners)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+    use std::f64::consts::PI;
+    use tests::assert_close;
+
+    fn ellipsoid() -> Transformed<Sphere> {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Color::white());
+        Transformed::new(sphere, Matrix4::scaling(Vec3::new(2.0, 1.0, 1.0))).unwrap()
+    }
+
+    #[test]
+    fn scaled_sphere_should_be_intersected_as_an_ellipsoid() {
+        let ray = Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let i = ellipsoid().intersects(&ray, 0.0, 100.0).unwrap();
+
+        assert_that!(i.distance, is(equal_to(3.0)));
+        assert_close(i.intersection_point, Vec3::new(2.0, 0.0, 0.0));
+        assert_close(i.normal, Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn normals_of_ellipsoid_should_be_perpendicular_to_its_surface() {
+        let direction = Vec3::new(-1.0, -1.0, 0.0).normalize();
+        let ray = Ray::new(Vec3::new(3.0, 3.0, 0.0), direction);
+
+        let i = ellipsoid().intersects(&ray, 0.0, 100.0).unwrap();
+
+        // The gradient of (x / 2)^2 + y^2 + z^2 at the intersection point.
+        let p = i.intersection_point;
+        let expected = Vec3::new(p.x / 2.0, 2.0 * p.y, 2.0 * p.z).normalize();
+        assert_close(i.normal, expected);
+    }
+
+    #[test]
+    fn tangents_of_ellipsoid_should_lie_in_its_surface() {
+        let direction = Vec3::new(-1.0, -1.0, -1.0).normalize();
+        let ray = Ray::new(Vec3::new(3.0, 3.0, 3.0), direction);
+
+        let i = ellipsoid().intersects(&ray, 0.0, 100.0).unwrap();
+
+        let tangent = i.tangent.unwrap();
+        assert_that!(tangent.length() > 0.0, is(true));
+        assert_that!(tangent.dot(i.normal).abs() < 1e-9, is(true));
+    }
+
+    #[test]
+    fn singular_transform_should_be_rejected() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Color::white());
+
+        let flat = Transformed::new(sphere, Matrix4::scaling(Vec3::new(1.0, 0.0, 1.0)));
+
+        assert_that!(flat.is_none(), is(true));
+    }
+
+    #[test]
+    fn bounding_box_should_be_transformed() {
+        let bounds = ellipsoid().bounding_box().unwrap();
+
+        assert_that!(bounds.min, is(equal_to(Vec3::new(-2.0, -1.0, -1.0))));
+        assert_that!(bounds.max, is(equal_to(Vec3::new(2.0, 1.0, 1.0))));
+    }
+
+    #[test]
+    fn transformed_shape_should_be_moved_by_its_origin() {
+        let moved = ellipsoid().move_to(Vec3::new(0.0, 0.0, -10.0));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_that!(
+            moved.intersects(&ray, 0.0, 100.0).unwrap().distance,
+            is(equal_to(9.0))
+        );
+    }
+
+    #[test]
+    fn moving_shape_should_be_hit_where_it_is_at_the_time_of_the_ray() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Color::white());
+        let moving = Moving::between(sphere, Vec3::new(0.0, 0.0, 0.0), Vec3::new(4.0, 0.0, 0.0));
+        let ray = Ray::new(Vec3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let halfway = moving.intersects(&ray.with_time(0.5), 0.0, 100.0).unwrap();
+
+        assert_that!(halfway.distance, is(equal_to(4.0)));
+        assert_close(halfway.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert_that!(moving.intersects(&ray, 0.0, 100.0).is_none(), is(true));
+        assert_that!(
+            moving.intersects(&ray.with_time(2.0), 0.0, 100.0).is_none(),
+            is(true)
+        );
+    }
+
+    #[test]
+    fn bounding_box_of_moving_shape_should_cover_the_whole_path() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Color::white());
+        let moving = Moving::new(
+            sphere,
+            vec![
+                (1.0, Matrix4::translation(Vec3::new(0.0, 3.0, 0.0))),
+                (0.0, Matrix4::create_identity()),
+                (2.0, Matrix4::translation(Vec3::new(4.0, 3.0, 0.0))),
+            ],
+        );
+
+        let bounds = moving.bounding_box().unwrap();
+
+        assert_close(
+            moving.transform_at(1.5) * Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 3.0, 0.0),
+        );
+        assert_that!(bounds.min, is(equal_to(Vec3::new(-1.0, -1.0, -1.0))));
+        assert_that!(bounds.max, is(equal_to(Vec3::new(5.0, 4.0, 1.0))));
+    }
+
+    #[test]
+    fn stretched_light_should_be_sampled_like_the_light_it_becomes() {
+        let light = Material::Emissive(Color::white());
+        let square = Rect::xz((0.0, 1.0), (0.0, 1.0), 0.0, light.clone());
+        let stretched =
+            Transformed::new(square, Matrix4::scaling(Vec3::new(2.0, 1.0, 1.0))).unwrap();
+        let rect = Rect::xz((0.0, 2.0), (0.0, 1.0), 0.0, light);
+        let origin = Vec3::new(1.0, 5.0, 0.5);
+
+        let point = stretched.sample_towards(origin, 0.0).unwrap();
+        let scene = Scene::new(vec![Box::new(stretched)]);
+
+        assert_that!(scene.has_emitters(), is(true));
+        assert_that!(point.y, is(equal_to(0.0)));
+        assert_that!(point.x >= 0.0 && point.x <= 2.0, is(true));
+        for &target in &[Vec3::new(1.0, 0.0, 0.5), Vec3::new(1.8, 0.0, 0.1)] {
+            let expected = rect.pdf_towards(origin, target, 0.0);
+            let pdf = scene.emitter_pdf(origin, target, 0.0);
+            assert_that!((pdf - expected).abs() < 1e-9, is(true));
+        }
+    }
+
+    #[test]
+    fn moving_light_should_be_sampled_where_it_is_at_the_time() {
+        let light = Sphere::with_material(
+            Vec3::new(0.0, 0.0, 0.0),
+            1.0,
+            Material::Emissive(Color::white()),
+        );
+        let end = Vec3::new(0.0, 0.0, -10.0);
+        let moving = Moving::between(light, Vec3::new(0.0, 0.0, -4.0), end);
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        // The sphere fills a cone with this cosine as seen from 10 units away.
+        let cos_max = (1.0 - 1.0 / 100.0_f64).sqrt();
+
+        let point = moving.sample_towards(origin, 1.0).unwrap();
+
+        assert_that!(moving.is_emitter(), is(true));
+        assert_that!(((point - end).length() - 1.0).abs() < 1e-9, is(true));
+        assert_that!(
+            (moving.pdf_towards(origin, point, 1.0) - 1.0 / (2.0 * PI * (1.0 - cos_max))).abs()
+                < 1e-6,
+            is(true)
+        );
+        assert_that!(moving.pdf_towards(origin, point, 0.0), is(equal_to(0.0)));
+    }
+
+    #[test]
+    fn transformed_fog_should_be_entered_through_the_transform() {
+        let fog = ConstantMedium::new(
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Color::white()),
+            100.0,
+            Color::black(),
+        );
+        let stretched = Transformed::new(fog, Matrix4::scaling(Vec3::new(1.0, 1.0, 2.0)));
+        let scene = Scene::new(vec![Box::new(stretched.unwrap())]).with_background(Color::white());
+        let inside = Ray::new(Vec3::new(0.0, 0.0, 1.5), Vec3::new(0.0, 0.0, 1.0));
+        let passing = Ray::new(Vec3::new(0.0, 1.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let entering = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let media = scene.media_at(&inside);
+        let hit = scene.intersects(&entering, 0.0, 100.0).unwrap();
+
+        assert_that!(media.len(), is(equal_to(1)));
+        // Half a unit to the edge of the stretched sphere is a quarter of a unit inside of it.
+        assert_that!(
+            (media[0].transmittance(&inside, 0.5) - (-25.0f64).exp()).abs() < 1e-12,
+            is(true)
+        );
+        assert_that!(hit.distance, is(equal_to(3.0)));
+        assert_that!(hit.shape.medium().is_some(), is(true));
+        assert_that!(
+            ::trace_ray_in_media(&passing, &scene, &[], 0, None),
+            is(equal_to(Color::white()))
+        );
+        assert_that!(
+            ::trace_ray_in_media(&entering, &scene, &[], 0, None),
+            is(equal_to(Color::black()))
+        );
+    }
+
+    #[test]
+    fn keys_at_nan_time_should_not_panic() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Color::white());
+        let moving = Moving::new(
+            sphere,
+            vec![
+                (f64::NAN, Matrix4::create_identity()),
+                (0.0, Matrix4::translation(Vec3::new(0.0, 3.0, 0.0))),
+            ],
+        );
+
+        assert_close(
+            moving.transform_at(0.0) * Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 3.0, 0.0),
+        );
+    }
+}
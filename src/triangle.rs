@@ -0,0 +1,286 @@
+use rand::{self, Rng};
+
+use light;
+use prelude::*;
+use scene::Intersection;
+
+const EPSILON: f64 = 1e-12;
+
+#[derive(Clone)]
+pub struct Triangle {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    colors: Option<[Color; 3]>,
+    pub material: Material,
+}
+
+impl Triangle {
+    pub fn new(a: Vec3, b: Vec3, c: Vec3, material: Material) -> Triangle {
+        Triangle {
+            a: a,
+            b: b,
+            c: c,
+            normals: None,
+            uvs: None,
+            colors: None,
+            material: material,
+        }
+    }
+
+    /// Adds per-vertex normals that are interpolated across the face to produce the shading
+    /// normal. The geometric normal is still given by the winding order of the vertices.
+    pub fn with_normals(self, na: Vec3, nb: Vec3, nc: Vec3) -> Triangle {
+        Triangle {
+            normals: Some([na.normalize(), nb.normalize(), nc.normalize()]),
+            ..self
+        }
+    }
+
+    /// Adds per-vertex texture coordinates, which replace the barycentric coordinates that
+    /// are otherwise reported as the `uv` of an intersection.
+    pub fn with_uvs(self, uva: (f64, f64), uvb: (f64, f64), uvc: (f64, f64)) -> Triangle {
+        Triangle {
+            uvs: Some([uva, uvb, uvc]),
+            ..self
+        }
+    }
+
+    /// Adds per-vertex colors, interpolated across the face to give the color of the surface.
+    pub fn with_colors(self, ca: Color, cb: Color, cc: Color) -> Triangle {
+        Triangle {
+            colors: Some([ca, cb, cc]),
+            ..self
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.a + self.b + self.c) / 3.0
+    }
+
+    pub fn area(&self) -> f64 {
+        (self.b - self.a).cross(self.c - self.a).length() / 2.0
+    }
+
+    /// A random point picked evenly over the area of the triangle.
+    pub fn sample_point(&self) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let s = rng.next_f64().sqrt();
+        let t = rng.next_f64();
+        self.a * (1.0 - s) + self.b * (s * (1.0 - t)) + self.c * (s * t)
+    }
+
+    pub fn geometric_normal(&self) -> Vec3 {
+        (self.b - self.a).cross(self.c - self.a).normalize()
+    }
+
+    fn shading_normal(&self, u: f64, v: f64, geometric_normal: Vec3) -> Vec3 {
+        match self.normals {
+            Some([na, nb, nc]) => {
+                let normal = ((1.0 - u - v) * na + u * nb + v * nc).normalize();
+                if normal.dot(geometric_normal) < 0.0 {
+                    normal.invert()
+                } else {
+                    normal
+                }
+            }
+            None => geometric_normal,
+        }
+    }
+
+    /// The material at the barycentric coordinates, in the color of the vertices if they have
+    /// one.
+    fn material_at(&self, u: f64, v: f64) -> Material {
+        match self.colors {
+            Some([ca, cb, cc]) => {
+                let color = (1.0 - u - v) * ca + u * cb + v * cc;
+                self.material.clone().with_color(color)
+            }
+            None => self.material.clone(),
+        }
+    }
+
+    /// The direction in which the texture coordinates' u increases, which is towards `b` when
+    /// the triangle has no texture coordinates.
+    fn tangent(&self) -> Vec3 {
+        let [uva, uvb, uvc] = self.uvs.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        let (edge1, edge2) = (self.b - self.a, self.c - self.a);
+        let (du1, dv1) = (uvb.0 - uva.0, uvb.1 - uva.1);
+        let (du2, dv2) = (uvc.0 - uva.0, uvc.1 - uva.1);
+        let determinant = du1 * dv2 - du2 * dv1;
+        if determinant == 0.0 {
+            return edge1;
+        }
+        (edge1 * dv2 - edge2 * dv1) / determinant
+    }
+
+    fn texture_coordinates(&self, u: f64, v: f64) -> (f64, f64) {
+        match self.uvs {
+            Some([uva, uvb, uvc]) => {
+                let w = 1.0 - u - v;
+                (
+                    w * uva.0 + u * uvb.0 + v * uvc.0,
+                    w * uva.1 + u * uvb.1 + v * uvc.1,
+                )
+            }
+            None => (u, v),
+        }
+    }
+}
+
+impl Intersectable for Triangle {
+    // Möller–Trumbore, with the barycentric coordinates of the hit kept around for the
+    // normal interpolation.
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let edge1 = self.b - self.a;
+        let edge2 = self.c - self.a;
+        let p = ray.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < EPSILON * edge1.length() * edge2.length() {
+            return None;
+        }
+
+        let inverse_determinant = 1.0 / determinant;
+        let s = ray.origin - self.a;
+        let u = s.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge1);
+        let v = ray.direction.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge2.dot(q) * inverse_determinant;
+        if distance < t_max && distance > t_min {
+            let geometric_normal = edge1.cross(edge2).normalize();
+            let normal = self.shading_normal(u, v, geometric_normal);
+            let (texture_u, texture_v) = self.texture_coordinates(u, v);
+            let intersection = Intersection::new(
+                distance,
+                ray.point_along_direction(distance),
+                normal,
+                Box::new(self.clone()),
+            );
+            Some(
+                intersection
+                    .with_geometric_normal(geometric_normal)
+                    .with_uv(texture_u, texture_v)
+                    .with_tangent(self.tangent())
+                    .with_material(self.material_at(u, v)),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let offset = vec - self.centroid();
+        Box::new(Triangle {
+            a: self.a + offset,
+            b: self.b + offset,
+            c: self.c + offset,
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(&[self.a, self.b, self.c]))
+    }
+
+    fn is_emitter(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn sample_towards(&self, _: Vec3, _: f64) -> Option<Vec3> {
+        Some(self.sample_point())
+    }
+
+    fn pdf_towards(&self, origin: Vec3, point: Vec3, _: f64) -> f64 {
+        light::hit_at(self, origin, point).map_or(0.0, |intersection| {
+            light::area_pdf(origin, &intersection, self.area())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use prelude::*;
+
+    fn triangle() -> Triangle {
+        Triangle::new(
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(-1.0, 1.0, 0.0),
+            Material::Lambertian(Color::white()),
+        )
+    }
+
+    #[test]
+    fn ray_should_intersect_with_triangle() {
+        let ray = Ray::new(Vec3::new(-0.5, -0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let i = triangle().intersects(&ray, 0.0, 1000.0).unwrap();
+
+        assert_that!(i.distance, is(equal_to(1.0)));
+        assert_that!(
+            i.intersection_point,
+            is(equal_to(Vec3::new(-0.5, -0.5, 0.0)))
+        );
+        assert_that!(i.normal, is(equal_to(Vec3::new(0.0, 0.0, 1.0))));
+        assert_that!(i.geometric_normal, is(equal_to(Vec3::new(0.0, 0.0, 1.0))));
+        assert_that!(i.uv, is(equal_to((0.25, 0.25))));
+    }
+
+    #[test]
+    fn ray_should_miss_outside_of_triangle_edges() {
+        let ray = Ray::new(Vec3::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_that!(triangle().intersects(&ray, 0.0, 1000.0).is_none(), is(true));
+    }
+
+    #[test]
+    fn ray_should_miss_parallel_triangle() {
+        let ray = Ray::new(Vec3::new(-0.5, -0.5, 1.0), Vec3::new(1.0, 0.0, 0.0));
+
+        assert_that!(triangle().intersects(&ray, 0.0, 1000.0).is_none(), is(true));
+    }
+
+    #[test]
+    fn ray_should_respect_distance_limits() {
+        let ray = Ray::new(Vec3::new(-0.5, -0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_that!(triangle().intersects(&ray, 0.0, 0.5).is_none(), is(true));
+        assert_that!(triangle().intersects(&ray, 1.5, 1000.0).is_none(), is(true));
+    }
+
+    #[test]
+    fn surface_color_should_be_interpolated_from_vertex_colors() {
+        let triangle = triangle().with_colors(Color::red(), Color::green(), Color::green());
+        let ray = Ray::new(Vec3::new(0.0, -1.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let i = triangle.intersects(&ray, 0.0, 1000.0).unwrap();
+        let (color, _) = i.shape.scatter(&ray, &i).unwrap();
+
+        assert_that!(color, is(equal_to(Color::new(0.5, 0.5, 0.0))));
+    }
+
+    #[test]
+    fn shading_normal_should_be_interpolated_from_vertex_normals() {
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let tilted = Vec3::new(1.0, 0.0, 1.0);
+        let triangle = triangle().with_normals(up, tilted, up);
+        let ray = Ray::new(Vec3::new(0.0, -1.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let i = triangle.intersects(&ray, 0.0, 1000.0).unwrap();
+
+        assert_that!(i.geometric_normal, is(equal_to(up)));
+        assert_that!(i.normal.x > 0.0, is(true));
+        assert_that!((i.normal.length() - 1.0).abs() < 1e-12, is(true));
+    }
+}
@@ -0,0 +1,426 @@
+use rand::{self, Rng};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use medium::{henyey_greenstein, Medium};
+use prelude::*;
+use scatter;
+use scene::Intersection;
+
+const HEADER_SIZE: usize = 12;
+
+/// Values on a regular 3D grid of voxels, stored with x changing fastest, then y, then z.
+#[derive(Clone, Debug)]
+pub struct VoxelGrid {
+    pub size: (usize, usize, usize),
+    values: Vec<f64>,
+}
+
+impl VoxelGrid {
+    pub fn new(size: (usize, usize, usize), values: Vec<f64>) -> VoxelGrid {
+        assert!(
+            size.0 > 0 && size.1 > 0 && size.2 > 0,
+            "a voxel grid needs at least one voxel along every axis"
+        );
+        assert_eq!(
+            values.len(),
+            size.0 * size.1 * size.2,
+            "a voxel grid needs one value per voxel"
+        );
+        VoxelGrid {
+            size: size,
+            values: values,
+        }
+    }
+
+    /// Reads a grid in the binary format of `parse`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<VoxelGrid> {
+        VoxelGrid::parse(&fs::read(path)?)
+    }
+
+    /// Parses a grid stored as the number of voxels along x, y and z as little-endian 32-bit
+    /// integers, followed by the value of every voxel as a little-endian 32-bit float.
+    pub fn parse(bytes: &[u8]) -> io::Result<VoxelGrid> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(invalid_data("voxel grid is missing its size".to_string()));
+        }
+        let read_u32 = |at: usize| {
+            u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        let size = (
+            read_u32(0) as usize,
+            read_u32(4) as usize,
+            read_u32(8) as usize,
+        );
+        let length = voxel_count(size)
+            .and_then(|count| count.checked_mul(4))
+            .and_then(|length| length.checked_add(HEADER_SIZE));
+        if length != Some(bytes.len()) {
+            return Err(invalid_data(format!(
+                "voxel grid of {}x{}x{} does not match its {} bytes",
+                size.0,
+                size.1,
+                size.2,
+                bytes.len()
+            )));
+        }
+        let values = bytes[HEADER_SIZE..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        Ok(VoxelGrid::new(size, values))
+    }
+
+    /// Reads a headerless grid of the given size with one byte per voxel, scaled to lie
+    /// between 0 and 1, which is how many scanned and simulated volumes are stored.
+    pub fn load_raw<P: AsRef<Path>>(path: P, size: (usize, usize, usize)) -> io::Result<VoxelGrid> {
+        let bytes = fs::read(path)?;
+        if voxel_count(size) != Some(bytes.len()) {
+            return Err(invalid_data(format!(
+                "raw voxel grid of {}x{}x{} does not match its {} bytes",
+                size.0,
+                size.1,
+                size.2,
+                bytes.len()
+            )));
+        }
+        let values = bytes.iter().map(|&b| b as f64 / 255.0).collect();
+        Ok(VoxelGrid::new(size, values))
+    }
+
+    pub fn value(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[x + self.size.0 * (y + self.size.1 * z)]
+    }
+
+    pub fn max(&self) -> f64 {
+        self.values.iter().cloned().fold(0.0, f64::max)
+    }
+
+    /// The trilinearly interpolated value at `point`, where the grid fills the unit cube with
+    /// the first voxel at the origin and the last one at (1, 1, 1). Zero outside of the cube.
+    pub fn sample(&self, point: Vec3) -> f64 {
+        let outside = |v: f64| !(0.0..=1.0).contains(&v);
+        if outside(point.x) || outside(point.y) || outside(point.z) {
+            return 0.0;
+        }
+        // The two neighbouring voxels along an axis, and how far between them the point is.
+        let axis = |v: f64, size: usize| {
+            let position = v * (size - 1) as f64;
+            let lower = (position.floor() as usize).min(size - 1);
+            ((lower, (lower + 1).min(size - 1)), position - lower as f64)
+        };
+        let ((x0, x1), fx) = axis(point.x, self.size.0);
+        let ((y0, y1), fy) = axis(point.y, self.size.1);
+        let ((z0, z1), fz) = axis(point.z, self.size.2);
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let plane = |z: usize| {
+            lerp(
+                lerp(self.value(x0, y0, z), self.value(x1, y0, z), fx),
+                lerp(self.value(x0, y1, z), self.value(x1, y1, z), fx),
+                fy,
+            )
+        };
+        lerp(plane(z0), plane(z1), fz)
+    }
+}
+
+/// The number of voxels in a grid of the given size, or `None` if there are none or too many
+/// to count.
+fn voxel_count(size: (usize, usize, usize)) -> Option<usize> {
+    size.0
+        .checked_mul(size.1)
+        .and_then(|count| count.checked_mul(size.2))
+        .filter(|&count| count > 0)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Smoke or fire with a density that varies through a box, given by a voxel grid stretched
+/// over it. Rays are traced through it with delta tracking, which samples interactions
+/// against the highest density and rejects the ones where the actual density is lower.
+#[derive(Clone)]
+pub struct GridMedium {
+    pub density_scale: f64,
+    pub albedo: Color,
+    pub anisotropy: f64,
+    density: Rc<VoxelGrid>,
+    emission: Option<(Rc<VoxelGrid>, Color)>,
+    max_density: f64,
+    boundary: Rc<Cuboid>,
+}
+
+impl GridMedium {
+    pub fn new(
+        density: VoxelGrid,
+        min: Vec3,
+        max: Vec3,
+        density_scale: f64,
+        albedo: Color,
+    ) -> GridMedium {
+        GridMedium {
+            density_scale: density_scale,
+            albedo: albedo,
+            anisotropy: 0.0,
+            max_density: density.max() * density_scale,
+            density: Rc::new(density),
+            emission: None,
+            boundary: Rc::new(Cuboid::new(min, max, Material::Lambertian(Color::white()))),
+        }
+    }
+
+    /// Sets how the medium scatters light, see `henyey_greenstein`.
+    pub fn with_anisotropy(self, anisotropy: f64) -> GridMedium {
+        GridMedium {
+            anisotropy: anisotropy,
+            ..self
+        }
+    }
+
+    /// Makes the volume glow with `color`, scaled by the values of the grid, which is
+    /// stretched over the same box as the density.
+    pub fn with_emission(self, emission: VoxelGrid, color: Color) -> GridMedium {
+        GridMedium {
+            emission: Some((Rc::new(emission), color)),
+            ..self
+        }
+    }
+
+    fn local(&self, point: Vec3) -> Vec3 {
+        (point - self.boundary.min) / (self.boundary.max - self.boundary.min)
+    }
+
+    pub fn density(&self, point: Vec3) -> f64 {
+        self.density.sample(self.local(point)) * self.density_scale
+    }
+
+    /// The part of the ray before `t_max` that is inside of the box, if there is any density
+    /// to interact with at all.
+    fn extent(&self, ray: &Ray, t_max: f64) -> Option<(f64, f64)> {
+        if self.max_density <= 0.0 {
+            return None;
+        }
+        Aabb::new(self.boundary.min, self.boundary.max).clip(ray, 0.0, t_max)
+    }
+
+    /// The distance to the next tentative interaction, sampled against the highest density.
+    fn step(&self, ray: &Ray) -> f64 {
+        let mut rng = rand::thread_rng();
+        -(1.0 - rng.next_f64()).ln() / (self.max_density * ray.direction.length())
+    }
+}
+
+impl Medium for GridMedium {
+    fn sample_distance(&self, ray: &Ray, t_max: f64) -> Option<f64> {
+        let (mut t, t_max) = self.extent(ray, t_max)?;
+        let mut rng = rand::thread_rng();
+        loop {
+            t += self.step(ray);
+            if t >= t_max {
+                return None;
+            }
+            let density = self.density(ray.point_along_direction(t));
+            if rng.next_f64() * self.max_density < density {
+                return Some(t);
+            }
+        }
+    }
+
+    fn scatter_at(&self, ray: &Ray, point: Vec3) -> Option<(Color, Ray)> {
+        let direction = henyey_greenstein(ray.direction.normalize(), self.anisotropy);
+        Some((self.albedo, Ray::new(point, direction)))
+    }
+
+    /// Estimated with ratio tracking, which weighs every tentative interaction by the chance of
+    /// passing through it.
+    fn transmittance(&self, ray: &Ray, t_max: f64) -> f64 {
+        let (mut t, t_max) = match self.extent(ray, t_max) {
+            Some(extent) => extent,
+            None => return 1.0,
+        };
+        let mut transmittance = 1.0;
+        loop {
+            t += self.step(ray);
+            if t >= t_max {
+                return transmittance;
+            }
+            let density = self.density(ray.point_along_direction(t));
+            transmittance *= 1.0 - density / self.max_density;
+        }
+    }
+
+    fn emission(&self, _: &Ray, point: Vec3) -> Color {
+        match self.emission {
+            Some((ref grid, color)) => color * grid.sample(self.local(point)),
+            None => Color::black(),
+        }
+    }
+
+    fn boundary(&self) -> &dyn Intersectable {
+        &*self.boundary
+    }
+}
+
+impl Intersectable for GridMedium {
+    fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.boundary
+            .intersects(ray, t_min, t_max)
+            .map(|intersection| Intersection {
+                material: None,
+                shape: Box::new(self.clone()),
+                ..intersection
+            })
+    }
+
+    fn scatter(&self, ray: &Ray, intersection: &Intersection) -> Option<(Color, Ray)> {
+        scatter::transmission(ray, intersection)
+    }
+
+    fn move_to(&self, vec: Vec3) -> Box<dyn Intersectable> {
+        let offset = vec - (self.boundary.min + self.boundary.max) / 2.0;
+        let boundary = Cuboid::new(
+            self.boundary.min + offset,
+            self.boundary.max + offset,
+            Material::Lambertian(Color::white()),
+        );
+        Box::new(GridMedium {
+            boundary: Rc::new(boundary),
+            ..self.clone()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+
+    fn medium(&self) -> Option<&dyn Medium> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use light;
+    use medium::Medium;
+    use prelude::*;
+    use std::f64::consts::PI;
+    use std::io;
+
+    fn grid_bytes(size: (u32, u32, u32), values: &[f32]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &n in [size.0, size.1, size.2].iter() {
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn uniform(density: f64) -> GridMedium {
+        let grid = VoxelGrid::new((2, 2, 2), vec![1.0; 8]);
+        GridMedium::new(
+            grid,
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            density,
+            Color::white(),
+        )
+    }
+
+    #[test]
+    fn grid_should_be_parsed_from_its_binary_format() {
+        let values = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+
+        let grid = VoxelGrid::parse(&grid_bytes((3, 2, 1), &values)).unwrap();
+        let truncated = grid_bytes((3, 2, 2), &values);
+
+        assert_that!(grid.size, is(equal_to((3, 2, 1))));
+        assert_that!(grid.value(1, 1, 0), is(equal_to(4.0)));
+        let huge = grid_bytes((u32::MAX, u32::MAX, u32::MAX), &values);
+        assert_that!(VoxelGrid::parse(&truncated).is_err(), is(true));
+        assert_that!(
+            VoxelGrid::parse(&huge).unwrap_err().kind(),
+            is(equal_to(io::ErrorKind::InvalidData))
+        );
+        assert_that!(VoxelGrid::parse(&[1, 2]).is_err(), is(true));
+    }
+
+    #[test]
+    fn grid_should_be_interpolated_between_voxels() {
+        let values = (0..8).map(|i| i as f64).collect();
+        let grid = VoxelGrid::new((2, 2, 2), values);
+
+        assert_that!(grid.sample(Vec3::new(0.0, 0.0, 0.0)), is(equal_to(0.0)));
+        assert_that!(grid.sample(Vec3::new(1.0, 1.0, 1.0)), is(equal_to(7.0)));
+        assert_that!(grid.sample(Vec3::new(0.5, 0.5, 0.5)), is(equal_to(3.5)));
+        assert_that!(grid.sample(Vec3::new(0.25, 1.0, 0.0)), is(equal_to(2.25)));
+        assert_that!(grid.sample(Vec3::new(1.5, 0.5, 0.5)), is(equal_to(0.0)));
+    }
+
+    #[test]
+    fn tracking_should_match_a_constant_medium_for_a_uniform_grid() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let samples = 10000;
+        let medium = uniform(0.5);
+
+        let interacted = (0..samples)
+            .filter(|_| medium.sample_distance(&ray, 2.0).is_some())
+            .count();
+        let transmittance = (0..samples)
+            .map(|_| medium.transmittance(&ray, 2.0))
+            .sum::<f64>()
+            / samples as f64;
+
+        let expected = (-1.0f64).exp();
+        let fraction = interacted as f64 / samples as f64;
+        assert_that!((fraction - (1.0 - expected)).abs() < 0.03, is(true));
+        assert_that!((transmittance - expected).abs() < 0.03, is(true));
+    }
+
+    #[test]
+    fn shadow_rays_should_be_dimmed_by_the_density_along_them() {
+        // A white floor under a light that would reflect white without the smoke in between.
+        let floor = Rect::xz(
+            (-5.0, 5.0),
+            (-5.0, 5.0),
+            -2.0,
+            Material::Lambertian(Color::white()),
+        );
+        let intensity = Color::new(16.0 * PI, 16.0 * PI, 16.0 * PI);
+        let lamp = Light::point(Vec3::new(0.0, 2.0, 0.0), intensity);
+        let scene =
+            Scene::new(vec![Box::new(floor), Box::new(uniform(0.5))]).with_lights(vec![lamp]);
+        let ray = Ray::new(Vec3::new(0.0, -1.5, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let i = scene.intersects(&ray, 0.0, 100.0).unwrap();
+        let samples = 10000;
+
+        let lit = (0..samples)
+            .map(|_| light::sample_lights(&scene, &[], &ray, &i).r)
+            .sum::<f64>()
+            / samples as f64;
+
+        // The light goes through two units of smoke.
+        assert_that!((lit - (-1.0f64).exp()).abs() < 0.03, is(true));
+    }
+
+    #[test]
+    fn emission_should_follow_its_own_grid() {
+        let fire = VoxelGrid::new((2, 1, 1), vec![0.0, 1.0]);
+        let medium = uniform(1.0).with_emission(fire, Color::new(2.0, 1.0, 0.0));
+        let ray = Ray::new(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        assert_that!(
+            medium.emission(&ray, Vec3::new(0.0, 0.0, 0.0)),
+            is(equal_to(Color::new(1.0, 0.5, 0.0)))
+        );
+        assert_that!(
+            uniform(1.0).emission(&ray, Vec3::new(0.0, 0.0, 0.0)),
+            is(equal_to(Color::black()))
+        );
+    }
+}